@@ -13,24 +13,29 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::net::SocketAddr;
 use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 use std::collections::HashMap;
 
+use common::logging;
 use common::prelude::*;
 use common::state::State;
-use common::config::{Config, HttpConfig};
+use common::config::{Config, HttpConfig, JobsConfig};
 
-use scripts::{Blueprint, Repository, JobContext};
+use cron::CronTimer;
+use scripts::{Blueprint, Repository, JobContext, RunAs};
 use processor::{Processor, ProcessorApi};
+use utils::{ListenAddr, TimeString};
 use web::WebApp;
 
 
 struct InnerApp {
     locked: bool,
+    state: Arc<State>,
     scripts_blueprint: Blueprint,
     processor: Processor<Repository>,
+    cron: CronTimer,
     http: Option<WebApp<ProcessorApi<Repository>>>,
 }
 
@@ -46,33 +51,38 @@ impl InnerApp {
             state.clone(),
         )?;
 
+        let cron =
+            CronTimer::new(Arc::new(blueprint.repository()), processor.api());
+
         Ok(InnerApp {
             locked: false,
+            state,
             scripts_blueprint: blueprint,
             http: None,
             processor,
+            cron,
         })
     }
 
     fn restart_http_server(&mut self, config: &HttpConfig) -> Result<()> {
-        // Stop the server if it's already running
-        if let Some(http) = self.http.take() {
-            http.stop();
-        }
+        let hooks = Arc::new(self.scripts_blueprint.repository());
+        let processor = self.processor.api();
+        let state = self.state.clone();
 
-        let http = WebApp::new(
-            Arc::new(self.scripts_blueprint.repository()),
-            config,
-            self.processor.api(),
-        )?;
+        // Hand the sockets over if the server is already running
+        if let Some(ref mut http) = self.http {
+            http.reload(hooks, config, processor, state)?;
+        } else {
+            self.http = Some(WebApp::new(hooks, config, processor, state)?);
+        }
 
         // Lock the server if it was locked before
         if self.locked {
-            http.lock();
+            if let Some(ref http) = self.http {
+                http.lock();
+            }
         }
 
-        self.http = Some(http);
-
         Ok(())
     }
 
@@ -86,9 +96,24 @@ impl InnerApp {
         Ok(())
     }
 
-    fn set_job_environment(&self, env: HashMap<String, String>) -> Result<()> {
+    fn set_job_context(
+        &mut self, env: HashMap<String, String>, jobs: &JobsConfig,
+    ) -> Result<()> {
+        let run_as = RunAs::new(
+            jobs.user.as_deref(), jobs.user_group.as_deref(),
+        )?;
+        if let Some(ref run_as) = run_as {
+            run_as.check_switch()?;
+        }
+        let run_as = run_as.map(Arc::new);
+
+        // The scripts are checked against the new user when reloaded
+        self.scripts_blueprint.set_run_as(run_as.clone());
+
         self.processor.api().update_context(JobContext {
             environment: env,
+            run_as,
+            limits: jobs.limits.clone(),
             .. JobContext::default()
         })?;
         Ok(())
@@ -99,14 +124,49 @@ impl InnerApp {
         Ok(())
     }
 
-    fn http_addr(&self) -> Option<&SocketAddr> {
+    fn set_group_limits(&self, limits: HashMap<String, u16>) -> Result<()> {
+        self.processor.api().set_group_limits(limits)?;
+        Ok(())
+    }
+
+    fn set_priority_aging(&self, aging: Option<&TimeString>) -> Result<()> {
+        self.processor.api().set_aging(
+            aging.map(|aging| Duration::from_secs(aging.as_u64())),
+        )?;
+        Ok(())
+    }
+
+    fn set_queue_limit(&self, jobs: &JobsConfig) -> Result<()> {
+        self.processor
+            .api()
+            .set_queue_limit(jobs.max_queued, jobs.overflow)?;
+        Ok(())
+    }
+
+    fn http_addrs(&self) -> &[ListenAddr] {
         if let Some(ref http) = self.http {
-            Some(http.addr())
+            http.addrs()
         } else {
-            None
+            &[]
         }
     }
 
+    fn http_tls_changed(&self, config: &HttpConfig) -> Result<bool> {
+        if let Some(ref http) = self.http {
+            http.tls_changed(config)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn reopen_logs(&self) -> Result<()> {
+        logging::reopen()?;
+        if let Some(ref http) = self.http {
+            http.reopen_access_log()?;
+        }
+        Ok(())
+    }
+
     fn lock(&mut self) -> Result<()> {
         if let Some(ref http) = self.http {
             http.lock();
@@ -134,6 +194,7 @@ impl InnerApp {
             http.lock();
         }
 
+        self.cron.stop();
         self.processor.stop()?;
 
         if let Some(http) = self.http.take() {
@@ -152,12 +213,18 @@ pub struct Fisher {
 
 impl Fisher {
     pub fn new(config: Config) -> Result<Self> {
+        config.validate()?;
+        logging::configure(&config.log)?;
+
         let mut inner = InnerApp::new()?;
+        inner.set_job_context(config.env.clone(), &config.jobs)?;
         inner.set_scripts_path(
             &config.scripts.path, config.scripts.recursive,
         )?;
-        inner.set_job_environment(config.env.clone())?;
         inner.set_threads_count(config.jobs.threads)?;
+        inner.set_group_limits(config.jobs.groups.clone())?;
+        inner.set_priority_aging(config.jobs.priority_aging.as_ref())?;
+        inner.set_queue_limit(&config.jobs)?;
         inner.restart_http_server(&config.http)?;
 
         Ok(Fisher {
@@ -166,8 +233,8 @@ impl Fisher {
         })
     }
 
-    pub fn web_address(&self) -> Option<&SocketAddr> {
-        self.inner.http_addr()
+    pub fn web_addresses(&self) -> &[ListenAddr] {
+        self.inner.http_addrs()
     }
 
     pub fn reload(&mut self, new_config: Config) -> Result<()> {
@@ -176,18 +243,50 @@ impl Fisher {
         let result = self.reload_inner(new_config);
         self.inner.unlock()?;
 
+        self.inner.state.record_reload(
+            result.as_ref().err().map(|error| error.to_string()),
+        );
+
         result
     }
 
+    /// Reopen the log files, for example after they were rotated.
+    pub fn reopen_logs(&self) -> Result<()> {
+        self.inner.reopen_logs()
+    }
+
+    /// Record a reload failed before the new configuration could be
+    /// applied, for example because it wasn't valid.
+    pub fn reload_failed(&self, error: &Error) {
+        self.inner.state.record_reload(Some(error.to_string()));
+    }
+
     fn reload_inner(&mut self, new_config: Config) -> Result<()> {
-        // Restart the HTTP server if its configuration changed
-        if self.config.http != new_config.http {
+        // Nothing is changed if the new configuration isn't valid
+        new_config.validate()?;
+
+        // Reconfigure logging if its configuration changed
+        if self.config.log != new_config.log {
+            logging::configure(&new_config.log)?;
+        }
+
+        // Restart the HTTP server if its configuration or its TLS
+        // certificate changed
+        if self.config.http != new_config.http
+            || self.inner.http_tls_changed(&new_config.http)?
+        {
             self.inner.restart_http_server(&new_config.http)?;
         }
 
-        // Update the job context if the environment is different
-        if self.config.env != new_config.env {
-            self.inner.set_job_environment(new_config.env.clone())?;
+        // Update the job context if the environment, the user or the limits
+        // are different
+        if self.config.env != new_config.env
+            || self.config.jobs.user != new_config.jobs.user
+            || self.config.jobs.user_group != new_config.jobs.user_group
+            || self.config.jobs.limits != new_config.jobs.limits
+        {
+            self.inner
+                .set_job_context(new_config.env.clone(), &new_config.jobs)?;
         }
 
         // Update the threads count if it's different
@@ -195,6 +294,24 @@ impl Fisher {
             self.inner.set_threads_count(new_config.jobs.threads)?;
         }
 
+        // Update the concurrency groups limits if they're different
+        if self.config.jobs.groups != new_config.jobs.groups {
+            self.inner.set_group_limits(new_config.jobs.groups.clone())?;
+        }
+
+        // Update the priority aging if it's different
+        if self.config.jobs.priority_aging != new_config.jobs.priority_aging {
+            self.inner
+                .set_priority_aging(new_config.jobs.priority_aging.as_ref())?;
+        }
+
+        // Update the queue limit if it's different
+        if self.config.jobs.max_queued != new_config.jobs.max_queued
+            || self.config.jobs.overflow != new_config.jobs.overflow
+        {
+            self.inner.set_queue_limit(&new_config.jobs)?;
+        }
+
         // Reload hooks, changing the script path
         self.inner.set_scripts_path(
             &new_config.scripts.path,
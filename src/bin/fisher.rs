@@ -22,6 +22,7 @@ use std::io::Read;
 use std::path::Path;
 
 use fisher::*;
+use fisher::common::logging;
 use nix::sys::signal::{Signal, SigSet};
 
 
@@ -111,29 +112,42 @@ fn app() -> Result<()> {
     signals.add(Signal::SIGINT);
     signals.add(Signal::SIGTERM);
     signals.add(Signal::SIGUSR1);
+    signals.add(Signal::SIGHUP);
     signals.thread_block()?;
 
     let config_path = parse_cli();
 
     let mut app = Fisher::new(read_config(&config_path)?)?;
-    println!("HTTP server listening on {}", app.web_address().unwrap());
+    for addr in app.web_addresses() {
+        logging::info("HTTP server listening")
+            .field("address", addr)
+            .log();
+    }
 
     // Wait for signals while the other threads execute the application
     loop {
         match signals.wait()? {
             Signal::SIGINT | Signal::SIGTERM => break,
             Signal::SIGUSR1 => {
-                println!("Reloading configuration and scripts...");
+                logging::info("reloading configuration and scripts").log();
 
                 // Don't crash if the reload fails, just show errors
                 // No changes are applied if the reload fails
                 match read_config(&config_path) {
                     Ok(new_config) => {
                         if let Err(err) = app.reload(new_config) {
-                            err.pretty_print()
+                            err.log()
                         }
                     }
-                    Err(err) => err.pretty_print(),
+                    Err(err) => {
+                        app.reload_failed(&err);
+                        err.log();
+                    }
+                }
+            }
+            Signal::SIGHUP => {
+                if let Err(err) = app.reopen_logs() {
+                    err.log();
                 }
             }
             _ => {}
@@ -149,7 +163,7 @@ fn app() -> Result<()> {
 
 fn main() {
     if let Err(err) = app() {
-        err.pretty_print();
+        err.log();
         std::process::exit(1);
     }
 }
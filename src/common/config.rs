@@ -18,14 +18,16 @@
 
 use std::collections::HashMap;
 use std::str::FromStr;
-use std::net::SocketAddr;
 use std::fmt;
 use std::result::Result as StdResult;
 
-use serde::de::{Error as DeError, Visitor, Deserialize, Deserializer};
+use serde::de::{
+    Error as DeError, Visitor, Deserialize, Deserializer, SeqAccess,
+};
 
+use common::logging::{Destination, Format, Level};
 use common::prelude::*;
-use utils;
+use utils::{self, IpNetwork, ListenAddr};
 
 
 macro_rules! default {
@@ -64,6 +66,16 @@ pub struct Config {
     /// Extra environment variables.
     #[serde(default)]
     pub env: HashMap<String, String>,
+    /// Configuration for logging.
+    #[serde(default)]
+    pub log: LogConfig,
+}
+
+impl Config {
+    /// Check the values which are allowed by their types but aren't valid.
+    pub fn validate(&self) -> Result<()> {
+        self.jobs.validate()
+    }
 }
 
 
@@ -73,29 +85,240 @@ pub struct HttpConfig {
     /// The number of proxies Fisher is behind.
     #[serde(rename="behind-proxies", default="default_behind_proxies")]
     pub behind_proxies: u8,
-    /// The socket address to bind.
-    #[serde(default="default_bind")]
-    pub bind: SocketAddr,
+    /// The networks of the proxies Fisher trusts.
+    #[serde(rename="trusted-proxies", default)]
+    pub trusted_proxies: Vec<IpNetwork>,
+    /// The header proxies use to forward the client IP.
+    #[serde(rename="proxy-header", default="default_proxy_header")]
+    pub proxy_header: ProxyHeader,
+    /// The addresses to listen on.
+    #[serde(default="default_bind", deserialize_with="deserialize_bind")]
+    pub bind: Vec<ListenAddr>,
+    /// Configuration for the Unix sockets Fisher listens on
+    #[serde(rename="unix-socket", default)]
+    pub unix_socket: UnixSocketConfig,
+    /// The number of threads serving the requests of each address
+    #[serde(default="default_workers")]
+    pub workers: u16,
+    /// The maximum time clients can take to send a request
+    #[serde(rename="read-timeout", default="default_read_timeout")]
+    pub read_timeout: utils::TimeString,
+    /// The maximum size of the body of a request, in bytes
+    #[serde(rename="max-body-size", default="default_max_body_size")]
+    pub max_body_size: u64,
+    /// The maximum time to wait for synchronous scripts
+    #[serde(rename="sync-timeout", default="default_sync_timeout")]
+    pub sync_timeout: utils::TimeString,
+    /// Include the reason of rejected webhooks in the responses
+    #[serde(rename="verbose-errors", default="default_verbose_errors")]
+    pub verbose_errors: bool,
     /// The rate limit for bad requests
     #[serde(rename="rate-limit", default)]
     pub rate_limit: RateLimitConfig,
     /// Enable or disable the health endpoint
     #[serde(rename="health-endpoint", default="default_health_endpoint")]
     pub health_endpoint: bool,
+    /// Enable or disable the metrics endpoint
+    #[serde(rename="metrics-endpoint", default="default_metrics_endpoint")]
+    pub metrics_endpoint: bool,
+    /// Credentials required to access the protected endpoints
+    #[serde(default)]
+    pub auth: AuthConfig,
+    /// Configuration for the access log
+    #[serde(rename="access-log", default)]
+    pub access_log: AccessLogConfig,
+    /// Configuration for serving HTTPS
+    #[serde(default)]
+    pub tls: TlsConfig,
 }
 
 default_fn!(default_behind_proxies: u8 = 0);
-default_fn!(default_bind: SocketAddr = "127.0.0.1:8000".parse().unwrap());
+default_fn!(default_proxy_header: ProxyHeader = ProxyHeader::XForwardedFor);
+default_fn!(default_bind: Vec<ListenAddr> =
+    vec!["127.0.0.1:8000".parse().unwrap()]);
+default_fn!(default_workers: u16 = 4);
+default_fn!(default_read_timeout: utils::TimeString = 30.into());
+default_fn!(default_max_body_size: u64 = 10 * 1024 * 1024);
+default_fn!(default_sync_timeout: utils::TimeString = 30.into());
+default_fn!(default_verbose_errors: bool = false);
 default_fn!(default_health_endpoint: bool = true);
+default_fn!(default_metrics_endpoint: bool = false);
 
 default!(HttpConfig {
     behind_proxies: default_behind_proxies(),
+    trusted_proxies: Vec::new(),
+    proxy_header: default_proxy_header(),
     bind: default_bind(),
+    unix_socket: UnixSocketConfig::default(),
+    workers: default_workers(),
+    read_timeout: default_read_timeout(),
+    max_body_size: default_max_body_size(),
+    sync_timeout: default_sync_timeout(),
+    verbose_errors: default_verbose_errors(),
     rate_limit: RateLimitConfig::default(),
     health_endpoint: default_health_endpoint(),
+    metrics_endpoint: default_metrics_endpoint(),
+    auth: AuthConfig::default(),
+    access_log: AccessLogConfig::default(),
+    tls: TlsConfig::default(),
+});
+
+
+struct BindVisitor;
+
+impl<'de> Visitor<'de> for BindVisitor {
+    type Value = Vec<ListenAddr>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an address or a list of addresses")
+    }
+
+    fn visit_str<E: DeError>(self, s: &str) -> StdResult<Vec<ListenAddr>, E> {
+        match s.parse() {
+            Ok(parsed) => Ok(vec![parsed]),
+            Err(e) => Err(E::custom(e.to_string())),
+        }
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(
+        self, mut seq: A,
+    ) -> StdResult<Vec<ListenAddr>, A::Error> {
+        let mut result = Vec::new();
+        while let Some(addr) = seq.next_element()? {
+            result.push(addr);
+        }
+
+        if result.is_empty() {
+            Err(A::Error::custom("at least one address is needed"))
+        } else {
+            Ok(result)
+        }
+    }
+}
+
+fn deserialize_bind<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> StdResult<Vec<ListenAddr>, D::Error> {
+    deserializer.deserialize_any(BindVisitor)
+}
+
+
+/// Configuration for the Unix sockets the HTTP server listens on.
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+pub struct UnixSocketConfig {
+    /// The permissions of the socket, in octal.
+    #[serde(default = "default_unix_socket_mode")]
+    pub mode: String,
+    /// The group owning the socket, if it should be changed.
+    #[serde(default)]
+    pub group: Option<String>,
+}
+
+default_fn!(default_unix_socket_mode: String = "0660".into());
+
+default!(UnixSocketConfig {
+    mode: default_unix_socket_mode(),
+    group: None,
+});
+
+
+/// Configuration for the authentication of protected HTTP endpoints.
+#[derive(Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct AuthConfig {
+    /// The token accepted in the `Authorization: Bearer` header.
+    #[serde(default)]
+    pub token: Option<String>,
+    /// The username accepted with HTTP Basic authentication.
+    #[serde(default)]
+    pub username: Option<String>,
+    /// The password accepted with HTTP Basic authentication.
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+
+/// Configuration for serving HTTPS.
+#[derive(Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct TlsConfig {
+    /// The path to the PEM-encoded certificate.
+    #[serde(default)]
+    pub certificate: Option<String>,
+    /// The path to the PEM-encoded private key of the certificate.
+    #[serde(rename="private-key", default)]
+    pub private_key: Option<String>,
+    /// The path to the PEM-encoded CAs client certificates are verified
+    /// with, if clients should present one.
+    #[serde(rename="client-ca", default)]
+    pub client_ca: Option<String>,
+    /// Which requests need a client certificate.
+    #[serde(rename="client-auth", default)]
+    pub client_auth: ClientAuth,
+}
+
+
+/// Which requests need a client certificate, if client certificates are
+/// enabled.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientAuth {
+    /// Connections without a valid certificate are refused.
+    Required,
+    /// Only the protected endpoints need a valid certificate.
+    Optional,
+}
+
+impl Default for ClientAuth {
+    fn default() -> Self {
+        ClientAuth::Required
+    }
+}
+
+
+/// Configuration for the HTTP access log.
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+pub struct AccessLogConfig {
+    /// The file to write the access log to, if enabled.
+    #[serde(default)]
+    pub path: Option<String>,
+    /// The format of the access log.
+    #[serde(default = "default_access_log_format")]
+    pub format: AccessLogFormat,
+}
+
+default_fn!(default_access_log_format: AccessLogFormat =
+    AccessLogFormat::Common);
+
+default!(AccessLogConfig {
+    path: None,
+    format: default_access_log_format(),
 });
 
 
+/// The header containing the addresses forwarded by the proxies.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProxyHeader {
+    /// The de-facto standard `X-Forwarded-For` header.
+    XForwardedFor,
+    /// The `Forwarded` header defined in RFC 7239.
+    Forwarded,
+    /// The `X-Real-IP` header, containing only the client address.
+    XRealIp,
+}
+
+
+/// The format of the HTTP access log.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+    /// The Common Log Format.
+    Common,
+    /// The Combined Log Format, with the referer, the user agent and the
+    /// latency.
+    Combined,
+}
+
+
 /// Configuration for rate limiting.
 #[derive(Debug, PartialEq, Eq)]
 pub struct RateLimitConfig {
@@ -182,14 +405,117 @@ pub struct JobsConfig {
     /// The number of execution threads to use.
     #[serde(default = "default_threads")]
     pub threads: u16,
+    /// The number of jobs of each concurrency group allowed to run at the
+    /// same time. Groups not listed here run one job at a time.
+    #[serde(default)]
+    pub groups: HashMap<String, u16>,
+    /// How long a job has to wait in the queue for its priority to be
+    /// raised by one, if the priority of waiting jobs should be raised.
+    #[serde(rename = "priority-aging", default)]
+    pub priority_aging: Option<utils::TimeString>,
+    /// The maximum number of jobs waiting in the queue, if it's limited.
+    #[serde(rename = "max-queued", default)]
+    pub max_queued: Option<usize>,
+    /// What to do when a job is queued while the queue is full.
+    #[serde(default = "default_overflow")]
+    pub overflow: OverflowPolicy,
+    /// The user scripts are executed as by default.
+    #[serde(default)]
+    pub user: Option<String>,
+    /// The group scripts are executed as by default.
+    #[serde(rename = "user-group", default)]
+    pub user_group: Option<String>,
+    /// The resource limits of the jobs, unless scripts override them.
+    #[serde(default)]
+    pub limits: LimitsConfig,
 }
 
 default_fn!(default_threads: u16 = 1);
+default_fn!(default_overflow: OverflowPolicy = OverflowPolicy::Reject);
 
 default!(JobsConfig {
     threads: default_threads(),
+    groups: HashMap::new(),
+    priority_aging: None,
+    max_queued: None,
+    overflow: default_overflow(),
+    user: None,
+    user_group: None,
+    limits: LimitsConfig::default(),
 });
 
+impl JobsConfig {
+    fn validate(&self) -> Result<()> {
+        // The jobs of a group which can't run any of them would wait forever
+        for (group, limit) in &self.groups {
+            if *limit == 0 {
+                return Err(ErrorKind::InvalidGroupLimit(group.clone()).into());
+            }
+        }
+
+        if self.max_queued == Some(0) {
+            return Err(ErrorKind::InvalidMaxQueued.into());
+        }
+
+        // Jobs aging instantly would be executed ignoring their priority
+        if let Some(ref aging) = self.priority_aging {
+            if aging.as_u64() == 0 {
+                return Err(ErrorKind::InvalidPriorityAging.into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+/// Limits on the resources used by the processes of a job.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct LimitsConfig {
+    /// The CPU time each process can use.
+    #[serde(default)]
+    pub cpu: Option<utils::TimeString>,
+    /// The maximum size of the address space of each process, in bytes.
+    #[serde(default)]
+    pub memory: Option<u64>,
+    /// The maximum number of files each process can open.
+    #[serde(default)]
+    pub files: Option<u64>,
+    /// The maximum number of processes of the user running the job.
+    #[serde(default)]
+    pub processes: Option<u64>,
+    /// The maximum size of core dumps, in bytes.
+    #[serde(default)]
+    pub core: Option<u64>,
+}
+
+impl LimitsConfig {
+    /// Fill the limits not set with the ones of the other struct.
+    pub fn or(&self, defaults: &LimitsConfig) -> LimitsConfig {
+        LimitsConfig {
+            cpu: self.cpu.clone().or_else(|| defaults.cpu.clone()),
+            memory: self.memory.or(defaults.memory),
+            files: self.files.or(defaults.files),
+            processes: self.processes.or(defaults.processes),
+            core: self.core.or(defaults.core),
+        }
+    }
+}
+
+
+/// What to do with new jobs when the queue is full.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverflowPolicy {
+    /// Reject the new job.
+    Reject,
+    /// Drop the job which has been waiting for the longest time.
+    DropOldest,
+    /// Drop the job which would be executed last, which might be the new
+    /// one.
+    DropLowestPriority,
+}
+
 
 /// Configuration for looking scripts up.
 #[derive(Debug, PartialEq, Eq, Deserialize)]
@@ -209,3 +535,78 @@ default!(ScriptsConfig {
     path: default_path(),
     recursive: default_recursive(),
 });
+
+
+/// Configuration for logging.
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+pub struct LogConfig {
+    /// The minimum level of the messages to log.
+    #[serde(default = "default_log_level")]
+    pub level: Level,
+    /// The format of the messages.
+    #[serde(default = "default_log_format")]
+    pub format: Format,
+    /// Where to send the messages.
+    #[serde(default = "default_log_destination")]
+    pub destination: Destination,
+    /// The file to write the messages to, if the destination is a file.
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+default_fn!(default_log_level: Level = Level::Info);
+default_fn!(default_log_format: Format = Format::Text);
+default_fn!(default_log_destination: Destination = Destination::Stdout);
+
+default!(LogConfig {
+    level: default_log_level(),
+    format: default_log_format(),
+    destination: default_log_destination(),
+    path: None,
+});
+
+
+#[cfg(test)]
+mod tests {
+    use common::prelude::*;
+
+    use super::{Config, JobsConfig};
+
+
+    fn validate(jobs: JobsConfig) -> Result<()> {
+        Config {
+            jobs,
+            ..Config::default()
+        }
+        .validate()
+    }
+
+    #[test]
+    fn test_validate_jobs() {
+        assert!(validate(JobsConfig::default()).is_ok());
+
+        let mut jobs = JobsConfig::default();
+        jobs.groups.insert("deploy".into(), 2);
+        assert!(validate(jobs).is_ok());
+
+        let mut jobs = JobsConfig::default();
+        jobs.groups.insert("deploy".into(), 0);
+        assert_err!(validate(jobs), ErrorKind::InvalidGroupLimit(..));
+
+        let mut jobs = JobsConfig::default();
+        jobs.priority_aging = Some(60.into());
+        assert!(validate(jobs).is_ok());
+
+        let mut jobs = JobsConfig::default();
+        jobs.priority_aging = Some(0.into());
+        assert_err!(validate(jobs), ErrorKind::InvalidPriorityAging);
+
+        let mut jobs = JobsConfig::default();
+        jobs.max_queued = Some(1);
+        assert!(validate(jobs).is_ok());
+
+        let mut jobs = JobsConfig::default();
+        jobs.max_queued = Some(0);
+        assert_err!(validate(jobs), ErrorKind::InvalidMaxQueued);
+    }
+}
@@ -17,6 +17,8 @@ use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
 
+use common::logging::{self, Event};
+
 /// Convert a path relative to the current directory, if possible.
 ///
 /// This is used to display prettier error messages, and returns the original
@@ -69,6 +71,16 @@ error_chain! {
             display("odd length for hex string"),
         }
 
+        // Base64 parsing errors
+        Base64InvalidChar(chr: char) {
+            description("invalid char in base64"),
+            display("invalid char in base64: {}", chr),
+        }
+        Base64InvalidLength {
+            description("invalid length for base64 string"),
+            display("invalid length for base64 string"),
+        }
+
         // Time strings
         TimeStringInvalid(string: String) {
             description("invalid time string"),
@@ -92,6 +104,18 @@ error_chain! {
             description("wrong request kind"),
             display("wrong request kind"),
         }
+        InvalidForwardedHeader(value: String) {
+            description("invalid Forwarded header"),
+            display("invalid Forwarded header: {}", value),
+        }
+        RequestTimeout {
+            description("request timed out"),
+            display("the request wasn't received in time"),
+        }
+        RequestBodyTooLarge(limit: u64) {
+            description("request body too large"),
+            display("the request body is larger than {} bytes", limit),
+        }
 
         // Rate limit config
         RateLimitConfigTooManySlashes {
@@ -99,6 +123,97 @@ error_chain! {
             display("too many slashes present"),
         }
 
+        // Proxies config
+        InvalidIpNetwork(network: String) {
+            description("invalid IP network"),
+            display("invalid IP network: {}", network),
+        }
+        ProxyConfigConflict {
+            description("conflicting proxies configuration"),
+            display("behind-proxies and trusted-proxies can't be both set"),
+        }
+
+        // Authentication config
+        AuthConfigIncomplete {
+            description("incomplete HTTP Basic credentials"),
+            display("missing username or password for HTTP Basic auth"),
+        }
+
+        // Scripts preferences
+        InvalidStatusCode(status: u16) {
+            description("invalid HTTP status code"),
+            display("invalid HTTP status code: {}", status),
+        }
+        InvalidCoalesceKey(key: String) {
+            description("invalid coalesce key"),
+            display("invalid coalesce key: {}", key),
+        }
+        InvalidMaxParallel {
+            description("invalid max_parallel"),
+            display("max_parallel must be at least 1"),
+        }
+        FollowUpNotFound(script: String, followup: String) {
+            description("script started after another one not found"),
+            display(
+                "script {} should start {}, which doesn't exist",
+                script, followup,
+            ),
+        }
+        FollowUpCycle(chain: Vec<String>) {
+            description("scripts start each other in a loop"),
+            display(
+                "scripts start each other in a loop: {}",
+                chain.join(" -> "),
+            ),
+        }
+        UserNotFound(user: String) {
+            description("user not found"),
+            display("user not found: {}", user),
+        }
+        GroupNotFound(group: String) {
+            description("group not found"),
+            display("group not found: {}", group),
+        }
+        UserSwitchNotAllowed(user: String) {
+            description("can't switch user without running as root"),
+            display(
+                "Fisher must be started as root to run scripts as {}", user,
+            ),
+        }
+        UnsafeScript(file: String) {
+            description("script writable by other users"),
+            display(
+                "the script '{}' can be changed by users other than root \
+                 and the one running it",
+                relative_to_current(file).to_string_lossy(),
+            ),
+        }
+        UnsafeResponseBody(file: String) {
+            description("response body not written by the script"),
+            display(
+                "the response body '{}' isn't a file written by the script",
+                file,
+            ),
+        }
+        UnsafeScriptsDirectory(dir: String) {
+            description("scripts directory writable by other users"),
+            display(
+                "the directory '{}' containing scripts can be changed by \
+                 users other than root and the one running them",
+                relative_to_current(dir).to_string_lossy(),
+            ),
+        }
+
+        // Processor errors
+        QueueFull(script: String) {
+            description("too many jobs queued"),
+            display("too many jobs of the '{}' script are queued", script),
+        }
+        QueueOverflow {
+            description("the jobs queue is full"),
+            display("the jobs queue is full"),
+        }
+
         // Providers errors
         ProviderNotFound(name: String) {
             description("provider not found"),
@@ -112,6 +227,10 @@ error_chain! {
             description("invalid GitLab event name"),
             display("invalid GitLab event name: {}", name),
         }
+        ProviderCronInvalidSchedule(schedule: String) {
+            description("invalid cron schedule"),
+            display("invalid cron schedule: {}", schedule),
+        }
 
         // Broken things
         BrokenChannel {
@@ -145,16 +264,86 @@ error_chain! {
             description("error while parsing the rate limit config"),
             display("error while parsing rate limit config '{}'", string),
         }
+        InvalidGroupLimit(group: String) {
+            description("invalid concurrency group limit"),
+            display("the limit of the group {} must be at least 1", group),
+        }
+        InvalidMaxQueued {
+            description("invalid max-queued"),
+            display("jobs.max-queued must be at least 1"),
+        }
+        InvalidPriorityAging {
+            description("invalid priority aging"),
+            display("jobs.priority-aging must be at least 1 second"),
+        }
+        TlsConfigIncomplete {
+            description("incomplete TLS configuration"),
+            display("missing certificate or private key for TLS"),
+        }
+        TlsNotSupported {
+            description("TLS is not supported"),
+            display("this build of Fisher doesn't support TLS"),
+        }
+        TlsCertificateMissing {
+            description("no TLS certificate found"),
+            display("no certificate found in the TLS certificate file"),
+        }
+        TlsClientCaMissing {
+            description("no client CA found"),
+            display("no certificate found in the TLS client CA file"),
+        }
+        InvalidListenAddr(addr: String) {
+            description("invalid listen address"),
+            display("invalid listen address: {}", addr),
+        }
+        InvalidUnixSocketMode(mode: String) {
+            description("invalid Unix socket mode"),
+            display("invalid Unix socket mode (not in octal): {}", mode),
+        }
+        UnixSocketGroupNotFound(group: String) {
+            description("group of the Unix socket not found"),
+            display("group of the Unix socket not found: {}", group),
+        }
+        UnixSocketPathInUse(path: String) {
+            description("the path of the Unix socket is already used"),
+            display("the path of the Unix socket is not a socket: {}", path),
+        }
+        SystemdSocketsMissing {
+            description("no sockets passed by systemd"),
+            display("systemd didn't pass any socket to Fisher"),
+        }
+        SystemdSocketUnsupported(fd: i32) {
+            description("unsupported socket passed by systemd"),
+            display("unsupported socket passed by systemd (fd {})", fd),
+        }
+        LogFileMissing {
+            description("missing path of the log file"),
+            display("the log destination is a file, but no path was set"),
+        }
     }
 }
 
 impl Error {
-    pub fn pretty_print(&self) {
-        println!("Error: {}", self);
-        for chain in self.iter().skip(1) {
-            println!("  caused by: {}", chain);
+    /// Create a log event describing this error and what caused it.
+    pub fn to_event(&self) -> Event {
+        let event = logging::error(self.to_string());
+
+        let causes = self
+            .iter()
+            .skip(1)
+            .map(|cause| cause.to_string())
+            .collect::<Vec<_>>();
+        if causes.is_empty() {
+            event
+        } else {
+            event.field("caused_by", causes.join(": "))
         }
     }
+
+    /// Log this error and what caused it.
+    pub fn log(&self) {
+        self.to_event().log();
+    }
 }
 
 impl<T> From<::std::sync::mpsc::SendError<T>> for Error {
@@ -0,0 +1,432 @@
+// Copyright (C) 2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Structured logging.
+//!
+//! Every log message is an [`Event`](struct.Event.html), with a level, a
+//! message and some fields attached to it. Events are sent to a global
+//! logger, which formats them as plain text or JSON and writes them to the
+//! configured destination.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+
+use serde_json;
+
+use common::config::LogConfig;
+use common::prelude::*;
+use utils::UtcDate;
+
+const SYSLOG_SOCKET: &str = "/dev/log";
+const SYSLOG_FACILITY_DAEMON: u8 = 3;
+
+lazy_static! {
+    static ref LOGGER: Mutex<Logger> = Mutex::new(Logger::default());
+}
+
+/// The severity of a log message.
+#[derive(
+    Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    /// Messages useful only while debugging Fisher.
+    Debug,
+    /// Normal events, like incoming requests and executed jobs.
+    Info,
+    /// Something unusual happened, but Fisher can continue.
+    Warning,
+    /// Something failed.
+    Error,
+}
+
+impl Level {
+    fn name(&self) -> &'static str {
+        match *self {
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warning => "warning",
+            Level::Error => "error",
+        }
+    }
+
+    fn syslog_severity(&self) -> u8 {
+        match *self {
+            Level::Debug => 7,
+            Level::Info => 6,
+            Level::Warning => 4,
+            Level::Error => 3,
+        }
+    }
+}
+
+/// The format of the log messages.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Human-readable lines, with the fields as `key=value` pairs.
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+/// Where the log messages are sent.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Destination {
+    /// The standard output of the process.
+    Stdout,
+    /// A file, appending to it.
+    File,
+    /// The local syslog daemon.
+    Syslog,
+}
+
+enum Output {
+    Stdout,
+    File(File, PathBuf),
+    Syslog(UnixDatagram),
+}
+
+impl Output {
+    fn write(&mut self, level: Level, line: &str) -> io::Result<()> {
+        match *self {
+            Output::Stdout => {
+                // println! would panic if stdout was closed
+                let stdout = io::stdout();
+                let mut stdout = stdout.lock();
+                writeln!(stdout, "{}", line)
+            }
+            Output::File(ref mut file, _) => writeln!(file, "{}", line),
+            Output::Syslog(ref socket) => {
+                let priority =
+                    SYSLOG_FACILITY_DAEMON * 8 + level.syslog_severity();
+                let message = format!(
+                    "<{}>fisher[{}]: {}",
+                    priority,
+                    process::id(),
+                    line
+                );
+                socket.send(message.as_bytes()).map(|_| ())
+            }
+        }
+    }
+}
+
+struct Logger {
+    level: Level,
+    format: Format,
+    output: Output,
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Logger {
+            level: Level::Info,
+            format: Format::Text,
+            output: Output::Stdout,
+        }
+    }
+}
+
+impl Logger {
+    fn new(config: &LogConfig) -> Result<Self> {
+        let output = match config.destination {
+            Destination::Stdout => Output::Stdout,
+            Destination::File => {
+                let path = config
+                    .path
+                    .as_ref()
+                    .ok_or(ErrorKind::LogFileMissing)?;
+                let path = PathBuf::from(path);
+                Output::File(open(&path)?, path)
+            }
+            Destination::Syslog => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(SYSLOG_SOCKET)?;
+                Output::Syslog(socket)
+            }
+        };
+
+        Ok(Logger {
+            level: config.level,
+            format: config.format,
+            output,
+        })
+    }
+
+    fn reopen(&mut self) -> Result<()> {
+        if let Output::File(ref mut file, ref path) = self.output {
+            *file = open(path)?;
+        }
+        Ok(())
+    }
+
+    fn log(&mut self, event: &Event) {
+        if event.level < self.level {
+            return;
+        }
+
+        // Syslog adds its own timestamp to the messages
+        let timestamp = match self.output {
+            Output::Syslog(..) => None,
+            _ => Some(UtcDate::now().rfc3339()),
+        };
+        let line = event.format(self.format, timestamp.as_ref());
+
+        // There is no better place to report failures of the logger
+        if let Err(err) = self.output.write(event.level, &line) {
+            eprintln!("{}", line);
+            eprintln!("failed to write the log message: {}", err);
+        }
+    }
+}
+
+/// Configure the global logger, replacing the previous configuration.
+pub fn configure(config: &LogConfig) -> Result<()> {
+    let logger = Logger::new(config)?;
+    *lock_logger() = logger;
+    Ok(())
+}
+
+/// Reopen the log file, for example after it was rotated.
+pub fn reopen() -> Result<()> {
+    lock_logger().reopen()
+}
+
+fn open(path: &Path) -> Result<File> {
+    Ok(OpenOptions::new().create(true).append(true).open(path)?)
+}
+
+fn lock_logger() -> ::std::sync::MutexGuard<'static, Logger> {
+    // A panic while logging shouldn't prevent logging anything else
+    match LOGGER.lock() {
+        Ok(lock) => lock,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// A log message, with some fields attached to it.
+#[derive(Debug, Clone)]
+pub struct Event {
+    level: Level,
+    message: String,
+    fields: Vec<(&'static str, String)>,
+}
+
+impl Event {
+    /// Create a new event with the provided level and message.
+    pub fn new<M: Into<String>>(level: Level, message: M) -> Self {
+        Event {
+            level,
+            message: message.into(),
+            fields: Vec::new(),
+        }
+    }
+
+    /// Attach a field to the event.
+    pub fn field<V: ToString>(mut self, name: &'static str, value: V) -> Self {
+        self.fields.push((name, value.to_string()));
+        self
+    }
+
+    /// Send the event to the global logger.
+    pub fn log(self) {
+        lock_logger().log(&self);
+    }
+
+    fn format(&self, format: Format, timestamp: Option<&String>) -> String {
+        match format {
+            Format::Text => {
+                let mut line = String::new();
+                if let Some(timestamp) = timestamp {
+                    line.push_str(timestamp);
+                    line.push(' ');
+                }
+                line.push_str(&format!(
+                    "{:<7} {}",
+                    self.level.name().to_uppercase(),
+                    self.message
+                ));
+
+                for &(name, ref value) in &self.fields {
+                    line.push_str(&format!(" {}={}", name, quote(value)));
+                }
+
+                line
+            }
+            Format::Json => {
+                let mut object = serde_json::Map::new();
+                if let Some(timestamp) = timestamp {
+                    object.insert("timestamp".into(), json!(timestamp));
+                }
+                object.insert("level".into(), json!(self.level.name()));
+                object.insert("message".into(), json!(self.message));
+
+                for &(name, ref value) in &self.fields {
+                    object.insert(name.into(), json!(value));
+                }
+
+                serde_json::Value::Object(object).to_string()
+            }
+        }
+    }
+}
+
+/// Create a new event with the `debug` level.
+pub fn debug<M: Into<String>>(message: M) -> Event {
+    Event::new(Level::Debug, message)
+}
+
+/// Create a new event with the `info` level.
+pub fn info<M: Into<String>>(message: M) -> Event {
+    Event::new(Level::Info, message)
+}
+
+/// Create a new event with the `warning` level.
+pub fn warning<M: Into<String>>(message: M) -> Event {
+    Event::new(Level::Warning, message)
+}
+
+/// Create a new event with the `error` level.
+pub fn error<M: Into<String>>(message: M) -> Event {
+    Event::new(Level::Error, message)
+}
+
+/// Quote a value of the text format, if it needs to be quoted.
+fn quote(value: &str) -> String {
+    let needs_quotes = value.is_empty()
+        || value
+            .chars()
+            .any(|c| c.is_whitespace() || c == '"' || c == '=');
+
+    if needs_quotes {
+        format!("{:?}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::Read;
+
+    use serde_json;
+    use tempdir::TempDir;
+
+    use common::config::LogConfig;
+
+    use super::{quote, Destination, Event, Format, Level, Logger};
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("simple"), "simple");
+        assert_eq!(quote(""), "\"\"");
+        assert_eq!(quote("with space"), "\"with space\"");
+        assert_eq!(quote("a=b"), "\"a=b\"");
+        assert_eq!(quote("a\"b"), "\"a\\\"b\"");
+    }
+
+    #[test]
+    fn test_format_text() {
+        let event = Event::new(Level::Info, "request processed")
+            .field("method", "GET")
+            .field("path", "/hook/a b.sh")
+            .field("status", 200);
+        let timestamp = "2017-10-18T12:34:56.000Z".to_string();
+
+        assert_eq!(
+            event.format(Format::Text, Some(&timestamp)),
+            concat!(
+                "2017-10-18T12:34:56.000Z INFO    request processed ",
+                "method=GET path=\"/hook/a b.sh\" status=200",
+            )
+        );
+        assert_eq!(
+            event.format(Format::Text, None),
+            "INFO    request processed method=GET path=\"/hook/a b.sh\" \
+             status=200"
+        );
+    }
+
+    #[test]
+    fn test_format_json() {
+        let event = Event::new(Level::Error, "job failed")
+            .field("request_id", "abcd");
+        let timestamp = "2017-10-18T12:34:56.000Z".to_string();
+
+        let json: serde_json::Value = serde_json::from_str(
+            &event.format(Format::Json, Some(&timestamp)),
+        ).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "timestamp": "2017-10-18T12:34:56.000Z",
+                "level": "error",
+                "message": "job failed",
+                "request_id": "abcd",
+            })
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&event.format(Format::Json, None)).unwrap();
+        assert!(json.get("timestamp").is_none());
+    }
+
+    #[test]
+    fn test_level_ordering() {
+        assert!(Level::Debug < Level::Info);
+        assert!(Level::Info < Level::Warning);
+        assert!(Level::Warning < Level::Error);
+    }
+
+    #[test]
+    fn test_reopen() {
+        let dir = TempDir::new("fisher").unwrap();
+        let path = dir.path().join("fisher.log");
+        let rotated = dir.path().join("fisher.log.1");
+
+        let mut logger = Logger::new(&LogConfig {
+            destination: Destination::File,
+            path: Some(path.to_str().unwrap().into()),
+            ..LogConfig::default()
+        }).unwrap();
+
+        let read = |path| {
+            let mut content = String::new();
+            File::open(path)
+                .unwrap()
+                .read_to_string(&mut content)
+                .unwrap();
+            content
+        };
+
+        // Rotate the log, and ensure new messages go to the new file only
+        let event = Event::new(Level::Info, "hello");
+        logger.log(&event);
+        fs::rename(&path, &rotated).unwrap();
+        logger.reopen().unwrap();
+        logger.log(&event);
+        logger.log(&event);
+
+        assert_eq!(read(&rotated).lines().count(), 1);
+        assert_eq!(read(&path).lines().count(), 2);
+    }
+}
@@ -0,0 +1,488 @@
+// Copyright (C) 2019 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Fisher's metrics.
+//!
+//! This module keeps counters about what Fisher is doing, and renders them in
+//! the Prometheus text exposition format. Metrics are keyed by script name
+//! instead of script ID, so they survive reloads.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use common::structs::{HealthDetails, ScriptHealth};
+
+/// The upper bounds (in seconds) of the job duration histogram buckets.
+const DURATION_BUCKETS: &[f64] = &[
+    0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 600.0, 1800.0, 3600.0,
+];
+
+/// The reason why a webhook was rejected.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RejectReason {
+    /// The script doesn't exist.
+    NotFound,
+    /// The request didn't pass validation.
+    Forbidden,
+    /// The client is rate limited.
+    RateLimited,
+    /// The instance is locked.
+    Locked,
+    /// The script has too many jobs queued.
+    QueueFull,
+    /// The jobs queue is full.
+    QueueOverflow,
+}
+
+impl RejectReason {
+    fn name(&self) -> &'static str {
+        match *self {
+            RejectReason::NotFound => "not_found",
+            RejectReason::Forbidden => "forbidden",
+            RejectReason::RateLimited => "rate_limited",
+            RejectReason::Locked => "locked",
+            RejectReason::QueueFull => "queue_full",
+            RejectReason::QueueOverflow => "queue_overflow",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; DURATION_BUCKETS.len()];
+        }
+
+        for (bucket, bound) in self.buckets.iter_mut().zip(DURATION_BUCKETS) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+#[derive(Debug, Default)]
+struct MetricsInner {
+    received: u64,
+    rejected: BTreeMap<RejectReason, u64>,
+    executed: BTreeMap<(String, String), u64>,
+    jobs: BTreeMap<(String, bool), u64>,
+    durations: BTreeMap<String, Histogram>,
+    last_results: BTreeMap<String, ScriptHealth>,
+}
+
+/// Thread-safe collection of the Fisher metrics.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    inner: Mutex<MetricsInner>,
+}
+
+impl Metrics {
+    /// Create a new, empty, instance of the struct.
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    fn with_inner<R, F>(&self, func: F) -> R
+    where
+        F: FnOnce(&mut MetricsInner) -> R,
+    {
+        // Metrics are not important enough to crash on poisoned locks
+        match self.inner.lock() {
+            Ok(mut inner) => func(&mut inner),
+            Err(poisoned) => func(&mut poisoned.into_inner()),
+        }
+    }
+
+    /// Record a webhook was received.
+    pub fn webhook_received(&self) {
+        self.with_inner(|inner| inner.received += 1);
+    }
+
+    /// Record a webhook was rejected.
+    pub fn webhook_rejected(&self, reason: RejectReason) {
+        self.with_inner(|inner| {
+            *inner.rejected.entry(reason).or_insert(0) += 1;
+        });
+    }
+
+    /// Record a webhook caused a job to be queued.
+    pub fn webhook_executed(&self, script: &str, provider: Option<&str>) {
+        let key = (script.to_string(), provider.unwrap_or("none").into());
+        self.with_inner(|inner| {
+            *inner.executed.entry(key).or_insert(0) += 1;
+        });
+    }
+
+    /// Record a job finished running.
+    pub fn job_finished(&self, script: &str, success: bool, took: Duration) {
+        let seconds =
+            took.as_secs() as f64 + f64::from(took.subsec_nanos()) / 1e9;
+
+        self.with_inner(|inner| {
+            *inner.jobs.entry((script.into(), success)).or_insert(0) += 1;
+            inner
+                .durations
+                .entry(script.into())
+                .or_insert_with(Histogram::default)
+                .observe(seconds);
+            inner
+                .last_results
+                .entry(script.into())
+                .or_insert_with(ScriptHealth::default)
+                .record(success);
+        });
+    }
+
+    /// Get the average time the jobs of a script took to run, if any of them
+    /// finished.
+    pub fn average_duration(&self, script: &str) -> Option<Duration> {
+        self.with_inner(|inner| {
+            let histogram = inner.durations.get(script)?;
+            if histogram.count == 0 {
+                return None;
+            }
+
+            let average = histogram.sum / histogram.count as f64;
+            Some(Duration::new(
+                average.trunc() as u64,
+                (average.fract() * 1e9) as u32,
+            ))
+        })
+    }
+
+    /// Get the results of the last runs of a script.
+    pub fn script_health(&self, script: &str) -> ScriptHealth {
+        self.with_inner(|inner| {
+            inner.last_results.get(script).cloned().unwrap_or_default()
+        })
+    }
+
+    /// Render all the metrics in the Prometheus text exposition format.
+    pub fn render(
+        &self,
+        health: &HealthDetails,
+        rate_limited: usize,
+    ) -> String {
+        let mut out = String::new();
+
+        self.with_inner(|inner| {
+            header(
+                &mut out,
+                "webhooks_received_total",
+                "counter",
+                "Webhooks received by Fisher.",
+            );
+            sample(&mut out, "webhooks_received_total", &[], inner.received);
+
+            header(
+                &mut out,
+                "webhooks_rejected_total",
+                "counter",
+                "Webhooks rejected by Fisher, by reason.",
+            );
+            for (reason, count) in &inner.rejected {
+                sample(
+                    &mut out,
+                    "webhooks_rejected_total",
+                    &[("reason", reason.name())],
+                    *count,
+                );
+            }
+
+            header(
+                &mut out,
+                "webhooks_executed_total",
+                "counter",
+                "Webhooks which queued a job, by script and provider.",
+            );
+            for ((script, provider), count) in &inner.executed {
+                sample(
+                    &mut out,
+                    "webhooks_executed_total",
+                    &[("script", script), ("provider", provider)],
+                    *count,
+                );
+            }
+
+            header(
+                &mut out,
+                "jobs_total",
+                "counter",
+                "Jobs which finished running, by script and result.",
+            );
+            for (&(ref script, success), count) in &inner.jobs {
+                let result = if success { "success" } else { "failure" };
+                sample(
+                    &mut out,
+                    "jobs_total",
+                    &[("script", script), ("result", result)],
+                    *count,
+                );
+            }
+
+            header(
+                &mut out,
+                "job_duration_seconds",
+                "histogram",
+                "Time spent running jobs, by script.",
+            );
+            for (script, histogram) in &inner.durations {
+                for (bound, count) in
+                    DURATION_BUCKETS.iter().zip(&histogram.buckets)
+                {
+                    sample(
+                        &mut out,
+                        "job_duration_seconds_bucket",
+                        &[("script", script), ("le", &bound.to_string())],
+                        *count,
+                    );
+                }
+                sample(
+                    &mut out,
+                    "job_duration_seconds_bucket",
+                    &[("script", script), ("le", "+Inf")],
+                    histogram.count,
+                );
+                sample(
+                    &mut out,
+                    "job_duration_seconds_sum",
+                    &[("script", script)],
+                    histogram.sum,
+                );
+                sample(
+                    &mut out,
+                    "job_duration_seconds_count",
+                    &[("script", script)],
+                    histogram.count,
+                );
+            }
+        });
+
+        header(
+            &mut out,
+            "queued_jobs",
+            "gauge",
+            "Jobs waiting to be processed.",
+        );
+        sample(&mut out, "queued_jobs", &[], health.queued_jobs);
+
+        header(
+            &mut out,
+            "busy_threads",
+            "gauge",
+            "Threads currently processing jobs.",
+        );
+        sample(&mut out, "busy_threads", &[], health.busy_threads);
+
+        header(
+            &mut out,
+            "max_threads",
+            "gauge",
+            "Threads available to process jobs.",
+        );
+        sample(&mut out, "max_threads", &[], health.max_threads);
+
+        header(
+            &mut out,
+            "jobs_dropped_total",
+            "counter",
+            "Queued jobs dropped because the queue was full.",
+        );
+        sample(&mut out, "jobs_dropped_total", &[], health.dropped_jobs);
+
+        header(
+            &mut out,
+            "rate_limiter_entries",
+            "gauge",
+            "Clients tracked by the rate limiter.",
+        );
+        sample(&mut out, "rate_limiter_entries", &[], rate_limited);
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP fisher_{} {}", name, help);
+    let _ = writeln!(out, "# TYPE fisher_{} {}", name, kind);
+}
+
+fn sample<V: ToString>(
+    out: &mut String,
+    name: &str,
+    labels: &[(&str, &str)],
+    value: V,
+) {
+    let _ = write!(out, "fisher_{}", name);
+
+    if !labels.is_empty() {
+        out.push('{');
+        for (i, &(key, value)) in labels.iter().enumerate() {
+            if i != 0 {
+                out.push(',');
+            }
+            let _ = write!(out, "{}=\"{}\"", key, escape_label(value));
+        }
+        out.push('}');
+    }
+
+    let _ = writeln!(out, " {}", value.to_string());
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use common::structs::HealthDetails;
+
+    use super::{escape_label, Metrics, RejectReason};
+
+    fn health() -> HealthDetails {
+        HealthDetails {
+            queued_jobs: 1,
+            busy_threads: 2,
+            max_threads: 3,
+            dropped_jobs: 5,
+            ..HealthDetails::default()
+        }
+    }
+
+    #[test]
+    fn test_escape_label() {
+        assert_eq!(escape_label("a.sh"), "a.sh");
+        assert_eq!(escape_label(r#"a"b\c"#), r#"a\"b\\c"#);
+        assert_eq!(escape_label("a\nb"), r"a\nb");
+    }
+
+    #[test]
+    fn test_script_health() {
+        let metrics = Metrics::new();
+
+        // Scripts which never ran don't have any result
+        let health = metrics.script_health("a.sh");
+        assert!(health.last_success.is_none());
+        assert!(health.last_failure.is_none());
+
+        metrics.job_finished("a.sh", true, Duration::from_secs(1));
+        let health = metrics.script_health("a.sh");
+        assert!(health.last_success.is_some());
+        assert!(health.last_failure.is_none());
+
+        metrics.job_finished("a.sh", false, Duration::from_secs(1));
+        let health = metrics.script_health("a.sh");
+        assert!(health.last_success.is_some());
+        assert!(health.last_failure.is_some());
+        assert!(metrics.script_health("b.sh").last_failure.is_none());
+    }
+
+    #[test]
+    fn test_average_duration() {
+        let metrics = Metrics::new();
+        assert_eq!(metrics.average_duration("a.sh"), None);
+
+        metrics.job_finished("a.sh", true, Duration::from_secs(1));
+        metrics.job_finished("a.sh", false, Duration::from_millis(2500));
+        assert_eq!(
+            metrics.average_duration("a.sh"),
+            Some(Duration::from_millis(1750))
+        );
+        assert_eq!(metrics.average_duration("b.sh"), None);
+    }
+
+    #[test]
+    fn test_render_empty() {
+        let rendered = Metrics::new().render(&health(), 4);
+
+        assert!(rendered.contains("fisher_webhooks_received_total 0\n"));
+        assert!(rendered.contains("fisher_queued_jobs 1\n"));
+        assert!(rendered.contains("fisher_busy_threads 2\n"));
+        assert!(rendered.contains("fisher_max_threads 3\n"));
+        assert!(rendered.contains("fisher_jobs_dropped_total 5\n"));
+        assert!(rendered.contains("fisher_rate_limiter_entries 4\n"));
+        assert!(!rendered.contains("fisher_jobs_total{"));
+    }
+
+    #[test]
+    fn test_render_counters() {
+        let metrics = Metrics::new();
+        for _ in 0..3 {
+            metrics.webhook_received();
+        }
+        metrics.webhook_rejected(RejectReason::NotFound);
+        metrics.webhook_rejected(RejectReason::Locked);
+        metrics.webhook_rejected(RejectReason::Locked);
+        metrics.webhook_executed("a.sh", Some("GitHub"));
+        metrics.webhook_executed("b.sh", None);
+
+        let rendered = metrics.render(&health(), 0);
+        for expected in &[
+            "fisher_webhooks_received_total 3\n",
+            "fisher_webhooks_rejected_total{reason=\"not_found\"} 1\n",
+            "fisher_webhooks_rejected_total{reason=\"locked\"} 2\n",
+            concat!(
+                "fisher_webhooks_executed_total",
+                "{script=\"a.sh\",provider=\"GitHub\"} 1\n",
+            ),
+            concat!(
+                "fisher_webhooks_executed_total",
+                "{script=\"b.sh\",provider=\"none\"} 1\n",
+            ),
+        ] {
+            assert!(rendered.contains(expected), "missing: {}", expected);
+        }
+    }
+
+    #[test]
+    fn test_render_jobs() {
+        let metrics = Metrics::new();
+        metrics.job_finished("a.sh", true, Duration::from_millis(300));
+        metrics.job_finished("a.sh", true, Duration::from_secs(20));
+        metrics.job_finished("a.sh", false, Duration::from_secs(7200));
+
+        let rendered = metrics.render(&health(), 0);
+        for expected in &[
+            "fisher_jobs_total{script=\"a.sh\",result=\"success\"} 2\n",
+            "fisher_jobs_total{script=\"a.sh\",result=\"failure\"} 1\n",
+            "fisher_job_duration_seconds_bucket{script=\"a.sh\",le=\"0.1\"} 0\n",
+            "fisher_job_duration_seconds_bucket{script=\"a.sh\",le=\"0.5\"} 1\n",
+            "fisher_job_duration_seconds_bucket{script=\"a.sh\",le=\"30\"} 2\n",
+            "fisher_job_duration_seconds_bucket{script=\"a.sh\",le=\"+Inf\"} 3\n",
+            "fisher_job_duration_seconds_sum{script=\"a.sh\"} 7220.3\n",
+            "fisher_job_duration_seconds_count{script=\"a.sh\"} 3\n",
+        ] {
+            assert!(rendered.contains(expected), "missing: {}", expected);
+        }
+    }
+}
@@ -19,6 +19,8 @@
 
 pub mod config;
 pub mod errors;
+pub mod logging;
+pub mod metrics;
 pub mod prelude;
 pub mod serial;
 pub mod state;
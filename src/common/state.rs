@@ -23,6 +23,11 @@
 use std::cmp::Ordering as CmpOrdering;
 use std::cmp::PartialOrd;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use common::metrics::Metrics;
+use common::structs::ReloadDetails;
 
 /// This enum represents a kind of ID.
 ///
@@ -68,6 +73,9 @@ impl PartialOrd for UniqueId {
 #[derive(Debug)]
 pub struct State {
     counter: AtomicUsize,
+    metrics: Metrics,
+    started_at: Instant,
+    last_reload: Mutex<Option<ReloadDetails>>,
 }
 
 impl State {
@@ -75,9 +83,41 @@ impl State {
     pub fn new() -> Self {
         Self {
             counter: AtomicUsize::new(0),
+            metrics: Metrics::new(),
+            started_at: Instant::now(),
+            last_reload: Mutex::new(None),
+        }
+    }
+
+    /// Get how much time passed since this instance of Fisher started.
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    /// Record the outcome of a configuration reload, with the error which
+    /// caused it to fail if it failed.
+    pub fn record_reload(&self, error: Option<String>) {
+        let mut last_reload = match self.last_reload.lock() {
+            Ok(lock) => lock,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        *last_reload = Some(ReloadDetails::now(error));
+    }
+
+    /// Get the outcome of the last configuration reload, if there was one.
+    pub fn last_reload(&self) -> Option<ReloadDetails> {
+        match self.last_reload.lock() {
+            Ok(lock) => lock.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
         }
     }
 
+    /// Get the [`Metrics`](../metrics/struct.Metrics.html) collected by this
+    /// instance of Fisher.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
     /// Get the next ID for a specific [`IdKind`](enum.IdKind.html). The ID is
     /// guaranteed to be unique and greater than the last ID.
     pub fn next_id(&self, kind: IdKind) -> UniqueId {
@@ -105,4 +145,20 @@ mod tests {
         assert!(id1 != id2);
         assert!(id1 != id3);
     }
+
+    #[test]
+    fn test_record_reload() {
+        let state = State::new();
+        assert!(state.last_reload().is_none());
+
+        state.record_reload(Some("broken".into()));
+        let reload = state.last_reload().unwrap();
+        assert!(!reload.success);
+        assert_eq!(reload.error, Some("broken".into()));
+
+        state.record_reload(None);
+        let reload = state.last_reload().unwrap();
+        assert!(reload.success);
+        assert!(reload.error.is_none());
+    }
 }
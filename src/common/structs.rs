@@ -16,9 +16,21 @@
 //! Structs used by Fisher.
 
 
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+
+fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+
 /// This struct contains some information about how the processor is feeling.
 
-#[derive(Copy, Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Default, Serialize)]
 pub struct HealthDetails {
     /// The number of jobs in the queue, waiting to be processed.
     pub queued_jobs: usize,
@@ -28,4 +40,135 @@ pub struct HealthDetails {
 
     /// The total number of threads running, either waiting or working.
     pub max_threads: u16,
+
+    /// How many seconds the oldest job in the queue has been waiting, if
+    /// there are queued jobs.
+    pub oldest_queued_job: Option<u64>,
+
+    /// The maximum number of jobs allowed in the queue, if it's limited.
+    pub max_queued: Option<usize>,
+
+    /// The number of jobs rejected since Fisher started because the queue
+    /// was full.
+    pub rejected_jobs: u64,
+
+    /// The number of queued jobs dropped since Fisher started to make room
+    /// for new ones.
+    pub dropped_jobs: u64,
+
+    /// The number of scripts currently loaded.
+    pub loaded_scripts: usize,
+
+    /// Whether the instance is locked, and so it's not running new jobs.
+    pub locked: bool,
+
+    /// The number of seconds since Fisher started.
+    pub uptime: u64,
+
+    /// The outcome of the last configuration reload, if there was one.
+    pub last_reload: Option<ReloadDetails>,
+
+    /// The results of the last runs of each loaded script.
+    pub scripts: BTreeMap<String, ScriptHealth>,
+}
+
+impl HealthDetails {
+    /// Return if the instance is ready to process webhooks, which means
+    /// it's not locked and it has some scripts loaded.
+    pub fn ready(&self) -> bool {
+        !self.locked && self.loaded_scripts > 0
+    }
+}
+
+
+/// This struct contains the outcome of a configuration reload.
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ReloadDetails {
+    /// When the reload happened, as a UNIX timestamp.
+    pub timestamp: u64,
+
+    /// Whether the reload was successful.
+    pub success: bool,
+
+    /// The error which caused the reload to fail, if it failed.
+    pub error: Option<String>,
+}
+
+impl ReloadDetails {
+    /// Create a new instance of the struct for a reload which happened now.
+    pub fn now(error: Option<String>) -> Self {
+        ReloadDetails {
+            timestamp: timestamp(),
+            success: error.is_none(),
+            error,
+        }
+    }
+}
+
+
+/// This struct contains the results of the last runs of a script.
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ScriptHealth {
+    /// When the script last completed successfully, as a UNIX timestamp.
+    pub last_success: Option<u64>,
+
+    /// When the script last failed, as a UNIX timestamp.
+    pub last_failure: Option<u64>,
+}
+
+impl ScriptHealth {
+    /// Record the script completed just now.
+    pub fn record(&mut self, success: bool) {
+        if success {
+            self.last_success = Some(timestamp());
+        } else {
+            self.last_failure = Some(timestamp());
+        }
+    }
+}
+
+
+/// This enum represents the state a job is in.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    /// The job is waiting to be executed.
+    Queued,
+
+    /// The job is currently being executed.
+    Running,
+
+    /// The job completed successfully.
+    Succeeded,
+
+    /// The job failed.
+    Failed,
+
+    /// The job was replaced by a newer one before being executed.
+    Superseded,
+
+    /// The job was removed from the queue to make room for other jobs.
+    Dropped,
+}
+
+
+/// This struct contains some information about a job.
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct JobDetails {
+    /// The unique ID of the job.
+    pub id: String,
+
+    /// The name of the script executed by the job.
+    pub script: String,
+
+    /// The state the job is in.
+    pub status: JobStatus,
+
+    /// How many jobs are going to be executed before this one, if the job
+    /// is still queued.
+    pub queue_position: Option<usize>,
 }
@@ -18,9 +18,10 @@
 use std::hash::Hash;
 use std::sync::Arc;
 use std::fmt::Debug;
+use std::time::Duration;
 
 use super::prelude::*;
-use super::structs::HealthDetails;
+use super::structs::{HealthDetails, JobDetails};
 
 
 /// This trait represents a script that can be run by Fisher.
@@ -32,9 +33,41 @@ pub trait ScriptTrait {
     /// the same between calls to the same script.
     fn id(&self) -> Self::Id;
 
+    /// This method returns the name of the script.
+    fn name(&self) -> &str;
+
     /// This method returns if multiple instances of the script can be safely
     /// run in parallel.
     fn can_be_parallel(&self) -> bool;
+
+    /// This method returns how many jobs of the script can run at the same
+    /// time, if there is a limit.
+    fn max_parallel(&self) -> Option<usize> {
+        if self.can_be_parallel() {
+            None
+        } else {
+            Some(1)
+        }
+    }
+
+    /// This method returns how many jobs of the script can wait in the
+    /// queue at the same time, if there is a limit.
+    fn max_queued(&self) -> Option<usize> {
+        None
+    }
+
+    /// This method returns how long the jobs of the script wait in the queue
+    /// before being executed, giving newer jobs the chance to replace them.
+    fn debounce(&self) -> Option<Duration> {
+        None
+    }
+
+    /// This method returns the name of the concurrency group the script
+    /// belongs to, if any. The number of jobs of the same group running at
+    /// the same time is limited.
+    fn group(&self) -> Option<&str> {
+        None
+    }
 }
 
 
@@ -77,7 +110,7 @@ pub trait JobTrait<S: ScriptTrait> {
     type Context: Debug + Send + Sync;
 
     /// The output that will be returned by the job.
-    type Output: Clone + Send + Sync;
+    type Output: JobOutputTrait + Clone + Send + Sync;
 
     /// Execute the job and return the output of it.
     fn execute(&self, ctx: &Self::Context) -> Result<Self::Output>;
@@ -87,13 +120,34 @@ pub trait JobTrait<S: ScriptTrait> {
 
     /// Get the name of the underlying script.
     fn script_name(&self) -> &str;
+
+    /// Get the ID of the request which caused this job, if there is one.
+    fn request_id(&self) -> Option<&str>;
+
+    /// Get the key used to coalesce this job with the other ones of the same
+    /// script: a newer job replaces the older queued ones with the same key.
+    /// Jobs without a key are never coalesced.
+    fn coalesce_key(&self) -> Option<String> {
+        None
+    }
+}
+
+
+/// This trait represents the output of a Job processed by Fisher.
+pub trait JobOutputTrait {
+    /// Return if the job completed successfully.
+    fn success(&self) -> bool;
 }
 
 
 /// This trait represents the API of the processor
 pub trait ProcessorApiTrait<S: ScriptsRepositoryTrait>: Send {
-    /// Queue a new job into the processor.
-    fn queue(&self, job: S::Job, priority: isize) -> Result<()>;
+    /// Queue a new job into the processor, returning its details. The job
+    /// is refused if its script has too many jobs queued already.
+    fn queue(&self, job: S::Job, priority: isize) -> Result<JobDetails>;
+
+    /// Get the details of a job queued, running or recently completed.
+    fn job_details(&self, id: &str) -> Result<Option<JobDetails>>;
 
     /// Get some insights about the health of the processor.
     fn health_details(&self) -> Result<HealthDetails>;
@@ -0,0 +1,126 @@
+// Copyright (C) 2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! This module contains the timer queueing the jobs of the scripts using the
+//! `Cron` provider when their schedule matches.
+
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use common::logging;
+use common::prelude::*;
+use scripts::Repository;
+use utils::UtcDate;
+
+
+/// A thread checking every minute if some scripts are scheduled to run.
+#[derive(Debug)]
+pub struct CronTimer {
+    stop: mpsc::Sender<()>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl CronTimer {
+    pub fn new<A>(hooks: Arc<Repository>, processor: A) -> Self
+    where
+        A: ProcessorApiTrait<Repository> + 'static,
+    {
+        let (stop_send, stop_recv) = mpsc::channel();
+
+        let handle = thread::spawn(move || {
+            let mut next = next_minute(SystemTime::now());
+            loop {
+                let wait = next
+                    .duration_since(SystemTime::now())
+                    .unwrap_or_default();
+                match stop_recv.recv_timeout(wait) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    _ => break,
+                }
+
+                // The timeout might expire slightly before the minute starts
+                let now = SystemTime::now();
+                if now < next {
+                    continue;
+                }
+
+                queue_jobs(&hooks, &processor, &UtcDate::new(next));
+
+                // Minutes missed while the system was suspended are skipped
+                next = next_minute(now);
+            }
+        });
+
+        CronTimer {
+            stop: stop_send,
+            handle,
+        }
+    }
+
+    pub fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.handle.join();
+    }
+}
+
+
+fn next_minute(time: SystemTime) -> SystemTime {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    UNIX_EPOCH + Duration::from_secs((secs / 60 + 1) * 60)
+}
+
+
+fn queue_jobs<A>(hooks: &Repository, processor: &A, time: &UtcDate)
+where
+    A: ProcessorApiTrait<Repository>,
+{
+    for job in hooks.cron_jobs(time) {
+        let script = job.script_name().to_string();
+        let priority = job.priority();
+
+        if let Err(error) = processor.queue(job, priority) {
+            logging::warning("scheduled job rejected")
+                .field("script", script)
+                .field("error", error)
+                .log();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::next_minute;
+
+    #[test]
+    fn test_next_minute() {
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+
+        assert_eq!(next_minute(at(0)), at(60));
+        assert_eq!(next_minute(at(59)), at(60));
+        assert_eq!(next_minute(at(60)), at(120));
+        assert_eq!(
+            next_minute(at(1_508_295_599) + Duration::from_millis(999)),
+            at(1_508_295_600)
+        );
+    }
+}
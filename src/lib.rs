@@ -22,7 +22,10 @@ extern crate error_chain;
 extern crate hyper;
 #[macro_use]
 extern crate lazy_static;
+extern crate libc;
 extern crate nix;
+#[cfg(feature = "tls")]
+extern crate openssl;
 extern crate rand;
 extern crate regex;
 extern crate hmac;
@@ -33,13 +36,13 @@ extern crate serde_derive;
 #[macro_use]
 extern crate serde_json;
 extern crate tempdir;
-extern crate tiny_http;
 extern crate url;
 extern crate users;
 
 #[macro_use]
 mod utils;
 mod app;
+mod cron;
 mod processor;
 mod providers;
 mod requests;
@@ -51,3 +54,4 @@ pub mod common;
 pub use app::Fisher;
 pub use common::config::Config;
 pub use common::errors::*;
+pub use utils::ListenAddr;
@@ -13,11 +13,14 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::collections::HashMap;
 use std::sync::{mpsc, Arc};
+use std::time::Duration;
 
+use common::config::OverflowPolicy;
 use common::prelude::*;
 use common::state::State;
-use common::structs::HealthDetails;
+use common::structs::{HealthDetails, JobDetails};
 
 use processor::scheduler::{Scheduler, SchedulerInput};
 #[cfg(test)]
@@ -105,12 +108,40 @@ impl<S: ScriptsRepositoryTrait> ProcessorApi<S> {
         self.input.send(SchedulerInput::SetThreadsCount(count))?;
         Ok(())
     }
+
+    pub fn set_aging(&self, aging: Option<Duration>) -> Result<()> {
+        self.input.send(SchedulerInput::SetAging(aging))?;
+        Ok(())
+    }
+
+    pub fn set_queue_limit(
+        &self,
+        max_queued: Option<usize>,
+        overflow: OverflowPolicy,
+    ) -> Result<()> {
+        self.input
+            .send(SchedulerInput::SetQueueLimit(max_queued, overflow))?;
+        Ok(())
+    }
+
+    pub fn set_group_limits(&self, limits: HashMap<String, u16>) -> Result<()> {
+        self.input.send(SchedulerInput::SetGroupLimits(limits))?;
+        Ok(())
+    }
 }
 
 impl<S: ScriptsRepositoryTrait> ProcessorApiTrait<S> for ProcessorApi<S> {
-    fn queue(&self, job: Job<S>, priority: isize) -> Result<()> {
-        self.input.send(SchedulerInput::Job(job, priority))?;
-        Ok(())
+    fn queue(&self, job: Job<S>, priority: isize) -> Result<JobDetails> {
+        let (res_send, res_recv) = mpsc::channel();
+        self.input.send(SchedulerInput::Job(job, priority, res_send))?;
+        res_recv.recv()?
+    }
+
+    fn job_details(&self, id: &str) -> Result<Option<JobDetails>> {
+        let (res_send, res_recv) = mpsc::channel();
+        self.input
+            .send(SchedulerInput::JobDetails(id.into(), res_send))?;
+        Ok(res_recv.recv()?)
     }
 
     fn health_details(&self) -> Result<HealthDetails> {
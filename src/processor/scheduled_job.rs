@@ -14,34 +14,83 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::cmp::Ordering;
+use std::time::{Duration, Instant};
 
+use common::logging::Event;
 use common::prelude::*;
 use common::serial::Serial;
+use utils;
 
 use super::types::{Job, JobContext, JobOutput, ScriptId};
 
+/// A limit on how many jobs can run at the same time.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ConcurrencyKey<Id> {
+    /// The jobs of a script limited in how many can run in parallel.
+    Script(Id),
+    /// The jobs of all the scripts in a concurrency group.
+    Group(String),
+}
+
 #[derive(Debug)]
 pub struct ScheduledJob<S: ScriptsRepositoryTrait> {
+    id: String,
     job: Job<S>,
+    coalesce_key: Option<String>,
+    concurrency: Vec<ConcurrencyKey<ScriptId<S>>>,
+    max_parallel: Option<usize>,
+    aging: Option<Duration>,
     priority: isize,
     serial: Serial,
+    queued_at: Instant,
 }
 
 impl<S: ScriptsRepositoryTrait> ScheduledJob<S> {
     pub fn new(job: Job<S>, priority: isize, serial: Serial) -> Self {
         Self {
+            id: utils::random_id(),
+            coalesce_key: job.coalesce_key(),
+            concurrency: Vec::new(),
+            max_parallel: None,
+            aging: None,
             job,
             priority,
             serial,
+            queued_at: Instant::now(),
         }
     }
 
+    /// Limit how many jobs sharing the keys can run at the same time.
+    pub fn with_concurrency(
+        mut self,
+        keys: Vec<ConcurrencyKey<ScriptId<S>>>,
+    ) -> Self {
+        self.concurrency = keys;
+        self
+    }
+
+    /// Limit how many jobs of the same script can run at the same time.
+    pub fn with_max_parallel(mut self, max_parallel: Option<usize>) -> Self {
+        self.max_parallel = max_parallel;
+        self
+    }
+
+    /// Raise the priority of the job by one every time the duration passes
+    /// while it waits to be executed.
+    pub fn set_aging(&mut self, aging: Option<Duration>) {
+        self.aging = aging;
+    }
+
     pub fn execute(&self, ctx: &JobContext<S>) -> Result<JobOutput<S>> {
         self.job.execute(ctx).chain_err(|| {
             ErrorKind::ScriptExecutionFailed(self.hook_name().into())
         })
     }
 
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
     pub fn hook_id(&self) -> ScriptId<S> {
         self.job.script_id()
     }
@@ -49,11 +98,62 @@ impl<S: ScriptsRepositoryTrait> ScheduledJob<S> {
     pub fn hook_name(&self) -> &str {
         self.job.script_name()
     }
+
+    pub fn request_id(&self) -> Option<&str> {
+        self.job.request_id()
+    }
+
+    pub fn concurrency(&self) -> &[ConcurrencyKey<ScriptId<S>>] {
+        &self.concurrency
+    }
+
+    pub fn max_parallel(&self) -> Option<usize> {
+        self.max_parallel
+    }
+
+    /// Check if this job should be replaced by the newer one.
+    pub fn superseded_by(&self, newer: &ScheduledJob<S>) -> bool {
+        self.hook_id() == newer.hook_id()
+            && self.coalesce_key.is_some()
+            && self.coalesce_key == newer.coalesce_key
+    }
+
+    /// Attach the details of this job to a log event.
+    pub fn annotate(&self, event: Event) -> Event {
+        let event = event
+            .field("job_id", self.id.as_str())
+            .field("script", self.hook_name());
+        if let Some(id) = self.request_id() {
+            event.field("request_id", id)
+        } else {
+            event
+        }
+    }
+
+    pub fn queued_at(&self) -> Instant {
+        self.queued_at
+    }
 }
 
 impl<S: ScriptsRepositoryTrait> Ord for ScheduledJob<S> {
     fn cmp(&self, other: &ScheduledJob<S>) -> Ordering {
-        let priority_ord = self.priority.cmp(&other.priority);
+        let priority_ord = match self.aging {
+            // All the jobs age at the same rate, so comparing the priorities
+            // at the time they were queued is enough to know which one is
+            // higher, at any time
+            Some(aging) => {
+                let waited_more = if self.queued_at <= other.queued_at {
+                    (other.queued_at - self.queued_at).as_nanos() as i128
+                } else {
+                    -((self.queued_at - other.queued_at).as_nanos() as i128)
+                };
+                let priority_diff = (self.priority as i128
+                    - other.priority as i128)
+                    * aging.as_nanos() as i128;
+                (priority_diff + waited_more).cmp(&0)
+            }
+            None => self.priority.cmp(&other.priority),
+        };
 
         if priority_ord == Ordering::Equal {
             self.serial.cmp(&other.serial).reverse()
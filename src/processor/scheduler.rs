@@ -13,25 +13,43 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::collections::{BTreeMap, BinaryHeap, HashMap, VecDeque};
+use std::mem;
 use std::sync::{mpsc, Arc, RwLock};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
+use common::config::OverflowPolicy;
+use common::logging;
 use common::prelude::*;
 use common::serial::Serial;
 use common::state::{State, UniqueId};
-use common::structs::HealthDetails;
+use common::structs::{HealthDetails, JobDetails, JobStatus};
 
-use super::scheduled_job::ScheduledJob;
+use super::scheduled_job::{ConcurrencyKey, ScheduledJob};
 use super::thread::{ProcessResult, Thread, ThreadCompleter};
 use super::types::{Job, JobContext, JobOutput, ScriptId};
 
 const STATUS_EVENTS_PRIORITY: isize = 1000;
 
+/// How many completed jobs are remembered, to report their status.
+const FINISHED_JOBS_HISTORY: usize = 1000;
+
+/// The jobs sharing a concurrency key with the ones which can't run.
+type Waiting<S> = HashMap<
+    ConcurrencyKey<ScriptId<S>>,
+    BinaryHeap<ScheduledJob<S>>,
+>;
+
+#[derive(Debug)]
+struct RunningJob<S: ScriptsRepositoryTrait> {
+    script: String,
+    concurrency: Vec<ConcurrencyKey<ScriptId<S>>>,
+}
+
 #[cfg(test)]
 #[derive(Debug)]
 pub struct DebugDetails<S: ScriptsRepositoryTrait> {
-    pub waiting: HashMap<ScriptId<S>, usize>,
+    pub waiting: HashMap<ConcurrencyKey<ScriptId<S>>, usize>,
 }
 
 #[cfg(test)]
@@ -40,7 +58,7 @@ impl<S: ScriptsRepositoryTrait> DebugDetails<S> {
         let waiting = scheduler
             .waiting
             .iter()
-            .map(|(key, value)| (*key, value.len()))
+            .map(|(key, value)| (key.clone(), value.len()))
             .collect();
 
         DebugDetails { waiting }
@@ -48,7 +66,8 @@ impl<S: ScriptsRepositoryTrait> DebugDetails<S> {
 }
 
 pub enum SchedulerInput<S: ScriptsRepositoryTrait> {
-    Job(Job<S>, isize),
+    Job(Job<S>, isize, mpsc::Sender<Result<JobDetails>>),
+    JobDetails(String, mpsc::Sender<Option<JobDetails>>),
     HealthStatus(mpsc::Sender<HealthDetails>),
     ProcessOutput(JobOutput<S>),
 
@@ -62,9 +81,12 @@ pub enum SchedulerInput<S: ScriptsRepositoryTrait> {
 
     UpdateContext(JobContext<S>),
     SetThreadsCount(u16),
+    SetGroupLimits(HashMap<String, u16>),
+    SetAging(Option<Duration>),
+    SetQueueLimit(Option<usize>, OverflowPolicy),
 
     StopSignal,
-    JobEnded(ScriptId<S>, ThreadCompleter),
+    JobEnded(String, bool, ThreadCompleter),
 }
 
 #[derive(Debug)]
@@ -77,8 +99,17 @@ pub struct Scheduler<S: ScriptsRepositoryTrait + 'static> {
     locked: bool,
     should_stop: bool,
     queue: BinaryHeap<ScheduledJob<S>>,
-    waiting: HashMap<ScriptId<S>, BinaryHeap<ScheduledJob<S>>>,
+    waiting: Waiting<S>,
+    delayed: Vec<(Instant, ScheduledJob<S>)>,
+    group_limits: HashMap<String, u16>,
+    aging: Option<Duration>,
+    max_queued: Option<usize>,
+    overflow: OverflowPolicy,
+    rejected_jobs: u64,
+    dropped_jobs: u64,
     threads: HashMap<UniqueId, Thread<S>>,
+    running: HashMap<String, RunningJob<S>>,
+    finished: VecDeque<JobDetails>,
 
     input_send: mpsc::Sender<SchedulerInput<S>>,
     input_recv: mpsc::Receiver<SchedulerInput<S>>,
@@ -95,14 +126,6 @@ impl<S: ScriptsRepositoryTrait> Scheduler<S> {
     ) -> Self {
         let (input_send, input_recv) = mpsc::channel();
 
-        // Populate the waiting HashMap with non-parallel hooks
-        let mut waiting = HashMap::new();
-        for hook in hooks.iter() {
-            if !hook.can_be_parallel() {
-                waiting.insert(hook.id(), BinaryHeap::new());
-            }
-        }
-
         Self {
             max_threads,
             hooks,
@@ -112,8 +135,17 @@ impl<S: ScriptsRepositoryTrait> Scheduler<S> {
             locked: false,
             should_stop: false,
             queue: BinaryHeap::new(),
-            waiting,
+            waiting: HashMap::new(),
+            delayed: Vec::new(),
+            group_limits: HashMap::new(),
+            aging: None,
+            max_queued: None,
+            overflow: OverflowPolicy::Reject,
+            rejected_jobs: 0,
+            dropped_jobs: 0,
             threads: HashMap::with_capacity(max_threads as usize),
+            running: HashMap::new(),
+            finished: VecDeque::new(),
 
             input_send,
             input_recv,
@@ -133,7 +165,7 @@ impl<S: ScriptsRepositoryTrait> Scheduler<S> {
 
         let mut serial = Serial::zero();
         let mut to_schedule = Vec::new();
-        while let Ok(input) = self.input_recv.recv() {
+        while let Some(input) = self.next_input() {
             // Check if the periodic cleanup should be done now
             if self.last_cleanup.elapsed().as_secs() > 30 {
                 self.cleanup_threads();
@@ -143,49 +175,79 @@ impl<S: ScriptsRepositoryTrait> Scheduler<S> {
             }
 
             match input {
-                SchedulerInput::Job(job, priority) => {
-                    self.queue_job(ScheduledJob::new(
+                SchedulerInput::Job(job, priority, return_to) => {
+                    let job = self.with_limits(ScheduledJob::new(
                         job,
                         priority,
                         serial.incr(),
                     ));
+
+                    if self.queue_full(&job) {
+                        let name = job.hook_name().to_string();
+                        logging::warning("job rejected")
+                            .field("script", name.as_str())
+                            .field("reason", "too many jobs queued")
+                            .log();
+
+                        let _ = return_to.send(Err(
+                            ErrorKind::QueueFull(name).into(),
+                        ));
+                        continue;
+                    }
+
+                    if let Err(error) = self.make_room(&job) {
+                        self.rejected_jobs += 1;
+                        logging::warning("job rejected")
+                            .field("script", job.hook_name())
+                            .field("reason", "the jobs queue is full")
+                            .log();
+
+                        let _ = return_to.send(Err(error));
+                        continue;
+                    }
+
+                    job.annotate(logging::info("job queued"))
+                        .field("priority", priority)
+                        .log();
+
+                    let id = job.id().to_string();
+                    self.supersede(&job);
+                    self.schedule_job(job);
                     self.run_jobs();
-                }
 
-                SchedulerInput::HealthStatus(return_to) => {
-                    // Count the busy threads
-                    let busy_threads = self
-                        .threads
-                        .values()
-                        .filter(|thread| thread.busy())
-                        .count();
-
-                    let mut queued_jobs = self.queue.len();
-                    for waiting in self.waiting.values() {
-                        queued_jobs += waiting.len();
+                    if let Some(details) = self.job_details(&id) {
+                        let _ = return_to.send(Ok(details));
                     }
+                }
 
-                    return_to.send(HealthDetails {
-                        queued_jobs,
-                        busy_threads: busy_threads as u16,
-                        max_threads: self.max_threads,
-                    })?;
+                SchedulerInput::JobDetails(id, return_to) => {
+                    let _ = return_to.send(self.job_details(&id));
+                }
+
+                SchedulerInput::HealthStatus(return_to) => {
+                    return_to.send(self.health_details())?;
                 }
 
                 SchedulerInput::ProcessOutput(output) => {
                     if let Some(jobs) = self.hooks.jobs_after_output(output) {
                         for job in jobs {
-                            to_schedule.push(ScheduledJob::new(
+                            let job = self.with_limits(ScheduledJob::new(
                                 job,
                                 STATUS_EVENTS_PRIORITY,
                                 serial.incr(),
                             ));
+                            job.annotate(logging::info("follow-up job queued"))
+                                .field("priority", STATUS_EVENTS_PRIORITY)
+                                .log();
+
+                            to_schedule.push(job);
                         }
                     }
 
                     // This is a separated step due to mutable borrows
                     for job in to_schedule.drain(..) {
-                        self.queue_job(job);
+                        self.supersede(&job);
+                        self.schedule_job(job);
                     }
 
                     self.run_jobs();
@@ -216,6 +278,33 @@ impl<S: ScriptsRepositoryTrait> Scheduler<S> {
                     *ptr = Arc::new(ctx);
                 }
 
+                SchedulerInput::SetGroupLimits(limits) => {
+                    self.group_limits = limits;
+
+                    // Some waiting jobs might be able to run now: the ones
+                    // which still can't are going to wait again
+                    let waiting = mem::take(&mut self.waiting);
+                    for (_, jobs) in waiting {
+                        for job in jobs.into_vec() {
+                            self.queue_job(job);
+                        }
+                    }
+                    self.run_jobs();
+                }
+
+                SchedulerInput::SetAging(aging) => {
+                    self.set_aging(aging);
+                    self.run_jobs();
+                }
+
+                SchedulerInput::SetQueueLimit(max_queued, overflow) => {
+                    // Jobs over a lower limit are kept until the next job is
+                    // queued, which is then rejected or drops all of them
+                    // depending on the overflow policy
+                    self.max_queued = max_queued;
+                    self.overflow = overflow;
+                }
+
                 SchedulerInput::SetThreadsCount(max) => {
                     self.max_threads = max;
 
@@ -230,22 +319,19 @@ impl<S: ScriptsRepositoryTrait> Scheduler<S> {
                     }
                 }
 
-                SchedulerInput::JobEnded(hook_id, completer) => {
+                SchedulerInput::JobEnded(id, success, completer) => {
                     completer.manual_complete();
+                    let released = self.record_finished(id, success);
 
                     // Cleanup threads if there are more than enough
                     if self.threads.len() > self.max_threads as usize {
                         self.cleanup_threads();
                     }
 
-                    // Put the highest-priority waiting job for this hook
-                    // back in the queue
-                    let mut push_back = None;
-                    if let Some(waiting) = self.waiting.get_mut(&hook_id) {
-                        push_back = waiting.pop();
-                    }
-                    if let Some(job) = push_back {
-                        self.queue_job(job);
+                    // Put the highest-priority jobs waiting for the
+                    // concurrency keys of the ended job back in the queue
+                    for key in &released {
+                        self.release_waiting(key);
                     }
 
                     self.run_jobs();
@@ -273,29 +359,349 @@ impl<S: ScriptsRepositoryTrait> Scheduler<S> {
         Ok(())
     }
 
+    /// Wait for the next input, releasing the delayed jobs when they're
+    /// ready to be executed.
+    fn next_input(&mut self) -> Option<SchedulerInput<S>> {
+        loop {
+            let next_release = self.delayed.iter().map(|&(at, _)| at).min();
+            let timeout = match next_release {
+                Some(at) => at.saturating_duration_since(Instant::now()),
+                None => return self.input_recv.recv().ok(),
+            };
+
+            let input = match self.input_recv.recv_timeout(timeout) {
+                Ok(input) => Some(input),
+                Err(mpsc::RecvTimeoutError::Timeout) => None,
+                Err(mpsc::RecvTimeoutError::Disconnected) => return None,
+            };
+
+            self.release_delayed();
+            if input.is_some() {
+                return input;
+            }
+        }
+    }
+
+    fn release_delayed(&mut self) {
+        let now = Instant::now();
+        let (ready, delayed): (Vec<_>, Vec<_>) =
+            mem::take(&mut self.delayed)
+                .into_iter()
+                .partition(|&(at, _)| at <= now);
+        self.delayed = delayed;
+
+        if !ready.is_empty() {
+            for (_, job) in ready {
+                self.queue_job(job);
+            }
+            self.run_jobs();
+        }
+    }
+
+    fn health_details(&self) -> HealthDetails {
+        // Count the busy threads
+        let busy_threads = self
+            .threads
+            .values()
+            .filter(|thread| thread.busy())
+            .count();
+
+        let mut queued_jobs = 0;
+        let mut oldest_queued_job = None;
+        for job in self.queued_jobs() {
+            queued_jobs += 1;
+
+            let age = job.queued_at().elapsed().as_secs();
+            if oldest_queued_job.map(|oldest| age > oldest).unwrap_or(true) {
+                oldest_queued_job = Some(age);
+            }
+        }
+
+        let metrics = self.state.metrics();
+        let scripts = self
+            .hooks
+            .iter()
+            .map(|hook| {
+                let name = hook.name().to_string();
+                let health = metrics.script_health(&name);
+                (name, health)
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        HealthDetails {
+            queued_jobs,
+            busy_threads: busy_threads as u16,
+            max_threads: self.max_threads,
+            oldest_queued_job,
+            max_queued: self.max_queued,
+            rejected_jobs: self.rejected_jobs,
+            dropped_jobs: self.dropped_jobs,
+            loaded_scripts: scripts.len(),
+            locked: self.locked,
+            uptime: self.state.uptime().as_secs(),
+            last_reload: self.state.last_reload(),
+            scripts,
+        }
+    }
+
+    fn queued_jobs<'a>(
+        &'a self,
+    ) -> impl Iterator<Item = &'a ScheduledJob<S>> + Clone + 'a {
+        self.queue
+            .iter()
+            .chain(self.waiting.values().flat_map(|waiting| waiting.iter()))
+            .chain(self.delayed.iter().map(|(_, job)| job))
+    }
+
+    fn script(&self, id: ScriptId<S>) -> Option<Arc<S::Script>> {
+        self.hooks.iter().find(|hook| hook.id() == id)
+    }
+
+    /// Check if the script of the job can't have more jobs waiting.
+    fn queue_full(&self, new: &ScheduledJob<S>) -> bool {
+        let script_id = new.hook_id();
+        let max_queued =
+            self.script(script_id).and_then(|hook| hook.max_queued());
+
+        if let Some(max) = max_queued {
+            // Jobs superseded by the new one are going to leave the queue
+            let queued = self
+                .queued_jobs()
+                .filter(|job| job.hook_id() == script_id)
+                .filter(|job| !job.superseded_by(new))
+                .count();
+            queued >= max
+        } else {
+            false
+        }
+    }
+
+    /// Make room in the queue for the new job if it's full, following the
+    /// overflow policy. An error is returned if the job can't be queued.
+    fn make_room(&mut self, new: &ScheduledJob<S>) -> Result<()> {
+        let max = match self.max_queued {
+            Some(max) => max,
+            None => return Ok(()),
+        };
+
+        // Jobs superseded by the new one are going to leave the queue anyway
+        let mut candidates = self
+            .queued_jobs()
+            .filter(|job| !job.superseded_by(new))
+            .collect::<Vec<_>>();
+        if candidates.len() < max {
+            return Ok(());
+        }
+
+        // No job can be dropped to make room if the limit is 0
+        let excess = candidates.len() + 1 - max;
+        if excess > candidates.len() {
+            return Err(ErrorKind::QueueOverflow.into());
+        }
+
+        match self.overflow {
+            OverflowPolicy::Reject => {
+                return Err(ErrorKind::QueueOverflow.into());
+            }
+            OverflowPolicy::DropOldest => {
+                candidates.sort_by_key(|job| job.queued_at());
+            }
+            OverflowPolicy::DropLowestPriority => {
+                candidates.sort();
+
+                // The new job is the one which would be executed last
+                if *candidates[excess - 1] > *new {
+                    return Err(ErrorKind::QueueOverflow.into());
+                }
+            }
+        }
+
+        let ids = candidates[..excess]
+            .iter()
+            .map(|job| job.id().to_string())
+            .collect::<Vec<_>>();
+        let dropped =
+            self.remove_queued(|job| ids.iter().any(|id| id == job.id()));
+
+        for job in dropped {
+            job.annotate(logging::warning("job dropped"))
+                .field("reason", "the jobs queue is full")
+                .log();
+
+            self.dropped_jobs += 1;
+            self.remember(JobDetails {
+                id: job.id().into(),
+                script: job.hook_name().into(),
+                status: JobStatus::Dropped,
+                queue_position: None,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn job_details(&self, id: &str) -> Option<JobDetails> {
+        if let Some(running) = self.running.get(id) {
+            return Some(JobDetails {
+                id: id.into(),
+                script: running.script.clone(),
+                status: JobStatus::Running,
+                queue_position: None,
+            });
+        }
+
+        let queued = self.queued_jobs();
+        if let Some(job) = queued.clone().find(|job| job.id() == id) {
+            // Count the jobs which are going to be executed before this one
+            let position = queued.filter(|other| *other > job).count();
+
+            return Some(JobDetails {
+                id: id.into(),
+                script: job.hook_name().into(),
+                status: JobStatus::Queued,
+                queue_position: Some(position),
+            });
+        }
+
+        self.finished.iter().find(|job| job.id == id).cloned()
+    }
+
+    /// Record the end of a job, returning the concurrency keys it held.
+    fn record_finished(
+        &mut self,
+        id: String,
+        success: bool,
+    ) -> Vec<ConcurrencyKey<ScriptId<S>>> {
+        if let Some(running) = self.running.remove(&id) {
+            self.remember(JobDetails {
+                id,
+                script: running.script,
+                status: if success {
+                    JobStatus::Succeeded
+                } else {
+                    JobStatus::Failed
+                },
+                queue_position: None,
+            });
+            running.concurrency
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn remember(&mut self, details: JobDetails) {
+        if self.finished.len() >= FINISHED_JOBS_HISTORY {
+            self.finished.pop_front();
+        }
+        self.finished.push_back(details);
+    }
+
+    /// Remove the jobs still waiting to be executed which are replaced by
+    /// the newer one.
+    fn supersede(&mut self, newer: &ScheduledJob<S>) {
+        let superseded = self.remove_queued(|job| job.superseded_by(newer));
+
+        for job in superseded {
+            job.annotate(logging::info("job superseded"))
+                .field("superseded_by", newer.id())
+                .log();
+
+            self.remember(JobDetails {
+                id: job.id().into(),
+                script: job.hook_name().into(),
+                status: JobStatus::Superseded,
+                queue_position: None,
+            });
+        }
+    }
+
+    /// Remove the jobs waiting to be executed matching the filter.
+    fn remove_queued<F>(&mut self, filter: F) -> Vec<ScheduledJob<S>>
+    where
+        F: Fn(&ScheduledJob<S>) -> bool,
+    {
+        let mut removed = Vec::new();
+
+        let queue = mem::take(&mut self.queue);
+        for job in queue.into_vec() {
+            if filter(&job) {
+                removed.push(job);
+            } else {
+                self.queue.push(job);
+            }
+        }
+
+        for waiting in self.waiting.values_mut() {
+            let jobs = mem::take(waiting);
+            for job in jobs.into_vec() {
+                if filter(&job) {
+                    removed.push(job);
+                } else {
+                    waiting.push(job);
+                }
+            }
+        }
+
+        let delayed = mem::take(&mut self.delayed);
+        for (at, job) in delayed {
+            if filter(&job) {
+                removed.push(job);
+            } else {
+                self.delayed.push((at, job));
+            }
+        }
+
+        removed
+    }
+
     #[inline]
     fn spawn_thread(&mut self) {
         let ctx_lock = self.jobs_context.clone();
         let input = self.input_send.clone();
+        let state = self.state.clone();
 
         let thread = Thread::new(
             move |job: ScheduledJob<S>, mut completer| {
                 completer.manual_mode();
 
                 let ctx = ctx_lock.read().unwrap().clone();
+                job.annotate(logging::info("job started")).log();
+
+                let started_at = Instant::now();
                 let result = job.execute(&ctx);
+                let took = started_at.elapsed();
+
+                let success = match result {
+                    Ok(ref output) => output.success(),
+                    Err(..) => false,
+                };
+                state.metrics().job_finished(job.hook_name(), success, took);
 
+                let took_ms =
+                    took.as_secs() * 1000 + u64::from(took.subsec_millis());
                 match result {
                     Ok(output) => {
+                        let event = if success {
+                            logging::info("job completed")
+                        } else {
+                            logging::warning("job failed")
+                        };
+                        job.annotate(event).field("duration_ms", took_ms).log();
+
                         input.send(SchedulerInput::ProcessOutput(output))?;
                     }
                     Err(error) => {
-                        error.pretty_print();
+                        job.annotate(error.to_event())
+                            .field("duration_ms", took_ms)
+                            .log();
                     }
                 }
 
-                input
-                    .send(SchedulerInput::JobEnded(job.hook_id(), completer))?;
+                input.send(SchedulerInput::JobEnded(
+                    job.id().into(),
+                    success,
+                    completer,
+                ))?;
 
                 Ok(())
             },
@@ -329,47 +735,9 @@ impl<S: ScriptsRepositoryTrait> Scheduler<S> {
     }
 
     fn cleanup_hooks(&mut self) {
-        // Get a set of all the queued hooks
-        let mut queued = HashSet::with_capacity(self.queue.len());
-        for job in self.queue.iter() {
-            queued.insert(job.hook_id());
-        }
-
-        // Remove old hooks from self.waiting
-        let mut to_remove = Vec::with_capacity(self.waiting.len());
-        for (hook_id, waiting) in self.waiting.iter() {
-            // This hook wasn't deleted
-            if self.hooks.id_exists(&hook_id) {
-                continue;
-            }
-
-            // There are jobs waiting
-            if !waiting.is_empty() {
-                continue;
-            }
-
-            // There are jobs in the queue
-            if queued.contains(&hook_id) {
-                continue;
-            }
-
-            to_remove.push(*hook_id);
-        }
-        for hook_id in &to_remove {
-            let _ = self.waiting.remove(&hook_id);
-        }
-
-        // Add new hooks
-        for hook in self.hooks.iter() {
-            if hook.can_be_parallel() {
-                continue;
-            }
-            if self.waiting.contains_key(&hook.id()) {
-                continue;
-            }
-
-            self.waiting.insert(hook.id(), BinaryHeap::new());
-        }
+        // Forget the concurrency keys without waiting jobs, including the
+        // ones of deleted scripts
+        self.waiting.retain(|_, waiting| !waiting.is_empty());
     }
 
     fn run_jobs(&mut self) {
@@ -382,11 +750,20 @@ impl<S: ScriptsRepositoryTrait> Scheduler<S> {
         // all of them are processed
         'main: loop {
             if let Some(mut job) = self.get_job() {
+                let id = job.id().to_string();
+                let running = RunningJob {
+                    script: job.hook_name().to_string(),
+                    concurrency: job.concurrency().to_vec(),
+                };
+
                 // Try to run the job in a thread
                 for thread in self.threads.values_mut() {
                     match thread.process(job) {
                         ProcessResult::Rejected(j) => job = j,
-                        ProcessResult::Executing => continue 'main,
+                        ProcessResult::Executing => {
+                            self.running.insert(id, running);
+                            continue 'main;
+                        }
                     }
                 }
                 self.queue_job(job);
@@ -395,61 +772,165 @@ impl<S: ScriptsRepositoryTrait> Scheduler<S> {
         }
     }
 
+    /// Queue a new job, delaying it if the script is debounced.
+    fn schedule_job(&mut self, job: ScheduledJob<S>) {
+        let debounce =
+            self.script(job.hook_id()).and_then(|hook| hook.debounce());
+
+        if let Some(debounce) = debounce {
+            self.delayed.push((Instant::now() + debounce, job));
+        } else {
+            self.queue_job(job);
+        }
+    }
+
     fn queue_job(&mut self, job: ScheduledJob<S>) {
-        let hook_id = job.hook_id();
-
-        // Put the job in waiting if it can't be parallel and
-        // it's already running
-        if self.is_running(hook_id) {
-            if let Some(waiting) = self.waiting.get_mut(&hook_id) {
-                waiting.push(job);
-                return;
-            }
+        // Put the job in waiting if too many jobs sharing its concurrency
+        // keys are already running
+        if let Some(key) = self.blocked_by(&job) {
+            self.waiting.entry(key).or_default().push(job);
+            return;
         }
 
         self.queue.push(job);
     }
 
     fn get_job(&mut self) -> Option<ScheduledJob<S>> {
+        while let Some(job) = self.queue.pop() {
+            // Put the job in waiting if too many jobs sharing its
+            // concurrency keys are already running
+            if let Some(key) = self.blocked_by(&job) {
+                self.waiting.entry(key).or_default().push(job);
+                continue;
+            }
+
+            return Some(job);
+        }
+
+        None
+    }
+
+    /// Change the aging policy of all the jobs not executed yet.
+    fn set_aging(&mut self, aging: Option<Duration>) {
+        self.aging = aging;
+
+        // The order of the jobs changes, so the heaps must be rebuilt
+        let queue = mem::take(&mut self.queue);
+        self.queue = with_aging(queue, aging);
+        for waiting in self.waiting.values_mut() {
+            let jobs = mem::take(waiting);
+            *waiting = with_aging(jobs, aging);
+        }
+        for &mut (_, ref mut job) in &mut self.delayed {
+            job.set_aging(aging);
+        }
+    }
+
+    /// Put the highest-priority job waiting for the key back in the queue.
+    /// Jobs still blocked by other keys are moved to their waiting list.
+    fn release_waiting(&mut self, key: &ConcurrencyKey<ScriptId<S>>) {
         loop {
-            if let Some(job) = self.queue.pop() {
-                let hook_id = job.hook_id();
-
-                // Put the job in waiting if it can't be parallel and
-                // it's already running
-                if self.is_running(hook_id) {
-                    if let Some(waiting) = self.waiting.get_mut(&hook_id) {
-                        waiting.push(job);
-                        continue;
-                    }
+            let job = match self.waiting.get_mut(key).and_then(|w| w.pop()) {
+                Some(job) => job,
+                None => break,
+            };
+
+            if let Some(other) = self.blocked_by(&job) {
+                let still_blocked = other == *key;
+                self.waiting.entry(other).or_default().push(job);
+
+                // Avoid looping forever if the key is still saturated
+                if still_blocked {
+                    break;
                 }
-
-                return Some(job);
             } else {
-                return None;
+                self.queue.push(job);
+                break;
             }
         }
     }
 
-    fn is_running(&self, hook: ScriptId<S>) -> bool {
-        for thread in self.threads.values() {
-            if thread.currently_running() == Some(hook) {
-                return true;
-            }
+    /// Attach the aging policy and the concurrency limits of its script to
+    /// the job. The limits are cached in the job, so they survive the script
+    /// being reloaded.
+    fn with_limits(&self, mut job: ScheduledJob<S>) -> ScheduledJob<S> {
+        job.set_aging(self.aging);
+
+        let script = match self.script(job.hook_id()) {
+            Some(script) => script,
+            None => return job,
+        };
+
+        let mut keys = Vec::new();
+        if let Some(group) = script.group() {
+            keys.push(ConcurrencyKey::Group(group.to_string()));
+        }
+        if script.max_parallel().is_some() {
+            keys.push(ConcurrencyKey::Script(job.hook_id()));
+        }
+
+        job.with_concurrency(keys)
+            .with_max_parallel(script.max_parallel())
+    }
+
+    /// Get the first concurrency key preventing the job from running.
+    fn blocked_by(
+        &self,
+        job: &ScheduledJob<S>,
+    ) -> Option<ConcurrencyKey<ScriptId<S>>> {
+        job.concurrency()
+            .iter()
+            .find(|key| {
+                let running = self
+                    .running
+                    .values()
+                    .filter(|running| running.concurrency.contains(key))
+                    .count();
+                running >= self.concurrency_limit(job, key)
+            })
+            .cloned()
+    }
+
+    fn concurrency_limit(
+        &self,
+        job: &ScheduledJob<S>,
+        key: &ConcurrencyKey<ScriptId<S>>,
+    ) -> usize {
+        match *key {
+            ConcurrencyKey::Script(..) => job.max_parallel().unwrap_or(1),
+            ConcurrencyKey::Group(ref name) => self
+                .group_limits
+                .get(name)
+                .map(|limit| *limit as usize)
+                .unwrap_or(1),
         }
+    }
+}
 
-        return false;
+fn with_aging<S: ScriptsRepositoryTrait>(
+    jobs: BinaryHeap<ScheduledJob<S>>,
+    aging: Option<Duration>,
+) -> BinaryHeap<ScheduledJob<S>> {
+    let mut jobs = jobs.into_vec();
+    for job in &mut jobs {
+        job.set_aging(aging);
     }
+    BinaryHeap::from(jobs)
 }
 
 #[cfg(test)]
 mod tests {
-    use std::collections::VecDeque;
+    use std::collections::{HashMap, VecDeque};
     use std::sync::{mpsc, Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
 
+    use common::config::OverflowPolicy;
     use common::prelude::*;
     use common::state::State;
+    use common::structs::JobStatus;
 
+    use super::super::scheduled_job::ConcurrencyKey;
     use super::super::test_utils::*;
     use super::super::Processor;
 
@@ -553,6 +1034,65 @@ mod tests {
         assert_eq!(output.as_str(), "8967452301");
     }
 
+    fn run_starving(aging: Option<Duration>) -> Result<String> {
+        let repo = Repository::<char>::new();
+
+        let (append_send, append_recv) = mpsc::channel();
+        repo.add_script("append", true, move |arg| {
+            append_send.send(arg)?;
+            Ok(())
+        });
+
+        let repo = Arc::new(repo);
+        let processor =
+            Processor::new(1, repo.clone(), (), Arc::new(State::new()))?;
+
+        let api = processor.api();
+        api.set_aging(aging)?;
+
+        // Prevent jobs from being run
+        api.lock()?;
+
+        // Queue a low priority job, while high priority jobs keep coming
+        api.queue(repo.job("append", 'l').unwrap(), 0)?;
+        for _ in 0..8 {
+            thread::sleep(Duration::from_millis(50));
+            api.queue(repo.job("append", 'h').unwrap(), 3)?;
+        }
+
+        // Allow the processor to work
+        api.unlock()?;
+
+        processor.stop()?;
+
+        // Collect the result from the channel
+        let mut output = String::new();
+        while let Ok(part) = append_recv.try_recv() {
+            output.push(part);
+        }
+
+        Ok(output)
+    }
+
+    #[test]
+    fn test_priority_aging() {
+        // Without aging the low priority job runs only when there is
+        // nothing else in the queue
+        let output = run_starving(None).unwrap();
+        assert_eq!(output.as_str(), "hhhhhhhhl");
+
+        // With aging the low priority job overtakes the newer jobs with an
+        // higher priority, since it waited more than three times the aging
+        let output = run_starving(Some(Duration::from_millis(50))).unwrap();
+        assert_eq!(output.len(), 9);
+        let low = output.find('l').unwrap();
+        assert!(low <= 3, "low priority job starved: {}", output);
+
+        // A long aging doesn't change the order of recent jobs
+        let output = run_starving(Some(Duration::from_secs(60))).unwrap();
+        assert_eq!(output.as_str(), "hhhhhhhhl");
+    }
+
     #[test]
     fn test_processor_multiple_threads() {
         let output = run_multiple_append(4, false).unwrap();
@@ -664,6 +1204,464 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_job_details() {
+        test_wrapper(|| {
+            let repo =
+                Repository::<Option<Arc<Mutex<mpsc::Receiver<()>>>>>::new();
+
+            repo.add_script("noop", true, |_| Ok(()));
+            repo.add_script("fail", true, |_| Err("failed".into()));
+            repo.add_script("wait", true, |recv| {
+                recv.unwrap().lock()?.recv()?;
+                Ok(())
+            });
+
+            let repo = Arc::new(repo);
+            let processor =
+                Processor::new(1, repo.clone(), (), Arc::new(State::new()))?;
+            let api = processor.api();
+
+            // The first job is executed right away
+            let (waiting_send, waiting_recv) = mpsc::channel();
+            let wait = api.queue(
+                repo.job("wait", Some(Arc::new(Mutex::new(waiting_recv))))
+                    .unwrap(),
+                0,
+            )?;
+            assert_eq!(wait.script, "wait");
+            assert_eq!(wait.status, JobStatus::Running);
+            assert_eq!(wait.queue_position, None);
+
+            // The other ones are queued behind it
+            let first = api.queue(repo.job("noop", None).unwrap(), 0)?;
+            let second = api.queue(repo.job("fail", None).unwrap(), 0)?;
+            assert_eq!(first.status, JobStatus::Queued);
+            assert_eq!(first.queue_position, Some(0));
+            assert_eq!(second.queue_position, Some(1));
+
+            // Jobs with an higher priority jump the queue
+            let urgent = api.queue(repo.job("noop", None).unwrap(), 10)?;
+            assert_eq!(urgent.queue_position, Some(0));
+            let first = api.job_details(&first.id)?.unwrap();
+            assert_eq!(first.queue_position, Some(1));
+
+            // Complete all the jobs
+            waiting_send.send(())?;
+            while api.job_details(&second.id)?.unwrap().status
+                != JobStatus::Failed
+            {}
+
+            let wait = api.job_details(&wait.id)?.unwrap();
+            assert_eq!(wait.status, JobStatus::Succeeded);
+            assert_eq!(wait.queue_position, None);
+            assert!(api.job_details("missing")?.is_none());
+
+            processor.stop()?;
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_max_queued() {
+        test_wrapper(|| {
+            let repo =
+                Repository::<Option<Arc<Mutex<mpsc::Receiver<()>>>>>::new();
+
+            let limits = ScriptLimits {
+                max_queued: Some(1),
+                ..ScriptLimits::default()
+            };
+            repo.add_limited_script("limited", true, limits, |_| Ok(()));
+            repo.add_script("noop", true, |_| Ok(()));
+            repo.add_script("wait", true, |recv| {
+                recv.unwrap().lock()?.recv()?;
+                Ok(())
+            });
+
+            let repo = Arc::new(repo);
+            let processor =
+                Processor::new(1, repo.clone(), (), Arc::new(State::new()))?;
+            let api = processor.api();
+
+            // Keep the only thread busy
+            let (waiting_send, waiting_recv) = mpsc::channel();
+            api.queue(
+                repo.job("wait", Some(Arc::new(Mutex::new(waiting_recv))))
+                    .unwrap(),
+                0,
+            )?;
+
+            // Only one job of the script can wait in the queue
+            let first = api.queue(repo.job("limited", None).unwrap(), 0)?;
+            assert_eq!(first.status, JobStatus::Queued);
+            let error = api
+                .queue(repo.job("limited", None).unwrap(), 0)
+                .unwrap_err();
+            if let ErrorKind::QueueFull(ref script) = *error.kind() {
+                assert_eq!(script, "limited");
+            } else {
+                panic!("wrong error: {}", error);
+            }
+
+            // Other scripts are not limited
+            api.queue(repo.job("noop", None).unwrap(), 0)?;
+            assert_eq!(api.health_details()?.queued_jobs, 2);
+
+            // The script can be queued again after the job started
+            waiting_send.send(())?;
+            while api.job_details(&first.id)?.unwrap().status
+                == JobStatus::Queued
+            {}
+            api.queue(repo.job("limited", None).unwrap(), 0)?;
+
+            processor.stop()?;
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_queue_overflow() {
+        test_wrapper(|| {
+            let repo =
+                Repository::<Option<Arc<Mutex<mpsc::Receiver<()>>>>>::new();
+            repo.add_script("noop", true, |_| Ok(()));
+            repo.add_script("wait", true, |recv| {
+                recv.unwrap().lock()?.recv()?;
+                Ok(())
+            });
+            let repo = Arc::new(repo);
+
+            let overflow = |policy| -> Result<_> {
+                let processor = Processor::new(
+                    1,
+                    repo.clone(),
+                    (),
+                    Arc::new(State::new()),
+                )?;
+                let api = processor.api();
+                api.set_queue_limit(Some(2), policy)?;
+
+                // Keep the only thread busy
+                let (waiting_send, waiting_recv) = mpsc::channel();
+                api.queue(
+                    repo.job("wait", Some(Arc::new(Mutex::new(waiting_recv))))
+                        .unwrap(),
+                    0,
+                )?;
+
+                let old = api.queue(repo.job("noop", None).unwrap(), 1)?;
+                let low = api.queue(repo.job("noop", None).unwrap(), 0)?;
+                let new = api.queue(repo.job("noop", None).unwrap(), 2);
+
+                // Jobs with the lowest priority are rejected, unless the
+                // oldest jobs are dropped to make room for them
+                let lowest = api.queue(repo.job("noop", None).unwrap(), -1);
+                if policy != OverflowPolicy::DropOldest {
+                    let error = lowest.unwrap_err();
+                    match *error.kind() {
+                        ErrorKind::QueueOverflow => {}
+                        _ => panic!("wrong error: {}", error),
+                    }
+                }
+
+                let details = api.health_details()?;
+                assert_eq!(details.queued_jobs, 2);
+                assert_eq!(details.max_queued, Some(2));
+
+                let status = |id: &str| -> Result<_> {
+                    Ok(api.job_details(id)?.unwrap().status)
+                };
+                let result = (
+                    status(&old.id)?,
+                    status(&low.id)?,
+                    new.is_ok(),
+                    details.rejected_jobs,
+                    details.dropped_jobs,
+                );
+
+                waiting_send.send(())?;
+                processor.stop()?;
+
+                Ok(result)
+            };
+
+            // New jobs are rejected when the queue is full
+            assert_eq!(
+                overflow(OverflowPolicy::Reject)?,
+                (JobStatus::Queued, JobStatus::Queued, false, 2, 0)
+            );
+
+            // The job waiting for the longest time is dropped
+            assert_eq!(
+                overflow(OverflowPolicy::DropOldest)?,
+                (JobStatus::Dropped, JobStatus::Dropped, true, 0, 2)
+            );
+
+            // The job which would be executed last is dropped
+            assert_eq!(
+                overflow(OverflowPolicy::DropLowestPriority)?,
+                (JobStatus::Queued, JobStatus::Dropped, true, 1, 1)
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_coalesce() {
+        test_wrapper(|| {
+            let repo =
+                Repository::<Option<Arc<Mutex<mpsc::Receiver<()>>>>>::new();
+
+            let limits = ScriptLimits {
+                coalesce: true,
+                ..ScriptLimits::default()
+            };
+            repo.add_limited_script("latest", true, limits, |_| Ok(()));
+            repo.add_script("wait", true, |recv| {
+                recv.unwrap().lock()?.recv()?;
+                Ok(())
+            });
+
+            let repo = Arc::new(repo);
+            let processor =
+                Processor::new(1, repo.clone(), (), Arc::new(State::new()))?;
+            let api = processor.api();
+
+            // Keep the only thread busy
+            let (waiting_send, waiting_recv) = mpsc::channel();
+            api.queue(
+                repo.job("wait", Some(Arc::new(Mutex::new(waiting_recv))))
+                    .unwrap(),
+                0,
+            )?;
+
+            // Newer jobs replace the ones still in the queue
+            let old = api.queue(repo.job("latest", None).unwrap(), 0)?;
+            let new = api.queue(repo.job("latest", None).unwrap(), 0)?;
+            assert_eq!(
+                api.job_details(&old.id)?.unwrap().status,
+                JobStatus::Superseded
+            );
+            assert_eq!(new.queue_position, Some(0));
+            assert_eq!(api.health_details()?.queued_jobs, 1);
+
+            // Only the newest job is executed
+            waiting_send.send(())?;
+            while api.job_details(&new.id)?.unwrap().status
+                != JobStatus::Succeeded
+            {}
+            assert_eq!(
+                api.job_details(&old.id)?.unwrap().status,
+                JobStatus::Superseded
+            );
+
+            processor.stop()?;
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_coalesce_max_queued() {
+        test_wrapper(|| {
+            let repo =
+                Repository::<Option<Arc<Mutex<mpsc::Receiver<()>>>>>::new();
+
+            let limits = ScriptLimits {
+                coalesce: true,
+                max_queued: Some(1),
+                ..ScriptLimits::default()
+            };
+            repo.add_limited_script("latest", true, limits, |_| Ok(()));
+            repo.add_script("wait", true, |recv| {
+                recv.unwrap().lock()?.recv()?;
+                Ok(())
+            });
+
+            let repo = Arc::new(repo);
+            let processor =
+                Processor::new(1, repo.clone(), (), Arc::new(State::new()))?;
+            let api = processor.api();
+
+            // Keep the only thread busy
+            let (waiting_send, waiting_recv) = mpsc::channel();
+            api.queue(
+                repo.job("wait", Some(Arc::new(Mutex::new(waiting_recv))))
+                    .unwrap(),
+                0,
+            )?;
+
+            // The jobs replaced by the new one don't count towards the limit
+            let old = api.queue(repo.job("latest", None).unwrap(), 0)?;
+            let new = api.queue(repo.job("latest", None).unwrap(), 0)?;
+            assert_eq!(
+                api.job_details(&old.id)?.unwrap().status,
+                JobStatus::Superseded
+            );
+            assert_eq!(new.status, JobStatus::Queued);
+            assert_eq!(api.health_details()?.queued_jobs, 1);
+
+            waiting_send.send(())?;
+            processor.stop()?;
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_debounce() {
+        test_wrapper(|| {
+            let repo = Repository::<()>::new();
+
+            let limits = ScriptLimits {
+                debounce: Some(Duration::from_millis(200)),
+                ..ScriptLimits::default()
+            };
+            repo.add_limited_script("debounced", true, limits, |_| Ok(()));
+
+            let repo = Arc::new(repo);
+            let processor =
+                Processor::new(1, repo.clone(), (), Arc::new(State::new()))?;
+            let api = processor.api();
+
+            // The jobs wait even if a thread is available
+            let started = Instant::now();
+            let old = api.queue(repo.job("debounced", ()).unwrap(), 0)?;
+            assert_eq!(old.status, JobStatus::Queued);
+            let new = api.queue(repo.job("debounced", ()).unwrap(), 0)?;
+            assert_eq!(
+                api.job_details(&old.id)?.unwrap().status,
+                JobStatus::Superseded
+            );
+
+            // The newest job is executed after the debounce time
+            while api.job_details(&new.id)?.unwrap().status
+                != JobStatus::Succeeded
+            {}
+            assert!(started.elapsed() >= Duration::from_millis(200));
+            assert_eq!(api.health_details()?.queued_jobs, 0);
+
+            processor.stop()?;
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_max_parallel() {
+        test_wrapper(|| {
+            let repo = Repository::<Arc<Mutex<mpsc::Receiver<()>>>>::new();
+
+            let limits = ScriptLimits {
+                max_parallel: Some(2),
+                ..ScriptLimits::default()
+            };
+            repo.add_limited_script("build", true, limits, |recv| {
+                recv.lock()?.recv()?;
+                Ok(())
+            });
+
+            let repo = Arc::new(repo);
+            let processor =
+                Processor::new(4, repo.clone(), (), Arc::new(State::new()))?;
+            let api = processor.api();
+
+            let mut waiters = VecDeque::new();
+            for _ in 0..3 {
+                let (unlock_send, unlock_recv) = mpsc::channel();
+                api.queue(
+                    repo.job("build", Arc::new(Mutex::new(unlock_recv)))
+                        .unwrap(),
+                    0,
+                )?;
+                waiters.push_back(unlock_send);
+            }
+
+            // Only two jobs run, even if there are idle threads
+            while api.health_details()?.busy_threads != 2 {}
+            assert_eq!(api.health_details()?.queued_jobs, 1);
+
+            // Ending a job starts the waiting one
+            waiters.pop_front().unwrap().send(())?;
+            while api.health_details()?.queued_jobs != 0 {}
+            assert_eq!(api.health_details()?.busy_threads, 2);
+
+            for waiter in waiters {
+                waiter.send(())?;
+            }
+            processor.stop()?;
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_concurrency_groups() {
+        test_wrapper(|| {
+            let repo = Repository::<Arc<Mutex<mpsc::Receiver<()>>>>::new();
+
+            let limits = ScriptLimits {
+                group: Some("database".into()),
+                ..ScriptLimits::default()
+            };
+            for name in &["migrate", "backup"] {
+                repo.add_limited_script(name, true, limits.clone(), |recv| {
+                    recv.lock()?.recv()?;
+                    Ok(())
+                });
+            }
+            repo.add_script("other", true, |recv| {
+                recv.lock()?.recv()?;
+                Ok(())
+            });
+
+            let repo = Arc::new(repo);
+            let processor =
+                Processor::new(4, repo.clone(), (), Arc::new(State::new()))?;
+            let api = processor.api();
+
+            let mut waiters = Vec::new();
+            for name in &["migrate", "backup", "migrate", "other"] {
+                let (unlock_send, unlock_recv) = mpsc::channel();
+                api.queue(
+                    repo.job(name, Arc::new(Mutex::new(unlock_recv)))
+                        .unwrap(),
+                    0,
+                )?;
+                waiters.push(unlock_send);
+            }
+
+            // Only one job of the group runs, without blocking the others
+            while api.health_details()?.busy_threads != 2 {}
+            assert_eq!(api.health_details()?.queued_jobs, 2);
+            let group = ConcurrencyKey::Group("database".into());
+            assert_eq!(api.debug_details()?.waiting.get(&group), Some(&2));
+
+            // Raising the limit of the group starts the waiting jobs
+            let mut group_limits = HashMap::new();
+            group_limits.insert("database".into(), 2);
+            api.set_group_limits(group_limits)?;
+            while api.health_details()?.busy_threads != 3 {}
+            assert_eq!(api.health_details()?.queued_jobs, 1);
+
+            // Ending a job of the group starts the last one
+            waiters[0].send(())?;
+            while api.health_details()?.queued_jobs != 0 {}
+            assert_eq!(api.health_details()?.busy_threads, 3);
+
+            for waiter in &waiters[1..] {
+                waiter.send(())?;
+            }
+            processor.stop()?;
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn test_cleanup_hooks() {
         test_wrapper(|| {
@@ -691,7 +1689,8 @@ mod tests {
                 waitings.push_back(unlock_send);
             }
 
-            let old_hook_id = repo.script_id_of("wait").unwrap();
+            let old_hook_id =
+                ConcurrencyKey::Script(repo.script_id_of("wait").unwrap());
 
             let debug = api.debug_details()?;
             assert_eq!(debug.waiting.get(&old_hook_id), Some(&9));
@@ -710,7 +1709,8 @@ mod tests {
             // Reload the scripts
             repo.recreate_scripts();
 
-            let new_hook_id = repo.script_id_of("wait").unwrap();
+            let new_hook_id =
+                ConcurrencyKey::Script(repo.script_id_of("wait").unwrap());
             assert!(new_hook_id != old_hook_id);
 
             // The new hook id shouldn't be present yet
@@ -721,10 +1721,10 @@ mod tests {
             // Execute a first cleanup
             api.cleanup()?;
 
-            // Now the new hook id should be present, but with no hooks
+            // The new hook id is only added when its jobs have to wait
             let debug = api.debug_details()?;
             assert_eq!(debug.waiting.get(&old_hook_id), Some(&4));
-            assert_eq!(debug.waiting.get(&new_hook_id), Some(&0));
+            assert_eq!(debug.waiting.get(&new_hook_id), None);
 
             for waiting in waitings.drain(..) {
                 waiting.send(())?;
@@ -739,7 +1739,7 @@ mod tests {
             // Now the old hook id should be gone
             let debug = api.debug_details()?;
             assert_eq!(debug.waiting.get(&old_hook_id), None);
-            assert_eq!(debug.waiting.get(&new_hook_id), Some(&0));
+            assert_eq!(debug.waiting.get(&new_hook_id), None);
 
             processor.stop()?;
 
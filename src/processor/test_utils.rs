@@ -17,14 +17,27 @@ use std::collections::{HashMap, VecDeque};
 use std::fmt::{self, Debug};
 use std::sync::{Arc, Mutex, RwLock};
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 
 use common::prelude::*;
 
 
+/// Optional limits applied by the processor to the jobs of a script.
+#[derive(Debug, Clone, Default)]
+pub struct ScriptLimits {
+    pub max_queued: Option<usize>,
+    pub coalesce: bool,
+    pub debounce: Option<Duration>,
+    pub group: Option<String>,
+    pub max_parallel: Option<usize>,
+}
+
+
 pub struct Script<I: Send + Sync + Debug + Clone> {
     id: usize,
     name: String,
     can_be_parallel: bool,
+    limits: ScriptLimits,
     func: Arc<Mutex<Box<Fn(I) -> Result<()> + Send>>>,
 }
 
@@ -35,9 +48,33 @@ impl<I: Send + Sync + Debug + Clone> ScriptTrait for Script<I> {
         self.id
     }
 
+    fn name(&self) -> &str {
+        &self.name
+    }
+
     fn can_be_parallel(&self) -> bool {
         self.can_be_parallel
     }
+
+    fn max_parallel(&self) -> Option<usize> {
+        if self.can_be_parallel {
+            self.limits.max_parallel
+        } else {
+            Some(1)
+        }
+    }
+
+    fn max_queued(&self) -> Option<usize> {
+        self.limits.max_queued
+    }
+
+    fn debounce(&self) -> Option<Duration> {
+        self.limits.debounce
+    }
+
+    fn group(&self) -> Option<&str> {
+        self.limits.group.as_deref()
+    }
 }
 
 impl<I: Send + Sync + Debug + Clone> Debug for Script<I> {
@@ -74,6 +111,26 @@ impl<I: Send + Sync + Debug + Clone> JobTrait<Script<I>> for Job<I> {
     fn script_name(&self) -> &str {
         &self.script.name
     }
+
+    fn request_id(&self) -> Option<&str> {
+        None
+    }
+
+    fn coalesce_key(&self) -> Option<String> {
+        let limits = &self.script.limits;
+        if limits.coalesce || limits.debounce.is_some() {
+            Some(String::new())
+        } else {
+            None
+        }
+    }
+}
+
+
+impl JobOutputTrait for () {
+    fn success(&self) -> bool {
+        true
+    }
 }
 
 
@@ -97,6 +154,16 @@ impl<I: Send + Sync + Debug + Clone> Repository<I> {
         name: &str,
         parallel: bool,
         func: F,
+    ) {
+        self.add_limited_script(name, parallel, ScriptLimits::default(), func);
+    }
+
+    pub fn add_limited_script<F: Fn(I) -> Result<()> + 'static + Send>(
+        &self,
+        name: &str,
+        parallel: bool,
+        limits: ScriptLimits,
+        func: F,
     ) {
         self.ids
             .write()
@@ -108,6 +175,7 @@ impl<I: Send + Sync + Debug + Clone> Repository<I> {
                 id: self.last_id.fetch_add(1, Ordering::SeqCst),
                 name: name.to_string(),
                 can_be_parallel: parallel,
+                limits,
                 func: Arc::new(Mutex::new(Box::new(func))),
             }),
         );
@@ -138,7 +206,12 @@ impl<I: Send + Sync + Debug + Clone> Repository<I> {
         self.scripts.write().unwrap().clear();
 
         for script in scripts.drain(..) {
-            self.add_script(&script.name, script.can_be_parallel, |_| Ok(()));
+            self.add_limited_script(
+                &script.name,
+                script.can_be_parallel,
+                script.limits.clone(),
+                |_| Ok(()),
+            );
         }
     }
 }
@@ -116,7 +116,7 @@ impl<S: ScriptsRepositoryTrait> Thread<S> {
             );
 
             if let Err(error) = result {
-                error.pretty_print();
+                error.log();
             }
         });
 
@@ -207,6 +207,7 @@ impl<S: ScriptsRepositoryTrait> Thread<S> {
         self.id
     }
 
+    #[cfg(test)]
     pub fn currently_running(&self) -> Option<ScriptId<S>> {
         if self.busy.load(Ordering::SeqCst) {
             self.last_running_id
@@ -0,0 +1,183 @@
+// Copyright (C) 2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use serde_json;
+
+use providers::prelude::*;
+use utils::{self, CronSchedule, UtcDate};
+
+
+/// A run of a script triggered by its schedule.
+#[derive(Debug, Clone)]
+pub struct CronEvent {
+    id: String,
+    schedule: String,
+    time: UtcDate,
+}
+
+impl CronEvent {
+    pub fn new(schedule: &CronSchedule, time: UtcDate) -> Self {
+        CronEvent {
+            id: utils::random_id(),
+            schedule: schedule.as_str().into(),
+            time,
+        }
+    }
+
+    #[inline]
+    pub fn request_id(&self) -> &str {
+        &self.id
+    }
+
+    #[inline]
+    pub fn schedule(&self) -> &str {
+        &self.schedule
+    }
+
+    #[inline]
+    pub fn time(&self) -> &UtcDate {
+        &self.time
+    }
+}
+
+
+#[derive(Debug, Deserialize)]
+struct CronConfig {
+    schedule: String,
+}
+
+
+#[derive(Debug)]
+pub struct CronProvider {
+    schedule: CronSchedule,
+}
+
+impl CronProvider {
+    #[inline]
+    pub fn schedule(&self) -> &CronSchedule {
+        &self.schedule
+    }
+}
+
+impl ProviderTrait for CronProvider {
+    fn new(config: &str) -> Result<Self> {
+        let config: CronConfig = serde_json::from_str(config)?;
+        Ok(CronProvider {
+            schedule: CronSchedule::parse(&config.schedule)?,
+        })
+    }
+
+    fn validate(&self, request: &Request) -> RequestType {
+        let req = if let Request::Cron(ref inner) = *request {
+            inner
+        } else {
+            return RequestType::Invalid(InvalidReason::WrongRequestKind);
+        };
+
+        // The run must be caused by this schedule
+        if req.schedule() != self.schedule.as_str() {
+            return RequestType::Invalid(
+                InvalidReason::EventNotAllowed(req.schedule().into()),
+            );
+        }
+
+        RequestType::ExecuteHook
+    }
+
+    fn build_env(&self, req: &Request, b: &mut EnvBuilder) -> Result<()> {
+        if let Request::Cron(ref inner) = *req {
+            b.add_env("SCHEDULE", inner.schedule());
+            b.add_env("TIME", inner.time().rfc3339());
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use providers::ProviderTrait;
+    use requests::{InvalidReason, Request, RequestType};
+    use scripts::EnvBuilder;
+    use utils::testing::*;
+    use utils::{CronSchedule, UtcDate};
+
+    use super::{CronEvent, CronProvider};
+
+    fn event(schedule: &str) -> Request {
+        let time =
+            UtcDate::new(UNIX_EPOCH + Duration::from_secs(1_508_295_600));
+        Request::Cron(CronEvent::new(
+            &CronSchedule::parse(schedule).unwrap(),
+            time,
+        ))
+    }
+
+    #[test]
+    fn test_new() {
+        for right in &[
+            r#"{"schedule": "0 3 * * *"}"#,
+            r#"{"schedule": "@hourly"}"#,
+        ] {
+            assert!(CronProvider::new(right).is_ok(), "{}", right);
+        }
+
+        for wrong in &[
+            r#"{}"#,
+            r#"{"schedule": 3}"#,
+            r#"{"schedule": "3am"}"#,
+            r#"{"schedule": "0 3 * *"}"#,
+        ] {
+            assert!(CronProvider::new(wrong).is_err(), "{}", wrong);
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let provider = CronProvider::new(r#"{"schedule": "0 3 * * *"}"#)
+            .unwrap();
+
+        assert_eq!(
+            provider.validate(&event("0 3 * * *")),
+            RequestType::ExecuteHook
+        );
+        assert_eq!(
+            provider.validate(&event("@daily")),
+            RequestType::Invalid(
+                InvalidReason::EventNotAllowed("@daily".into()),
+            )
+        );
+        assert_eq!(
+            provider.validate(&dummy_web_request().into()),
+            RequestType::Invalid(InvalidReason::WrongRequestKind)
+        );
+    }
+
+    #[test]
+    fn test_build_env() {
+        let provider = CronProvider::new(r#"{"schedule": "0 3 * * *"}"#)
+            .unwrap();
+
+        let mut b = EnvBuilder::dummy();
+        provider.build_env(&event("0 3 * * *"), &mut b).unwrap();
+
+        assert_eq!(b.dummy_data().env, hashmap! {
+            "SCHEDULE".into() => "0 3 * * *".into(),
+            "TIME".into() => "2017-10-18T03:00:00.000Z".into(),
+        });
+    }
+}
@@ -83,13 +83,15 @@ impl ProviderTrait for GitHubProvider {
         if let Request::Web(ref inner) = *request {
             req = inner;
         } else {
-            return RequestType::Invalid;
+            return RequestType::Invalid(InvalidReason::WrongRequestKind);
         }
 
         // Check if the correct headers are present
         for header in GITHUB_HEADERS.iter() {
             if !req.headers.contains_key(*header) {
-                return RequestType::Invalid;
+                return RequestType::Invalid(
+                    InvalidReason::MissingHeader(header),
+                );
             }
         }
 
@@ -98,26 +100,30 @@ impl ProviderTrait for GitHubProvider {
             // Check if the signature is valid
             let signature = &req.headers["X-Hub-Signature"];
             if !verify_signature(secret, &req.body, signature) {
-                return RequestType::Invalid;
+                return RequestType::Invalid(InvalidReason::BadSignature);
             }
         }
 
         // Check if the event is valid
         let event = &req.headers["X-GitHub-Event"];
-        if !(GITHUB_EVENTS.contains(&event.as_ref()) || *event == "ping") {
-            return RequestType::Invalid;
+        if !(GITHUB_EVENTS.contains(&event) || event == "ping") {
+            return RequestType::Invalid(
+                InvalidReason::UnknownEvent(event.to_string()),
+            );
         }
 
         // Check if the event should be accepted
         if let Some(ref events) = self.events {
-            if !(events.contains(event) || *event == "ping") {
-                return RequestType::Invalid;
+            if !(events.iter().any(|e| e == event) || event == "ping") {
+                return RequestType::Invalid(
+                    InvalidReason::EventNotAllowed(event.to_string()),
+                );
             }
         }
 
         // Check if the JSON in the body is valid
-        if serde_json::from_str::<serde_json::Value>(&req.body).is_err() {
-            return RequestType::Invalid;
+        if serde_json::from_slice::<serde_json::Value>(&req.body).is_err() {
+            return RequestType::Invalid(InvalidReason::InvalidJson);
         }
 
         // The "ping" event is a ping (doh!)
@@ -142,9 +148,12 @@ impl ProviderTrait for GitHubProvider {
 
         // Add specific environment variables for the `push` event
         let event = &req.headers["X-GitHub-Event"];
-        if self.events.as_ref().and_then(|e| Some(e.contains(event))).unwrap_or(false) {
-            if *event == "push" {
-                let parsed: PushEvent = serde_json::from_str(&req.body)?;
+        let whitelisted = self.events.as_ref()
+            .map(|events| events.iter().any(|e| e == event))
+            .unwrap_or(false);
+        if whitelisted {
+            if event == "push" {
+                let parsed: PushEvent = serde_json::from_slice(&req.body)?;
                 b.add_env("PUSH_REF", parsed.git_ref);
                 b.add_env("PUSH_HEAD", parsed.head_commit.id);
             }
@@ -155,7 +164,7 @@ impl ProviderTrait for GitHubProvider {
 }
 
 
-fn verify_signature(secret: &str, payload: &str, raw_signature: &str) -> bool {
+fn verify_signature(secret: &str, payload: &[u8], raw_signature: &str) -> bool {
     type HmacSha1 = Hmac<sha1::Sha1>;
 
     // The signature must have a =
@@ -188,7 +197,7 @@ fn verify_signature(secret: &str, payload: &str, raw_signature: &str) -> bool {
 
     // Verify the HMAC signature
     let mut mac = HmacSha1::new_varkey(secret.as_bytes()).unwrap();
-    mac.input(payload.as_bytes());
+    mac.input(payload);
     mac.verify(&signature).is_ok()
 }
 
@@ -196,7 +205,7 @@ fn verify_signature(secret: &str, payload: &str, raw_signature: &str) -> bool {
 #[cfg(test)]
 mod tests {
     use utils::testing::*;
-    use requests::RequestType;
+    use requests::{InvalidReason, RequestType};
     use web::WebRequest;
     use providers::ProviderTrait;
     use scripts::EnvBuilder;
@@ -241,19 +250,10 @@ mod tests {
         macro_rules! assert_req_type {
             ($provider:expr, $event:expr, $expected:expr) => {
                 let mut request = dummy_web_request();
-                let _ = request.headers.insert(
-                    "X-GitHub-Event".into(),
-                    $event.to_string(),
-                );
-                let _ = request.headers.insert(
-                    "X-GitHub-Delivery".into(),
-                    "12345".into(),
-                );
-                let _ = request.headers.insert(
-                    "X-Hub-Signature".into(),
-                    "invalid".into(),
-                );
-                request.body = "{}".into();
+                request.headers.append("X-GitHub-Event", $event);
+                request.headers.append("X-GitHub-Delivery", "12345");
+                request.headers.append("X-Hub-Signature", "invalid");
+                request.body = b"{}".to_vec();
 
                 assert_eq!($provider.validate(&request.into()), $expected);
             };
@@ -261,16 +261,57 @@ mod tests {
 
         assert_req_type!(provider, "ping", RequestType::Ping);
         for event in GITHUB_EVENTS.iter() {
-            assert_req_type!(provider, event, RequestType::ExecuteHook);
+            assert_req_type!(provider, *event, RequestType::ExecuteHook);
         }
     }
 
 
+    #[test]
+    fn test_validate_reasons() {
+        fn request(event: &str, signature: &str, body: &str) -> WebRequest {
+            let mut request = dummy_web_request();
+            request.headers.append("X-GitHub-Event", event);
+            request.headers.append("X-GitHub-Delivery", "12345");
+            request.headers.append("X-Hub-Signature", signature);
+            request.body = body.as_bytes().to_vec();
+            request
+        }
+
+        let provider = GitHubProvider::new("{}").unwrap();
+        assert_eq!(
+            provider.validate(&dummy_web_request().into()),
+            RequestType::Invalid(
+                InvalidReason::MissingHeader("X-GitHub-Event"),
+            )
+        );
+        assert_eq!(
+            provider.validate(&request("strange", "", "{}").into()),
+            RequestType::Invalid(InvalidReason::UnknownEvent("strange".into()))
+        );
+        assert_eq!(
+            provider.validate(&request("push", "", "invalid").into()),
+            RequestType::Invalid(InvalidReason::InvalidJson)
+        );
+
+        let provider = GitHubProvider::new(r#"{"events": ["push"]}"#).unwrap();
+        assert_eq!(
+            provider.validate(&request("fork", "", "{}").into()),
+            RequestType::Invalid(InvalidReason::EventNotAllowed("fork".into()))
+        );
+
+        let provider = GitHubProvider::new(r#"{"secret": "secret"}"#).unwrap();
+        assert_eq!(
+            provider.validate(&request("push", "sha1=00", "{}").into()),
+            RequestType::Invalid(InvalidReason::BadSignature)
+        );
+    }
+
+
     #[test]
     fn test_build_env() {
         let mut req = dummy_web_request();
-        req.headers.insert("X-GitHub-Event".into(), "ping".into());
-        req.headers.insert("X-GitHub-Delivery".into(), "12345".into());
+        req.headers.append("X-GitHub-Event", "ping");
+        req.headers.append("X-GitHub-Delivery", "12345");
 
         let provider = GitHubProvider::new("{}").unwrap();
         let mut b = EnvBuilder::dummy();
@@ -287,9 +328,9 @@ mod tests {
     fn dummy_push_event_request(event: &str) -> WebRequest {
         let mut req = dummy_web_request();
 
-        req.headers.insert("X-GitHub-Delivery".into(), "12345".into());
-        req.headers.insert("X-GitHub-Event".into(), event.into());
-        req.body = ::serde_json::to_string(&json!({
+        req.headers.append("X-GitHub-Delivery", "12345");
+        req.headers.append("X-GitHub-Event", event);
+        req.body = ::serde_json::to_vec(&json!({
             "ref": "refs/heads/master",
             "head_commit": json!({
                 "id": "deadbeef",
@@ -356,7 +397,7 @@ mod tests {
             "sha1=e75efc0f29bf50c23f99b30b86f7c78fdaf5f11d",
         ] {
             assert!(
-                !verify_signature("secret", "payload", signature),
+                !verify_signature("secret", b"payload", signature),
                 signature.to_string()
             );
         }
@@ -364,8 +405,15 @@ mod tests {
         // This is known to be right
         assert!(verify_signature(
             "secret",
-            "payload",
+            b"payload",
             "sha1=f75efc0f29bf50c23f99b30b86f7c78fdaf5f11d"
         ));
+
+        // Payloads don't need to be valid UTF-8
+        assert!(verify_signature(
+            "secret",
+            b"\xff\xfe{}\x00",
+            "sha1=2313ceded8db55e18269dd220f5fc89f34134012"
+        ));
     }
 }
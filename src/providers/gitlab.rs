@@ -62,13 +62,15 @@ impl ProviderTrait for GitLabProvider {
         if let Request::Web(ref inner) = *request {
             req = inner;
         } else {
-            return RequestType::Invalid;
+            return RequestType::Invalid(InvalidReason::WrongRequestKind);
         }
 
         // Check if the correct headers are provided
         for header in GITLAB_HEADERS.iter() {
             if !req.headers.contains_key(*header) {
-                return RequestType::Invalid;
+                return RequestType::Invalid(
+                    InvalidReason::MissingHeader(header),
+                );
             }
         }
 
@@ -78,26 +80,28 @@ impl ProviderTrait for GitLabProvider {
             if let Some(token) = req.headers.get("X-Gitlab-Token") {
                 // The token must match
                 if token != secret {
-                    return RequestType::Invalid;
+                    return RequestType::Invalid(InvalidReason::WrongSecret);
                 }
             } else {
-                return RequestType::Invalid;
+                return RequestType::Invalid(InvalidReason::MissingSecret);
             }
         }
 
-        let event = normalize_event_name(&*req.headers["X-Gitlab-Event"]);
+        let event = normalize_event_name(&req.headers["X-Gitlab-Event"]);
 
         // Check if the event should be accepted
         if let Some(ref events) = self.events {
             // The event is whitelisted
             if !events.contains(&event.to_string()) {
-                return RequestType::Invalid;
+                return RequestType::Invalid(
+                    InvalidReason::EventNotAllowed(event.to_string()),
+                );
             }
         }
 
         // Check if the JSON body is valid
-        if !serde_json::from_str::<serde_json::Value>(&req.body).is_ok() {
-            return RequestType::Invalid;
+        if !serde_json::from_slice::<serde_json::Value>(&req.body).is_ok() {
+            return RequestType::Invalid(InvalidReason::InvalidJson);
         }
 
         RequestType::ExecuteHook
@@ -113,7 +117,7 @@ impl ProviderTrait for GitLabProvider {
 
         // Get the current event name
         let event_header =
-            normalize_event_name(&*req.headers["X-Gitlab-Event"]);
+            normalize_event_name(&req.headers["X-Gitlab-Event"]);
 
         b.add_env("EVENT", event_header);
 
@@ -137,7 +141,7 @@ fn normalize_event_name(input: &str) -> &str {
 #[cfg(test)]
 mod tests {
     use utils::testing::*;
-    use requests::{Request, RequestType};
+    use requests::{InvalidReason, Request, RequestType};
     use web::WebRequest;
     use providers::ProviderTrait;
     use scripts::EnvBuilder;
@@ -148,9 +152,8 @@ mod tests {
     fn base_request() -> WebRequest {
         let mut base = dummy_web_request();
 
-        base.headers
-            .insert("X-Gitlab-Event".to_string(), "Push Hook".to_string());
-        base.body = r#"{"a": "b"}"#.to_string();
+        base.headers.append("X-Gitlab-Event", "Push Hook");
+        base.body = br#"{"a": "b"}"#.to_vec();
 
         base
     }
@@ -189,10 +192,11 @@ mod tests {
         let provider = GitLabProvider::new("{}").unwrap();
 
         for event in GITLAB_EVENTS.iter() {
-            let mut request = base_request();
+            let mut request = dummy_web_request();
             request
                 .headers
-                .insert("X-Gitlab-Event".to_string(), format!("{} Hook", event));
+                .append("X-Gitlab-Event", format!("{} Hook", event));
+            request.body = b"{}".to_vec();
 
             assert_eq!(
                 provider.validate(&request.into()),
@@ -207,27 +211,31 @@ mod tests {
         let provider = GitLabProvider::new("{}").unwrap();
 
         // Check with a dummy request - missing headers and no json body
+        let missing_header = RequestType::Invalid(
+            InvalidReason::MissingHeader("X-Gitlab-Event"),
+        );
         assert_eq!(
             provider.validate(&dummy_web_request().into()),
-            RequestType::Invalid
+            missing_header
         );
 
         // Check with a request with the headers and no JSON body
         let mut req = dummy_web_request();
-        req.headers
-            .insert("X-Gitlab-Event".to_string(), "Push Hook".to_string());
-        assert_eq!(provider.validate(&req.into()), RequestType::Invalid);
+        req.headers.append("X-Gitlab-Event", "Push Hook");
+        assert_eq!(
+            provider.validate(&req.into()),
+            RequestType::Invalid(InvalidReason::InvalidJson)
+        );
 
         // Check with a request with missing headers and a JSON body
         let mut req = dummy_web_request();
-        req.body = r#"{"a": "b"}"#.to_string();
-        assert_eq!(provider.validate(&req.into()), RequestType::Invalid);
+        req.body = br#"{"a": "b"}"#.to_vec();
+        assert_eq!(provider.validate(&req.into()), missing_header);
 
         // Check with a request with the headers and a JSON body
         let mut req = dummy_web_request();
-        req.headers
-            .insert("X-Gitlab-Event".to_string(), "Push Hook".to_string());
-        req.body = r#"{"a": "b"}"#.to_string();
+        req.headers.append("X-Gitlab-Event", "Push Hook");
+        req.body = br#"{"a": "b"}"#.to_vec();
         assert_eq!(provider.validate(&req.into()), RequestType::ExecuteHook);
     }
 
@@ -246,19 +254,20 @@ mod tests {
         // Check a request without the header
         assert_eq!(
             provider.validate(&base_request().into()),
-            RequestType::Invalid
+            RequestType::Invalid(InvalidReason::MissingSecret)
         );
 
         // Check a request with the header but a wrong token
         let mut req = base_request();
-        req.headers
-            .insert("X-Gitlab-Token".to_string(), "12345".to_string());
-        assert_eq!(provider.validate(&req.into()), RequestType::Invalid);
+        req.headers.append("X-Gitlab-Token", "12345");
+        assert_eq!(
+            provider.validate(&req.into()),
+            RequestType::Invalid(InvalidReason::WrongSecret)
+        );
 
         // Check a request with the header
         let mut req = base_request();
-        req.headers
-            .insert("X-Gitlab-Token".to_string(), "abcde".to_string());
+        req.headers.append("X-Gitlab-Token", "abcde");
         assert_eq!(provider.validate(&req.into()), RequestType::ExecuteHook);
     }
 
@@ -269,10 +278,9 @@ mod tests {
         let provider = GitLabProvider::new(config).unwrap();
 
         fn with_event(name: &str) -> Request {
-            let mut base = base_request();
-            base.body = "{}".to_string();
-            base.headers
-                .insert("X-Gitlab-Event".to_string(), name.to_string());
+            let mut base = dummy_web_request();
+            base.body = b"{}".to_vec();
+            base.headers.append("X-Gitlab-Event", name);
 
             Request::Web(base)
         }
@@ -284,7 +292,7 @@ mod tests {
         );
         assert_eq!(
             provider.validate(&with_event("Build Hook")),
-            RequestType::Invalid
+            RequestType::Invalid(InvalidReason::EventNotAllowed("Build".into()))
         );
 
         // Without a list of allowed events
@@ -307,7 +315,7 @@ mod tests {
     #[test]
     fn test_build_env() {
         let mut req = base_request();
-        req.headers.insert("X-Gitlab-Event".into(), "Push Hook".to_string());
+        req.headers.append("X-Gitlab-Event", "Push Hook");
 
         let provider = GitLabProvider::new("{}").unwrap();
         let mut b = EnvBuilder::dummy();
@@ -13,6 +13,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+mod cron;
 mod status;
 mod standalone;
 mod github;
@@ -23,12 +24,13 @@ pub mod testing;
 
 pub mod prelude {
     pub use providers::ProviderTrait;
-    pub use requests::{Request, RequestType};
+    pub use requests::{InvalidReason, Request, RequestType};
     pub use common::prelude::*;
     pub use scripts::EnvBuilder;
 }
 
 
+pub use self::cron::CronEvent;
 pub use self::status::{StatusEvent, StatusEventKind, StatusProvider};
 
 
@@ -146,6 +148,7 @@ macro_rules! ProviderEnum {
 ProviderEnum! {
     any(test, not(test)) | Standalone => self::standalone::StandaloneProvider,
     any(test, not(test)) | Status => self::status::StatusProvider,
+    any(test, not(test)) | Cron => self::cron::CronProvider,
     any(test, not(test)) | GitHub => self::github::GitHubProvider,
     any(test, not(test)) | GitLab => self::gitlab::GitLabProvider,
     test | Testing => self::testing::TestingProvider
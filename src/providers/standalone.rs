@@ -57,7 +57,7 @@ impl ProviderTrait for StandaloneProvider {
         if let Request::Web(ref inner) = *request {
             req = inner;
         } else {
-            return RequestType::Invalid;
+            return RequestType::Invalid(InvalidReason::WrongRequestKind);
         }
 
         // Check if the secret code is valid
@@ -70,19 +70,21 @@ impl ProviderTrait for StandaloneProvider {
                 found
             } else {
                 // No secret present, abort!
-                return RequestType::Invalid;
+                return RequestType::Invalid(InvalidReason::MissingSecret);
             };
 
             // Abort if the secret doesn't match
             if secret != correct_secret {
-                return RequestType::Invalid;
+                return RequestType::Invalid(InvalidReason::WrongSecret);
             }
         }
 
         // Check if the IP address is allowed
         if let Some(ref allowed) = self.from {
             if !allowed.contains(&req.source) {
-                return RequestType::Invalid;
+                return RequestType::Invalid(
+                    InvalidReason::IpNotAllowed(req.source),
+                );
             }
         }
 
@@ -100,7 +102,7 @@ mod tests {
     use std::collections::HashMap;
 
     use utils::testing::*;
-    use requests::RequestType;
+    use requests::{InvalidReason, RequestType};
     use providers::ProviderTrait;
     use scripts::EnvBuilder;
 
@@ -155,35 +157,37 @@ mod tests {
         // It should not be validate
         assert_eq!(
             p.validate(&dummy_web_request().into()),
-            RequestType::Invalid
+            RequestType::Invalid(InvalidReason::MissingSecret)
         );
 
         // Test a request with the secret param, but the wrong secret key
         // It should not be validated
         let mut req = dummy_web_request();
-        req.params
-            .insert(param_name.to_string(), "12345".to_string());
-        assert_eq!(p.validate(&req.into()), RequestType::Invalid);
+        req.params.append(param_name, "12345");
+        assert_eq!(
+            p.validate(&req.into()),
+            RequestType::Invalid(InvalidReason::WrongSecret)
+        );
 
         // Test a request with the secret param and the correct secret key
         // It should be validated
         let mut req = dummy_web_request();
-        req.params
-            .insert(param_name.to_string(), "abcde".to_string());
+        req.params.append(param_name, "abcde");
         assert_eq!(p.validate(&req.into()), RequestType::ExecuteHook);
 
         // Test a request with the secret header, but the wrong secret key
         // It should not be validated
         let mut req = dummy_web_request();
-        req.headers
-            .insert(header_name.to_string(), "12345".to_string());
-        assert_eq!(p.validate(&req.into()), RequestType::Invalid);
+        req.headers.append(header_name, "12345");
+        assert_eq!(
+            p.validate(&req.into()),
+            RequestType::Invalid(InvalidReason::WrongSecret)
+        );
 
         // Test a request with the secret header and the correct secret key
         // It should be validated
         let mut req = dummy_web_request();
-        req.headers
-            .insert(header_name.to_string(), "abcde".to_string());
+        req.headers.append(header_name, "abcde");
         assert_eq!(p.validate(&req.into()), RequestType::ExecuteHook);
     }
 
@@ -194,7 +198,12 @@ mod tests {
 
         let mut req = dummy_web_request();
         req.source = "127.0.0.1".parse().unwrap();
-        assert_eq!(p.validate(&req.into()), RequestType::Invalid);
+        assert_eq!(
+            p.validate(&req.into()),
+            RequestType::Invalid(
+                InvalidReason::IpNotAllowed("127.0.0.1".parse().unwrap()),
+            )
+        );
 
         for ip in &["192.168.1.1", "10.0.0.1"] {
             let mut req = dummy_web_request();
@@ -45,6 +45,23 @@ impl StatusEvent {
         }
     }
 
+    #[inline]
+    pub fn request_id(&self) -> &str {
+        match *self {
+            StatusEvent::JobCompleted(ref output) |
+            StatusEvent::JobFailed(ref output) => &output.request_id,
+        }
+    }
+
+    /// Get the name of what caused the job the event is about.
+    #[inline]
+    pub fn trigger(&self) -> &str {
+        match *self {
+            StatusEvent::JobCompleted(ref output) |
+            StatusEvent::JobFailed(ref output) => output.request_kind,
+        }
+    }
+
     #[inline]
     pub fn source_ip(&self) -> IpAddr {
         match *self {
@@ -105,17 +122,21 @@ impl ProviderTrait for StatusProvider {
         if let Request::Status(ref inner) = *request {
             req = inner;
         } else {
-            return RequestType::Invalid;
+            return RequestType::Invalid(InvalidReason::WrongRequestKind);
         }
 
         // The hook name must be allowed
         if !self.script_allowed(req.script_name()) {
-            return RequestType::Invalid;
+            return RequestType::Invalid(
+                InvalidReason::ScriptNotAllowed(req.script_name().into()),
+            );
         }
 
         // The event must be allowed
         if !self.events.contains(&req.kind()) {
-            return RequestType::Invalid;
+            return RequestType::Invalid(
+                InvalidReason::EventNotAllowed(req.kind().name().into()),
+            );
         }
 
         RequestType::ExecuteHook
@@ -130,12 +151,14 @@ impl ProviderTrait for StatusProvider {
 
         b.add_env("EVENT", req.kind().name());
         b.add_env("SCRIPT_NAME", req.script_name());
+        b.add_env("TRIGGER", req.trigger());
 
         match *req {
             StatusEvent::JobCompleted(ref out) => {
                 b.add_env("SUCCESS", "1");
                 b.add_env("EXIT_CODE", "0");
                 b.add_env("SIGNAL", "");
+                b.add_env("LIMIT", "");
 
                 write!(b.data_file("stdout")?, "{}", out.stdout)?;
                 write!(b.data_file("stderr")?, "{}", out.stderr)?;
@@ -152,6 +175,7 @@ impl ProviderTrait for StatusProvider {
                 } else {
                     String::with_capacity(0)
                 });
+                b.add_env("LIMIT", out.limit_exceeded.unwrap_or(""));
 
                 write!(b.data_file("stdout")?, "{}", out.stdout)?;
                 write!(b.data_file("stderr")?, "{}", out.stderr)?;
@@ -172,7 +196,7 @@ impl ProviderTrait for StatusProvider {
 #[cfg(test)]
 mod tests {
     use utils::testing::*;
-    use requests::RequestType;
+    use requests::{InvalidReason, RequestType};
     use providers::ProviderTrait;
     use scripts::EnvBuilder;
 
@@ -246,7 +270,9 @@ mod tests {
         assert_validate!(
             &StatusEvent::JobCompleted(dummy_job_output()).into(),
             r#"{"events": ["job-failed"]}"#,
-            RequestType::Invalid
+            RequestType::Invalid(
+                InvalidReason::EventNotAllowed("job-completed".into()),
+            )
         );
 
         // Test with a right allowed event
@@ -260,7 +286,9 @@ mod tests {
         assert_validate!(
             &StatusEvent::JobCompleted(dummy_job_output()).into(),
             r#"{"events": ["job-completed"], "scripts": ["invalid"]}"#,
-            RequestType::Invalid
+            RequestType::Invalid(
+                InvalidReason::ScriptNotAllowed("test".into()),
+            )
         );
 
         // Test with a right allowed hook
@@ -285,9 +313,11 @@ mod tests {
         assert_eq!(b.dummy_data().env, hashmap! {
             "EVENT".into() => "job-completed".into(),
             "SCRIPT_NAME".into() => "test".into(),
+            "TRIGGER".into() => "webhook".into(),
             "SUCCESS".into() => "1".into(),
             "EXIT_CODE".into() => "0".into(),
             "SIGNAL".into() => "".into(),
+            "LIMIT".into() => "".into(),
 
             // File paths
             "STDOUT".into() => "stdout".into(),
@@ -309,7 +339,8 @@ mod tests {
         let mut output = dummy_job_output();
         output.success = false;
         output.exit_code = None;
-        output.signal = Some(9);
+        output.signal = Some(24);
+        output.limit_exceeded = Some("cpu");
 
         let event = StatusEvent::JobFailed(output);
         let mut b = EnvBuilder::dummy();
@@ -318,9 +349,11 @@ mod tests {
         assert_eq!(b.dummy_data().env, hashmap! {
             "EVENT".into() => "job-failed".into(),
             "SCRIPT_NAME".into() => "test".into(),
+            "TRIGGER".into() => "webhook".into(),
             "SUCCESS".into() => "0".into(),
             "EXIT_CODE".into() => "".into(),
-            "SIGNAL".into() => "9".into(),
+            "SIGNAL".into() => "24".into(),
+            "LIMIT".into() => "cpu".into(),
 
             // File paths
             "STDOUT".into() => "stdout".into(),
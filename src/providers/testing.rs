@@ -43,20 +43,22 @@ impl ProviderTrait for TestingProvider {
         if let &Request::Web(ref inner) = request {
             req = inner;
         } else {
-            return RequestType::Invalid;
+            return RequestType::Invalid(InvalidReason::WrongRequestKind);
         }
 
         // If the secret param is provided, validate it
         if let Some(secret) = req.params.get("secret") {
             if secret != "testing" {
-                return RequestType::Invalid;
+                return RequestType::Invalid(InvalidReason::WrongSecret);
             }
         }
 
         // If the ip param is provided, validate it
         if let Some(ip) = req.params.get("ip") {
             if req.source != IpAddr::from_str(ip).unwrap() {
-                return RequestType::Invalid;
+                return RequestType::Invalid(
+                    InvalidReason::IpNotAllowed(req.source),
+                );
             }
         }
 
@@ -107,7 +109,7 @@ mod tests {
     use std::str::FromStr;
 
     use utils::testing::*;
-    use requests::RequestType;
+    use requests::{InvalidReason, RequestType};
     use providers::ProviderTrait;
     use scripts::EnvBuilder;
 
@@ -134,38 +136,42 @@ mod tests {
 
         // With the wrong secret
         let mut req = dummy_web_request();
-        req.params
-            .insert("secret".to_string(), "wrong!!!".to_string());
-        assert_eq!(p.validate(&req.into()), RequestType::Invalid);
+        req.params.append("secret", "wrong!!!");
+        assert_eq!(
+            p.validate(&req.into()),
+            RequestType::Invalid(InvalidReason::WrongSecret)
+        );
 
         // With the correct secret
         let mut req = dummy_web_request();
-        req.params
-            .insert("secret".to_string(), "testing".to_string());
+        req.params.append("secret", "testing");
         assert_eq!(p.validate(&req.into()), RequestType::ExecuteHook);
 
         // With the wrong IP address
         let mut req = dummy_web_request();
-        req.params.insert("ip".into(), "127.1.1.1".into());
+        req.params.append("ip", "127.1.1.1");
         req.source = IpAddr::from_str("127.2.2.2").unwrap();
-        assert_eq!(p.validate(&req.into()), RequestType::Invalid);
+        assert_eq!(
+            p.validate(&req.into()),
+            RequestType::Invalid(InvalidReason::IpNotAllowed(
+                IpAddr::from_str("127.2.2.2").unwrap(),
+            ))
+        );
 
         // With the right IP address
         let mut req = dummy_web_request();
-        req.params.insert("ip".into(), "127.1.1.1".into());
+        req.params.append("ip", "127.1.1.1");
         req.source = IpAddr::from_str("127.1.1.1").unwrap();
         assert_eq!(p.validate(&req.into()), RequestType::ExecuteHook);
 
         // With the request_type param but with no meaningful value
         let mut req = dummy_web_request();
-        req.params
-            .insert("request_type".to_string(), "something".to_string());
+        req.params.append("request_type", "something");
         assert_eq!(p.validate(&req.into()), RequestType::ExecuteHook);
 
         // With the request_type param and the "ping" value
         let mut req = dummy_web_request();
-        req.params
-            .insert("request_type".to_string(), "ping".to_string());
+        req.params.append("request_type", "ping");
         assert_eq!(p.validate(&req.into()), RequestType::Ping);
     }
 
@@ -187,7 +193,7 @@ mod tests {
 
         // With the env param
         let mut req = dummy_web_request();
-        req.params.insert("env".to_string(), "test".to_string());
+        req.params.append("env", "test");
 
         let mut b = EnvBuilder::dummy();
         p.build_env(&req.into(), &mut b).unwrap();
@@ -208,8 +214,7 @@ mod tests {
         assert!(p.trigger_status_hooks(&dummy_web_request().into()));
 
         let mut req = dummy_web_request();
-        req.params
-            .insert("ignore_status_hooks".into(), "yes".into());
+        req.params.append("ignore_status_hooks", "yes");
 
         assert!(!p.trigger_status_hooks(&req.into()));
     }
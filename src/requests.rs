@@ -13,16 +13,74 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::fmt;
+use std::net::IpAddr;
+
 use common::prelude::*;
 use web::WebRequest;
-use providers::StatusEvent;
+use providers::{CronEvent, StatusEvent};
 
 
-#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum RequestType {
     ExecuteHook,
     Ping,
-    Invalid,
+    Invalid(InvalidReason),
+}
+
+
+/// The reason why a provider considered a request invalid.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum InvalidReason {
+    WrongRequestKind,
+    MissingHeader(&'static str),
+    MissingSecret,
+    WrongSecret,
+    BadSignature,
+    UnknownEvent(String),
+    EventNotAllowed(String),
+    IpNotAllowed(IpAddr),
+    InvalidJson,
+    ScriptNotAllowed(String),
+    NoProviderMatched(Vec<(String, InvalidReason)>),
+}
+
+impl fmt::Display for InvalidReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InvalidReason::WrongRequestKind => {
+                write!(f, "wrong kind of request")
+            }
+            InvalidReason::MissingHeader(header) => {
+                write!(f, "missing header: {}", header)
+            }
+            InvalidReason::MissingSecret => write!(f, "missing secret"),
+            InvalidReason::WrongSecret => write!(f, "wrong secret"),
+            InvalidReason::BadSignature => write!(f, "invalid signature"),
+            InvalidReason::UnknownEvent(ref event) => {
+                write!(f, "unknown event: {}", event)
+            }
+            InvalidReason::EventNotAllowed(ref event) => {
+                write!(f, "event not allowed: {}", event)
+            }
+            InvalidReason::IpNotAllowed(ip) => {
+                write!(f, "IP address not allowed: {}", ip)
+            }
+            InvalidReason::InvalidJson => write!(f, "invalid JSON body"),
+            InvalidReason::ScriptNotAllowed(ref script) => {
+                write!(f, "script not allowed: {}", script)
+            }
+            InvalidReason::NoProviderMatched(ref reasons) => {
+                for (i, (provider, reason)) in reasons.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{}: {}", provider, reason)?;
+                }
+                Ok(())
+            }
+        }
+    }
 }
 
 
@@ -30,9 +88,28 @@ pub enum RequestType {
 pub enum Request {
     Web(WebRequest),
     Status(StatusEvent),
+    Cron(CronEvent),
 }
 
 impl Request {
+    /// Get the ID used to correlate everything caused by this request.
+    pub fn id(&self) -> &str {
+        match *self {
+            Request::Web(ref req) => &req.id,
+            Request::Status(ref req) => req.request_id(),
+            Request::Cron(ref req) => req.request_id(),
+        }
+    }
+
+    /// Get the name of what caused this request.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Request::Web(..) => "webhook",
+            Request::Status(..) => "status",
+            Request::Cron(..) => "cron",
+        }
+    }
+
     pub fn web(&self) -> Result<&WebRequest> {
         if let Request::Web(ref req) = *self {
             Ok(req)
@@ -55,3 +132,10 @@ impl From<StatusEvent> for Request {
         Request::Status(from)
     }
 }
+
+
+impl From<CronEvent> for Request {
+    fn from(from: CronEvent) -> Request {
+        Request::Cron(from)
+    }
+}
@@ -22,11 +22,12 @@ use std::sync::Arc;
 use common::prelude::*;
 use common::state::State;
 
-use scripts::Script;
+use scripts::{RunAs, Script};
 
 pub(in scripts) struct Collector {
     dirs: VecDeque<ReadDir>,
     state: Arc<State>,
+    run_as: Option<Arc<RunAs>>,
     base: PathBuf,
     recursive: bool,
 }
@@ -35,6 +36,7 @@ impl Collector {
     pub(in scripts) fn new<P: AsRef<Path>>(
         base: P,
         state: Arc<State>,
+        run_as: Option<Arc<RunAs>>,
         recursive: bool,
     ) -> Result<Self> {
         let mut dirs = VecDeque::new();
@@ -43,6 +45,7 @@ impl Collector {
         Ok(Collector {
             dirs,
             state,
+            run_as,
             base: base.as_ref().to_path_buf(),
             recursive,
         })
@@ -74,7 +77,12 @@ impl Collector {
 
         let exec = canonicalize(&e)?.to_str().unwrap().into();
 
-        Ok(Some(Arc::new(Script::load(name, exec, &self.state)?)))
+        Ok(Some(Arc::new(Script::load(
+            name,
+            exec,
+            &self.state,
+            self.run_as.as_ref(),
+        )?)))
     }
 }
 
@@ -136,7 +144,8 @@ mod tests {
     ) -> Result<()> {
         let mut found = 0;
 
-        let c = Collector::new(&env.scripts_dir(), env.state(), recurse)?;
+        let c =
+            Collector::new(&env.scripts_dir(), env.state(), None, recurse)?;
         for script in c {
             found += 1;
 
@@ -17,23 +17,27 @@ use std::collections::HashMap;
 use std::env;
 use std::ffi::{OsStr, OsString};
 use std::fs;
-use std::io::Write;
-use std::net::IpAddr;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
 use std::os::unix::process::{CommandExt, ExitStatusExt};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Output};
-use std::sync::Arc;
+use std::sync::{mpsc, Arc};
 
-use nix::unistd::{setpgid, Pid};
+use libc;
+use nix::unistd::{geteuid, setpgid, Pid, Uid};
 use tempdir::TempDir;
 use users;
 
+use common::config::LimitsConfig;
 use common::prelude::*;
 use common::state::UniqueId;
 
 use providers::Provider;
 use requests::Request;
-use scripts::Script;
+use scripts::limits;
+use scripts::{RunAs, Script};
 
 static DEFAULT_ENV: &[&'static str] = &["PATH", "LC_ALL", "LANG"];
 
@@ -43,6 +47,10 @@ static ENV_PREFIX: &'static str = "FISHER";
 pub struct Context {
     pub environment: HashMap<String, String>,
     pub username: String,
+    /// The user and the group scripts are executed as by default.
+    pub run_as: Option<Arc<RunAs>>,
+    /// The resource limits of the jobs, unless scripts override them.
+    pub limits: LimitsConfig,
 }
 
 impl Default for Context {
@@ -59,6 +67,8 @@ impl Default for Context {
         Context {
             environment: HashMap::new(),
             username,
+            run_as: None,
+            limits: LimitsConfig::default(),
         }
     }
 }
@@ -218,6 +228,7 @@ pub struct Job {
     script: Arc<Script>,
     provider: Option<Arc<Provider>>,
     request: Request,
+    respond_to: Option<mpsc::Sender<JobOutput>>,
 }
 
 impl Job {
@@ -230,13 +241,27 @@ impl Job {
             script,
             provider,
             request,
+            respond_to: None,
         }
     }
 
+    /// Send the output of the job to the channel when it completes.
+    pub fn respond_to(mut self, sender: mpsc::Sender<JobOutput>) -> Self {
+        self.respond_to = Some(sender);
+        self
+    }
+
+    /// Get the priority of the script the job belongs to.
+    pub fn priority(&self) -> isize {
+        self.script.priority()
+    }
+
     pub fn request_ip(&self) -> IpAddr {
         match self.request {
             Request::Web(ref req) => req.source,
             Request::Status(ref req) => req.source_ip(),
+            // Scheduled runs are started by Fisher itself
+            Request::Cron(..) => IpAddr::V4(Ipv4Addr::LOCALHOST),
         }
     }
 
@@ -248,6 +273,19 @@ impl Job {
         }
     }
 
+    /// Get the user the job is executed as, if Fisher has to switch to it.
+    fn run_as<'a>(&'a self, ctx: &'a Context) -> Option<&'a Arc<RunAs>> {
+        self.script
+            .run_as()
+            .or(ctx.run_as.as_ref())
+            .filter(|run_as| run_as.needs_switch())
+    }
+
+    /// Get the resource limits of the job.
+    fn limits(&self, ctx: &Context) -> LimitsConfig {
+        self.script.limits().or(&ctx.limits)
+    }
+
     fn process(&self, ctx: &Context) -> Result<JobOutput> {
         let mut command = Command::new(&self.script.exec());
 
@@ -255,6 +293,14 @@ impl Job {
         let working_directory = TempDir::new("fisher")?;
         let data_directory = TempDir::new("fisher")?;
 
+        // The directories must be accessible to the user running the job
+        let run_as = self.run_as(ctx).cloned();
+        if let Some(ref run_as) = run_as {
+            run_as.check_switch()?;
+            run_as.chown(working_directory.path())?;
+            run_as.chown(data_directory.path())?;
+        }
+
         // Prepare the command's environment
         {
             let mut builder =
@@ -263,10 +309,15 @@ impl Job {
         }
 
         command.current_dir(working_directory.path().to_str().unwrap());
-        command.env("HOME", working_directory.path().to_str().unwrap());
+        if let Some(ref run_as) = run_as {
+            command.env("HOME", run_as.home());
+        } else {
+            command.env("HOME", working_directory.path().to_str().unwrap());
+        }
 
-        // Set the request IP
+        // Set the request IP and ID
         command.env("FISHER_REQUEST_IP", self.request_ip().to_string());
+        command.env("FISHER_REQUEST_ID", self.request.id());
 
         // Save the request body
         let request_body = self.save_request_body(data_directory.path())?;
@@ -274,26 +325,57 @@ impl Job {
             command.env("FISHER_REQUEST_BODY", path.to_str().unwrap());
         }
 
+        // Synchronous scripts can write the JSON to respond with in a file
+        let response_body = data_directory.path().join("response_body");
+        if self.script.sync() {
+            let path = response_body.to_str().unwrap();
+            command.env("FISHER_RESPONSE_BODY", path);
+        }
+
         // Apply the custom environment
         for (key, value) in ctx.environment.iter() {
             command.env(&key, &value);
         }
 
+        // The response body must be written by the user running the job
+        let owner = run_as.as_ref().map_or_else(geteuid, |run_as| run_as.uid());
+
         // Make sure the process is isolated
-        command.before_exec(|| {
+        let limits = self.limits(ctx);
+        let child_limits = limits.clone();
+        command.before_exec(move || {
             // If a new process group is not created, the job still works fine
             let _ = setpgid(Pid::this(), Pid::from_raw(0));
 
+            // The limits are applied before dropping the privileges, since
+            // only root can raise them
+            limits::apply(&child_limits)?;
+
+            // Drop the privileges before executing the script
+            if let Some(ref run_as) = run_as {
+                run_as.switch()?;
+            }
+
             Ok(())
         });
 
         // Execute the hook
-        let output = command.output()?;
+        let (output, cpu_time) = limits::output(&mut command)?;
+        let mut result = JobOutput::new(self, output);
+        result.limit_exceeded =
+            limits::exceeded(&limits, result.signal, cpu_time);
+
+        if self.script.sync() {
+            if let Some(content) = read_response_body(&response_body, owner)? {
+                result.response =
+                    Some(String::from_utf8_lossy(&content).into());
+            }
+        }
 
         // The temp directory is dropped - and removed - here
 
         // Return the job output
-        Ok(JobOutput::new(self, output))
+        Ok(result)
     }
 
     fn prepare_env(
@@ -305,7 +387,11 @@ impl Job {
         builder.clear_env();
 
         // Set the USER environment variable with the correct username
-        builder.add_env_unprefixed("USER", &ctx.username);
+        if let Some(run_as) = self.run_as(ctx) {
+            builder.add_env_unprefixed("USER", run_as.name());
+        } else {
+            builder.add_env_unprefixed("USER", &ctx.username);
+        }
 
         // Apply the default environment
         // This is done (instead of the automatic inheritage) to whitelist
@@ -333,26 +419,65 @@ impl Job {
         // Get the request body, even if some request kinds don't have one
         let body = match self.request {
             Request::Web(ref req) => &req.body,
-            Request::Status(..) => return Ok(None),
+            Request::Status(..) | Request::Cron(..) => return Ok(None),
         };
 
         let mut path = base.to_path_buf();
         path.push("request_body");
 
-        // Write the request body on disk
-        let mut file = fs::File::create(&path)?;
-        write!(file, "{}\n", body)?;
+        // Write the raw request body on disk
+        fs::write(&path, body)?;
 
         Ok(Some(path))
     }
 }
 
+/// Read the response body written by the script, if any. The user running
+/// the script can change its directory, so only regular files owned by that
+/// user are read: Fisher could read any other file on its behalf otherwise.
+fn read_response_body(path: &Path, owner: Uid) -> Result<Option<Vec<u8>>> {
+    let unsafe_file =
+        || ErrorKind::UnsafeResponseBody(path.to_string_lossy().into());
+
+    // Opening a FIFO would otherwise wait for someone to write to it
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK)
+        .open(path);
+    let mut file = match file {
+        Ok(file) => file,
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => {
+            return Ok(None);
+        }
+        Err(ref error) if error.raw_os_error() == Some(libc::ELOOP) => {
+            return Err(unsafe_file().into());
+        }
+        Err(error) => return Err(error.into()),
+    };
+
+    let metadata = file.metadata()?;
+    if !metadata.is_file() || metadata.uid() != owner.as_raw() {
+        return Err(unsafe_file().into());
+    }
+
+    let mut content = Vec::new();
+    file.read_to_end(&mut content)?;
+    Ok(Some(content))
+}
+
 impl JobTrait<Script> for Job {
     type Context = Context;
     type Output = JobOutput;
 
     fn execute(&self, ctx: &Context) -> Result<JobOutput> {
-        self.process(ctx)
+        let output = self.process(ctx)?;
+
+        // The receiver might have stopped waiting for the output
+        if let Some(ref respond_to) = self.respond_to {
+            let _ = respond_to.send(output.clone());
+        }
+
+        Ok(output)
     }
 
     fn script_id(&self) -> UniqueId {
@@ -362,21 +487,39 @@ impl JobTrait<Script> for Job {
     fn script_name(&self) -> &str {
         self.script.name()
     }
+
+    fn request_id(&self) -> Option<&str> {
+        Some(self.request.id())
+    }
+
+    fn coalesce_key(&self) -> Option<String> {
+        self.script.coalesce_key(&self.request)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct JobOutput {
     pub stdout: String,
     pub stderr: String,
+    pub response: Option<String>,
 
     pub success: bool,
     pub exit_code: Option<i32>,
     pub signal: Option<i32>,
+    /// The resource limit which caused the job to be killed, if any.
+    pub limit_exceeded: Option<&'static str>,
 
     pub script_name: String,
+    pub request_id: String,
     pub request_ip: IpAddr,
+    pub request_kind: &'static str,
 
     pub trigger_status_hooks: bool,
+
+    /// The request and the provider of the job, inherited by the scripts
+    /// started after it.
+    pub request: Box<Request>,
+    pub provider: Option<Arc<Provider>>,
 }
 
 impl JobOutput {
@@ -384,34 +527,53 @@ impl JobOutput {
         JobOutput {
             stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
             stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            response: None,
 
             success: output.status.success(),
             exit_code: output.status.code(),
             signal: output.status.signal(),
+            limit_exceeded: None,
 
             script_name: job.script_name().into(),
+            request_id: job.request.id().into(),
             request_ip: job.request_ip(),
+            request_kind: job.request.kind(),
 
             trigger_status_hooks: job.trigger_status_hooks(),
+
+            request: Box::new(job.request.clone()),
+            provider: job.provider.clone(),
         }
     }
 }
 
+impl JobOutputTrait for JobOutput {
+    fn success(&self) -> bool {
+        self.success
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
     use std::env;
     use std::ffi::OsString;
-    use std::fs::File;
+    use std::fs::{self, File};
     use std::io::Read;
+    use std::os::unix::fs::PermissionsExt;
     use std::path::{Path, PathBuf};
-    use std::sync::Arc;
+    use std::sync::{mpsc, Arc};
 
+    use libc;
+    use nix;
     use users;
+    use users::os::unix::UserExt;
 
+    use common::config::LimitsConfig;
     use common::prelude::*;
     use requests::Request;
     use scripts::test_utils::*;
+    use scripts::RunAs;
     use utils;
 
     use super::{Context, Job, DEFAULT_ENV};
@@ -491,6 +653,63 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_job_response() {
+        test_wrapper(|env| {
+            let ctx = Context::default();
+            let req: Request = dummy_web_request().into();
+
+            env.create_script(
+                "stdout.sh",
+                &[r#"#!/bin/bash"#, r#"## Fisher: {"sync": true}"#, "echo hi"],
+            )?;
+            env.create_script(
+                "json.sh",
+                &[
+                    r#"#!/bin/bash"#,
+                    r#"## Fisher: {"sync": true}"#,
+                    r#"echo '{"a": 1}' > "${FISHER_RESPONSE_BODY}""#,
+                ],
+            )?;
+
+            // Without the file only the standard output is available
+            let (send, recv) = mpsc::channel();
+            let job = create_job(env, "stdout.sh", req.clone())?;
+            job.respond_to(send).execute(&ctx)?;
+            let output = recv.recv()?;
+            assert_eq!(output.stdout, "hi\n");
+            assert_eq!(output.response, None);
+
+            // The content of the file is returned if it's written
+            let job = create_job(env, "json.sh", req.clone())?;
+            let output = job.process(&ctx)?;
+            assert_eq!(output.response, Some("{\"a\": 1}\n".into()));
+
+            // Only regular files are read
+            for command in &[
+                r#"ln -s /etc/hostname "${FISHER_RESPONSE_BODY}""#,
+                r#"mkfifo "${FISHER_RESPONSE_BODY}""#,
+                r#"mkdir "${FISHER_RESPONSE_BODY}""#,
+            ] {
+                env.create_script(
+                    "unsafe.sh",
+                    &[
+                        r#"#!/bin/bash"#,
+                        r#"## Fisher: {"sync": true}"#,
+                        command,
+                    ],
+                )?;
+                let job = create_job(env, "unsafe.sh", req.clone())?;
+                assert_err!(
+                    job.process(&ctx),
+                    ErrorKind::UnsafeResponseBody(..)
+                );
+            }
+
+            Ok(())
+        })
+    }
+
     fn collect_env(env: &mut TestEnv, ctx: &Context) -> Result<PathBuf> {
         // Create a script that dumps the environment into files
         env.create_script(
@@ -512,9 +731,8 @@ mod tests {
 
         // Create a dummy request
         let mut req = dummy_web_request();
-        req.body = "a body!".into();
-        req.params
-            .insert("env".into(), out.to_str().unwrap().into());
+        req.body = b"a body!".to_vec();
+        req.params.append("env", out.to_str().unwrap());
 
         // Start the job
         let job = create_job(env, "dump.sh", req.into())?;
@@ -545,7 +763,7 @@ mod tests {
             assert_eq!(&content(&out, "executed")?, "executed\n");
 
             // Ensure the request body was provided
-            assert_eq!(&content(&out, "request_body")?, "a body!\n");
+            assert_eq!(&content(&out, "request_body")?, "a body!");
 
             // Get the script working directory
             let working_directory = content(&out, "pwd")?;
@@ -559,6 +777,7 @@ mod tests {
                 // Variables set by Fisher
                 "FISHER_TESTING_ENV",
                 "FISHER_REQUEST_IP",
+                "FISHER_REQUEST_ID",
                 "FISHER_REQUEST_BODY",
                 "FISHER_TESTING_PREPARED",
                 "HOME",
@@ -593,6 +812,7 @@ mod tests {
             // Ensure environment variables are correct
             assert_eq!(&env_vars["FISHER_TESTING_ENV"], &out.to_str().unwrap());
             assert_eq!(&env_vars["FISHER_REQUEST_IP"], &"127.0.0.1");
+            assert_eq!(&env_vars["FISHER_REQUEST_ID"], &"0123456789abcdef");
             assert_eq!(&env_vars["HOME"], &working_directory.trim());
             assert_eq!(
                 &env_vars["USER"],
@@ -669,4 +889,130 @@ mod tests {
             Ok(())
         });
     }
+
+    #[test]
+    fn test_job_run_as() {
+        // Only root can execute scripts as other users
+        if !nix::unistd::geteuid().is_root() {
+            return;
+        }
+
+        test_wrapper(|env| {
+            let nobody = users::get_user_by_name("nobody").unwrap();
+            let req: Request = dummy_web_request().into();
+
+            // The user needs to reach the scripts
+            let mode = fs::Permissions::from_mode(0o755);
+            fs::set_permissions(env.scripts_dir(), mode)?;
+
+            let dump = &[
+                r#"echo "$(id -u) $(id -g) ${USER} ${HOME}""#,
+                r#"touch "$(pwd)/file""#,
+            ];
+            env.create_script(
+                "default.sh",
+                &[&["#!/bin/bash"], &dump[..]].concat(),
+            )?;
+            env.create_script(
+                "nobody.sh",
+                &[
+                    &["#!/bin/bash", r#"## Fisher: {"user": "nobody"}"#][..],
+                    &dump[..],
+                ].concat(),
+            )?;
+            let expected = format!(
+                "{} {} nobody {}\n",
+                nobody.uid(),
+                nobody.primary_group_id(),
+                nobody.home_dir().display(),
+            );
+
+            // Scripts can choose the user they're executed as
+            let job = create_job(env, "nobody.sh", req.clone())?;
+            let result = job.process(&Context::default())?;
+            assert!(result.success, "{}", result.stderr);
+            assert_eq!(result.stdout, expected);
+
+            // The default user is used by the other scripts
+            let ctx = Context {
+                run_as: RunAs::new(Some("nobody"), None)?.map(Arc::new),
+                ..Context::default()
+            };
+            let job = create_job(env, "default.sh", req.clone())?;
+            let result = job.process(&ctx)?;
+            assert!(result.success, "{}", result.stderr);
+            assert_eq!(result.stdout, expected);
+
+            // Scripts not executed as another user are not changed
+            let job = create_job(env, "default.sh", req)?;
+            let result = job.process(&Context::default())?;
+            assert!(result.success, "{}", result.stderr);
+            assert!(result.stdout.starts_with("0 0 root "));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_job_limits() {
+        test_wrapper(|env| {
+            let req: Request = dummy_web_request().into();
+
+            env.create_script(
+                "limits.sh",
+                &[
+                    "#!/bin/bash",
+                    r#"## Fisher: {"limits": {"files": 64, "core": 0}}"#,
+                    "echo $(ulimit -n) $(ulimit -c) $(ulimit -v)",
+                ],
+            )?;
+            env.create_script(
+                "spin.sh",
+                &[
+                    "#!/bin/bash",
+                    r#"## Fisher: {"limits": {"cpu": "1s"}}"#,
+                    "while true; do true; done",
+                ],
+            )?;
+            env.create_script(
+                "stubborn.sh",
+                &[
+                    "#!/bin/bash",
+                    r#"## Fisher: {"limits": {"cpu": "1s"}}"#,
+                    "trap '' XCPU",
+                    "while true; do true; done",
+                ],
+            )?;
+
+            // The limits of the script override the default ones
+            let ctx = Context {
+                limits: LimitsConfig {
+                    memory: Some(1024 * 1024 * 1024),
+                    files: Some(128),
+                    ..LimitsConfig::default()
+                },
+                ..Context::default()
+            };
+            let job = create_job(env, "limits.sh", req.clone())?;
+            let result = job.process(&ctx)?;
+            assert!(result.success, "{}", result.stderr);
+            assert_eq!(result.stdout, "64 0 1048576\n");
+            assert!(result.limit_exceeded.is_none());
+
+            // Jobs killed for exceeding a limit are reported
+            let job = create_job(env, "spin.sh", req.clone())?;
+            let result = job.process(&Context::default())?;
+            assert!(!result.success);
+            assert_eq!(result.signal, Some(libc::SIGXCPU));
+            assert_eq!(result.limit_exceeded, Some("cpu"));
+
+            // Including the ones killed after ignoring the first signal
+            let job = create_job(env, "stubborn.sh", req)?;
+            let result = job.process(&Context::default())?;
+            assert_eq!(result.signal, Some(libc::SIGKILL));
+            assert_eq!(result.limit_exceeded, Some("cpu"));
+
+            Ok(())
+        });
+    }
 }
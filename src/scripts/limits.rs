@@ -0,0 +1,175 @@
+// Copyright (C) 2018 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::io::{self, Read};
+use std::mem;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use libc;
+
+use common::config::LimitsConfig;
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type Resource = libc::__rlimit_resource_t;
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+type Resource = libc::c_int;
+
+/// Apply the limits to the current process. This is meant to be called in
+/// the child process, just before executing the script.
+pub fn apply(limits: &LimitsConfig) -> io::Result<()> {
+    if let Some(ref cpu) = limits.cpu {
+        // The process receives SIGXCPU when it reaches the limit, and it's
+        // killed a second later if it's still running
+        set(libc::RLIMIT_CPU, cpu.as_u64(), cpu.as_u64() + 1)?;
+    }
+    if let Some(memory) = limits.memory {
+        set(libc::RLIMIT_AS, memory, memory)?;
+    }
+    if let Some(files) = limits.files {
+        set(libc::RLIMIT_NOFILE, files, files)?;
+    }
+    if let Some(processes) = limits.processes {
+        set(libc::RLIMIT_NPROC, processes, processes)?;
+    }
+    if let Some(core) = limits.core {
+        set(libc::RLIMIT_CORE, core, core)?;
+    }
+
+    Ok(())
+}
+
+fn set(resource: Resource, soft: u64, hard: u64) -> io::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: soft as libc::rlim_t,
+        rlim_max: hard as libc::rlim_t,
+    };
+
+    if unsafe { libc::setrlimit(resource, &limit) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Execute the command like `Command::output` does, also returning the CPU
+/// time used by the process.
+pub fn output(command: &mut Command) -> io::Result<(Output, Duration)> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Both pipes are read at the same time, since the process would block
+    // if the one not being read is full
+    let mut stderr_pipe = child.stderr.take().unwrap();
+    let stderr = thread::spawn(move || {
+        let mut stderr = Vec::new();
+        stderr_pipe.read_to_end(&mut stderr).map(|_| stderr)
+    });
+    let mut stdout = Vec::new();
+    let stdout_result = child.stdout.take().unwrap().read_to_end(&mut stdout);
+
+    // The process is waited for even if its output couldn't be read, so it
+    // doesn't stay around as a zombie
+    let mut status = 0;
+    let mut usage: libc::rusage = unsafe { mem::zeroed() };
+    let pid = child.id() as libc::pid_t;
+    while unsafe { libc::wait4(pid, &mut status, 0, &mut usage) } < 0 {
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+
+    stdout_result?;
+    let stderr = stderr
+        .join()
+        .map_err(|_| io::Error::new(io::ErrorKind::Other, "reader panicked"))??;
+
+    let cpu_time = duration(usage.ru_utime) + duration(usage.ru_stime);
+    let output = Output {
+        status: ExitStatus::from_raw(status),
+        stdout,
+        stderr,
+    };
+    Ok((output, cpu_time))
+}
+
+fn duration(time: libc::timeval) -> Duration {
+    Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000)
+}
+
+/// Get the name of the limit which caused the process to be killed, if it
+/// was killed for exceeding one. Only the CPU time limit can be detected,
+/// since reaching the other ones makes system calls fail instead.
+pub fn exceeded(
+    limits: &LimitsConfig,
+    signal: Option<i32>,
+    cpu_time: Duration,
+) -> Option<&'static str> {
+    let cpu = limits.cpu.as_ref().map(|cpu| Duration::from_secs(cpu.as_u64()));
+    match (signal, cpu) {
+        (Some(libc::SIGXCPU), Some(_)) => Some("cpu"),
+        // Processes ignoring SIGXCPU are killed a second later
+        (Some(libc::SIGKILL), Some(cpu)) if cpu_time >= cpu => Some("cpu"),
+        _ => None,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+    use std::time::Duration;
+
+    use libc;
+
+    use common::config::LimitsConfig;
+
+    use super::{exceeded, output};
+
+
+    #[test]
+    fn test_output() {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("echo out; echo err >&2; exit 3");
+        let (output, _) = output(&mut command).unwrap();
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(output.stdout, b"out\n");
+        assert_eq!(output.stderr, b"err\n");
+    }
+
+    #[test]
+    fn test_exceeded() {
+        let limits = LimitsConfig {
+            cpu: Some(1.into()),
+            ..LimitsConfig::default()
+        };
+        let second = Duration::from_secs(1);
+        let kill = Some(libc::SIGKILL);
+
+        assert_eq!(exceeded(&limits, Some(libc::SIGXCPU), second), Some("cpu"));
+        assert_eq!(exceeded(&limits, kill, second), Some("cpu"));
+
+        // Processes killed before reaching the limit were killed by others
+        assert_eq!(exceeded(&limits, kill, Duration::from_millis(10)), None);
+        assert_eq!(exceeded(&LimitsConfig::default(), kill, second), None);
+        assert_eq!(exceeded(&limits, None, second), None);
+    }
+}
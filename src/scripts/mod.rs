@@ -17,10 +17,13 @@
 mod test_utils;
 mod collector;
 mod jobs;
+mod limits;
 mod repository;
+mod run_as;
 mod script;
 
 pub use self::repository::{Blueprint, Repository};
 pub use self::repository::{ScriptsIter, StatusJobsIter};
 pub use self::script::{Script, ScriptProvider};
 pub use self::jobs::{Job, JobOutput, Context as JobContext, EnvBuilder};
+pub use self::run_as::RunAs;
@@ -13,17 +13,20 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
+use std::vec;
 
 use common::prelude::*;
 use common::state::{State, UniqueId};
-use providers::{Provider, StatusEvent, StatusEventKind};
+use providers::{CronEvent, Provider, StatusEvent, StatusEventKind};
 use requests::Request;
 use scripts::collector::Collector;
 use scripts::jobs::{Job, JobOutput};
+use scripts::run_as::RunAs;
 use scripts::script::{Script, ScriptProvider};
+use utils::UtcDate;
 
 pub struct ScriptsIter {
     inner: Arc<RwLock<RepositoryInner>>,
@@ -101,6 +104,7 @@ struct RepositoryInner {
     by_id: HashMap<UniqueId, Arc<Script>>,
     by_name: HashMap<String, Arc<Script>>,
     status_hooks: HashMap<StatusEventKind, Vec<ScriptProvider>>,
+    cron_hooks: Vec<ScriptProvider>,
 }
 
 impl RepositoryInner {
@@ -110,6 +114,7 @@ impl RepositoryInner {
             by_id: HashMap::new(),
             by_name: HashMap::new(),
             status_hooks: HashMap::new(),
+            cron_hooks: Vec::new(),
         }
     }
 
@@ -132,12 +137,80 @@ impl RepositoryInner {
                         });
                 }
             }
+
+            if let Provider::Cron(..) = *provider.as_ref() {
+                self.cron_hooks.push(ScriptProvider {
+                    script: script.clone(),
+                    provider: provider.clone(),
+                });
+            }
         }
     }
 
     pub fn get_by_name(&self, name: &str) -> Option<Arc<Script>> {
         self.by_name.get(name).cloned()
     }
+
+    /// Ensure all the scripts started after other ones exist, and that they
+    /// don't start each other in a loop.
+    fn check_followups(&self) -> Result<()> {
+        let mut checked = HashSet::new();
+        for script in &self.scripts {
+            let mut chain = Vec::new();
+            self.check_followups_of(script, &mut chain, &mut checked)?;
+        }
+        Ok(())
+    }
+
+    fn check_followups_of(
+        &self,
+        script: &Script,
+        chain: &mut Vec<String>,
+        checked: &mut HashSet<String>,
+    ) -> Result<()> {
+        let name = script.name().to_string();
+        if let Some(pos) = chain.iter().position(|prev| *prev == name) {
+            let mut cycle = chain[pos..].to_vec();
+            cycle.push(name);
+            return Err(ErrorKind::FollowUpCycle(cycle).into());
+        }
+        if checked.contains(&name) {
+            return Ok(());
+        }
+
+        chain.push(name.clone());
+        for followup in script.followups() {
+            let next = self.get_by_name(followup).ok_or_else(|| {
+                ErrorKind::FollowUpNotFound(name.clone(), followup.into())
+            })?;
+            self.check_followups_of(&next, chain, checked)?;
+        }
+        chain.pop();
+
+        checked.insert(name);
+        Ok(())
+    }
+
+    /// Get the jobs started after the output of a job.
+    fn followup_jobs(&self, output: &JobOutput) -> Vec<Job> {
+        let script = match self.get_by_name(&output.script_name) {
+            Some(script) => script,
+            None => return Vec::new(),
+        };
+
+        script
+            .followups_after(output.success)
+            .into_iter()
+            .filter_map(|name| self.get_by_name(name))
+            .map(|next| {
+                Job::new(
+                    next,
+                    output.provider.clone(),
+                    (*output.request).clone(),
+                )
+            })
+            .collect()
+    }
 }
 
 #[derive(Debug)]
@@ -152,13 +225,36 @@ impl Repository {
             Err(poisoned) => poisoned.get_ref().get_by_name(name),
         }
     }
+
+    /// Get the jobs of the scripts scheduled to run in the minute of the
+    /// provided time.
+    pub fn cron_jobs(&self, time: &UtcDate) -> Vec<Job> {
+        let inner = match self.inner.read() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        let mut jobs = Vec::new();
+        for hp in &inner.cron_hooks {
+            if let Provider::Cron(ref cron) = *hp.provider {
+                if cron.schedule().matches(time) {
+                    jobs.push(Job::new(
+                        hp.script.clone(),
+                        Some(hp.provider.clone()),
+                        Request::Cron(CronEvent::new(cron.schedule(), *time)),
+                    ));
+                }
+            }
+        }
+        jobs
+    }
 }
 
 impl ScriptsRepositoryTrait for Repository {
     type Script = Script;
     type Job = Job;
     type ScriptsIter = ScriptsIter;
-    type JobsIter = StatusJobsIter;
+    type JobsIter = vec::IntoIter<Job>;
 
     fn id_exists(&self, id: &UniqueId) -> bool {
         match self.inner.read() {
@@ -171,18 +267,27 @@ impl ScriptsRepositoryTrait for Repository {
         ScriptsIter::new(self.inner.clone())
     }
 
-    fn jobs_after_output(&self, output: JobOutput) -> Option<StatusJobsIter> {
-        if !output.trigger_status_hooks {
-            return None;
+    fn jobs_after_output(&self, output: JobOutput) -> Option<Self::JobsIter> {
+        // The scripts started after this one are queued first
+        let mut jobs = match self.inner.read() {
+            Ok(inner) => inner.followup_jobs(&output),
+            Err(poisoned) => poisoned.get_ref().followup_jobs(&output),
+        };
+
+        if output.trigger_status_hooks {
+            let event = if output.success {
+                StatusEvent::JobCompleted(output)
+            } else {
+                StatusEvent::JobFailed(output)
+            };
+            jobs.extend(StatusJobsIter::new(self.inner.clone(), event));
         }
 
-        let event = if output.success {
-            StatusEvent::JobCompleted(output)
+        if jobs.is_empty() {
+            None
         } else {
-            StatusEvent::JobFailed(output)
-        };
-
-        Some(StatusJobsIter::new(self.inner.clone(), event))
+            Some(jobs.into_iter())
+        }
     }
 }
 
@@ -190,6 +295,7 @@ impl ScriptsRepositoryTrait for Repository {
 pub struct Blueprint {
     added: Vec<Arc<Script>>,
     collect_paths: Vec<(PathBuf, bool)>,
+    run_as: Option<Arc<RunAs>>,
 
     inner: Arc<RwLock<RepositoryInner>>,
     state: Arc<State>,
@@ -200,6 +306,7 @@ impl Blueprint {
         Blueprint {
             added: Vec::new(),
             collect_paths: Vec::new(),
+            run_as: None,
 
             inner: Arc::new(RwLock::new(RepositoryInner::new())),
             state,
@@ -211,6 +318,12 @@ impl Blueprint {
         self.collect_paths.clear();
     }
 
+    /// Set the default user scripts are executed as, which is used to
+    /// check the collected scripts from the next reload.
+    pub fn set_run_as(&mut self, run_as: Option<Arc<RunAs>>) {
+        self.run_as = run_as;
+    }
+
     #[cfg(test)]
     pub fn insert(&mut self, script: Arc<Script>) -> Result<()> {
         self.added.push(script);
@@ -242,12 +355,19 @@ impl Blueprint {
         // Collect scripts from paths
         let mut collector;
         for &(ref p, recursive) in &self.collect_paths {
-            collector = Collector::new(p, self.state.clone(), recursive)?;
+            collector = Collector::new(
+                p,
+                self.state.clone(),
+                self.run_as.clone(),
+                recursive,
+            )?;
             for script in collector {
                 inner.insert(script?);
             }
         }
 
+        inner.check_followups()?;
+
         {
             let mut to_update = self.inner.write()?;
             *to_update = inner;
@@ -268,10 +388,13 @@ mod tests {
     use std::fs;
     use std::os::unix::fs as unix_fs;
     use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
 
     use common::prelude::*;
     use providers::StatusEventKind;
     use scripts::test_utils::*;
+    use utils::testing::dummy_job_output;
+    use utils::UtcDate;
 
     use super::{Blueprint, Repository};
 
@@ -552,4 +675,141 @@ mod tests {
             Ok(())
         })
     }
+
+    #[test]
+    fn test_followup_jobs() {
+        test_wrapper(|env| {
+            env.create_script(
+                "build.sh",
+                &[
+                    r#"#!/bin/bash"#,
+                    concat!(
+                        r#"## Fisher: {"then": ["notify.sh"], "#,
+                        r#""after_success": ["test.sh"]}"#,
+                    ),
+                ],
+            )?;
+            env.create_script("test.sh", &[r#"#!/bin/bash"#])?;
+            env.create_script("notify.sh", &[r#"#!/bin/bash"#])?;
+
+            let mut blueprint = Blueprint::new(env.state());
+            blueprint.collect_path(env.scripts_dir(), false)?;
+            let repository = blueprint.repository();
+
+            let followups = |success| {
+                let mut output = dummy_job_output();
+                output.script_name = "build.sh".into();
+                output.success = success;
+                output.trigger_status_hooks = false;
+
+                repository
+                    .jobs_after_output(output)
+                    .map(|jobs| {
+                        jobs.map(|job| {
+                            // The jobs inherit the original request
+                            let id = job.request_id();
+                            assert_eq!(id, Some("0123456789abcdef"));
+                            job.script_name().to_string()
+                        })
+                        .collect::<Vec<_>>()
+                    })
+                    .unwrap_or_default()
+            };
+
+            assert_eq!(followups(true), vec!["notify.sh", "test.sh"]);
+            assert_eq!(followups(false), vec!["notify.sh"]);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_followups_are_checked() {
+        test_wrapper(|env| {
+            env.create_script(
+                "build.sh",
+                &[r#"#!/bin/bash"#, r#"## Fisher: {"then": ["test.sh"]}"#],
+            )?;
+
+            // The scripts started after other ones must exist
+            let mut blueprint = Blueprint::new(env.state());
+            assert_err!(
+                blueprint.collect_path(env.scripts_dir(), false),
+                ErrorKind::FollowUpNotFound(..)
+            );
+
+            // The scripts can't start each other in a loop
+            env.create_script(
+                "test.sh",
+                &[
+                    r#"#!/bin/bash"#,
+                    r#"## Fisher: {"after_success": ["deploy.sh"]}"#,
+                ],
+            )?;
+            env.create_script(
+                "deploy.sh",
+                &[r#"#!/bin/bash"#, r#"## Fisher: {"then": ["build.sh"]}"#],
+            )?;
+            match blueprint.reload() {
+                Err(Error(ErrorKind::FollowUpCycle(chain), _)) => {
+                    assert_eq!(chain.len(), 4);
+                    assert_eq!(chain.first(), chain.last());
+                }
+                other => panic!("unexpected result: {:?}", other),
+            }
+
+            // Breaking the loop makes the scripts valid
+            fs::remove_file(env.scripts_dir().join("deploy.sh"))?;
+            env.create_script("deploy.sh", &[r#"#!/bin/bash"#])?;
+            blueprint.reload()?;
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_cron_jobs() {
+        test_wrapper(|env| {
+            env.create_script(
+                "nightly.sh",
+                &[
+                    r#"#!/bin/bash"#,
+                    r#"## Fisher-Cron: {"schedule": "0 3 * * *"}"#,
+                ],
+            )?;
+            env.create_script(
+                "hourly.sh",
+                &[
+                    r#"#!/bin/bash"#,
+                    r#"## Fisher-Cron: {"schedule": "@hourly"}"#,
+                ],
+            )?;
+            env.create_script(
+                "normal.sh",
+                &[r#"#!/bin/bash"#, r#"## Fisher-Testing: {}"#],
+            )?;
+
+            let mut blueprint = Blueprint::new(env.state());
+            blueprint.collect_path(env.scripts_dir(), false)?;
+            let repository = blueprint.repository();
+
+            let names = |secs| {
+                let time = UtcDate::new(UNIX_EPOCH + Duration::from_secs(secs));
+                let mut names = repository
+                    .cron_jobs(&time)
+                    .iter()
+                    .map(|job| job.script_name().to_string())
+                    .collect::<Vec<_>>();
+                names.sort();
+                names
+            };
+
+            // 2017-10-18T03:00:00Z
+            assert_eq!(names(1_508_295_600), vec!["hourly.sh", "nightly.sh"]);
+            assert_eq!(names(1_508_299_200), vec!["hourly.sh"]);
+            assert!(names(1_508_295_660).is_empty());
+
+            Ok(())
+        })
+    }
 }
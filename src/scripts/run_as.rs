@@ -0,0 +1,339 @@
+// Copyright (C) 2018 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use nix;
+use nix::unistd::{
+    chown, getegid, geteuid, setgid, setgroups, setuid, Gid, Uid,
+};
+use users;
+use users::os::unix::UserExt;
+
+use common::prelude::*;
+
+/// The user and the group a script is executed as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunAs {
+    name: String,
+    uid: Uid,
+    gid: Gid,
+    groups: Vec<Gid>,
+    home: PathBuf,
+}
+
+impl RunAs {
+    /// Look up the user and the group to execute scripts as. The current
+    /// user is used if only the group is provided, and the primary group of
+    /// the user is used if only the user is provided.
+    pub fn new(
+        user: Option<&str>,
+        group: Option<&str>,
+    ) -> Result<Option<Self>> {
+        let user = match (user, group) {
+            (None, None) => return Ok(None),
+            (Some(name), _) => users::get_user_by_name(name)
+                .ok_or_else(|| ErrorKind::UserNotFound(name.into()))?,
+            (None, Some(_)) => {
+                let uid = geteuid();
+                users::get_user_by_uid(uid.as_raw()).ok_or_else(|| {
+                    ErrorKind::UserNotFound(uid.to_string())
+                })?
+            }
+        };
+
+        let gid = if let Some(name) = group {
+            users::get_group_by_name(name)
+                .ok_or_else(|| ErrorKind::GroupNotFound(name.into()))?
+                .gid()
+        } else {
+            user.primary_group_id()
+        };
+
+        // The supplementary groups of the user are kept, along with the
+        // chosen group
+        let groups = users::get_user_groups(user.name(), gid)
+            .unwrap_or_default()
+            .iter()
+            .map(|group| Gid::from_raw(group.gid()))
+            .collect();
+
+        Ok(Some(RunAs {
+            name: user.name().to_string_lossy().into_owned(),
+            uid: Uid::from_raw(user.uid()),
+            gid: Gid::from_raw(gid),
+            groups,
+            home: user.home_dir().to_path_buf(),
+        }))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn home(&self) -> &Path {
+        &self.home
+    }
+
+    pub fn uid(&self) -> Uid {
+        self.uid
+    }
+
+    /// Check if scripts executed as this user need Fisher to switch to it.
+    pub fn needs_switch(&self) -> bool {
+        self.uid != geteuid() || self.gid != getegid()
+    }
+
+    /// Check if Fisher is allowed to switch to this user, if it needs to.
+    pub fn check_switch(&self) -> Result<()> {
+        if !self.needs_switch() || geteuid().is_root() {
+            Ok(())
+        } else {
+            Err(ErrorKind::UserSwitchNotAllowed(self.name.clone()).into())
+        }
+    }
+
+    /// Give the ownership of a path to this user and group.
+    pub fn chown<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        chown(path.as_ref(), Some(self.uid), Some(self.gid))?;
+        Ok(())
+    }
+
+    /// Switch the current process to this user and group. This is meant to
+    /// be called in the child process, just before executing the script.
+    pub fn switch(&self) -> io::Result<()> {
+        setgroups(&self.groups).map_err(to_io_error)?;
+        setgid(self.gid).map_err(to_io_error)?;
+        setuid(self.uid).map_err(to_io_error)?;
+        Ok(())
+    }
+}
+
+fn to_io_error(error: nix::Error) -> io::Error {
+    // The system calls used here only fail with an errno
+    if let nix::Error::Sys(errno) = error {
+        io::Error::from_raw_os_error(errno as i32)
+    } else {
+        io::Error::from(io::ErrorKind::Other)
+    }
+}
+
+/// Check the script can't be changed by users other than root and the one
+/// executing it, which would allow them to run commands as that user. This
+/// is checked only if Fisher runs as root, since it's not possible to gain
+/// more privileges otherwise.
+pub fn check_script(exec: &str, run_as: Option<&RunAs>) -> Result<()> {
+    if !geteuid().is_root() {
+        return Ok(());
+    }
+
+    if !is_safe(Path::new(exec), run_as)? {
+        return Err(ErrorKind::UnsafeScript(exec.into()).into());
+    }
+
+    // Scripts can also be replaced by whoever can change any directory
+    // containing them, including the ones reached through symlinks
+    let path = Path::new(exec);
+    let real = fs::canonicalize(path)?;
+    let dirs = path.ancestors().skip(1).chain(real.ancestors().skip(1));
+    for dir in dirs.filter(|dir| !dir.as_os_str().is_empty()) {
+        if !is_safe(dir, run_as)? {
+            return Err(ErrorKind::UnsafeScriptsDirectory(
+                dir.to_string_lossy().into_owned(),
+            ).into());
+        }
+    }
+
+    Ok(())
+}
+
+fn is_safe(path: &Path, run_as: Option<&RunAs>) -> Result<bool> {
+    let metadata = fs::metadata(path)?;
+    let owner = Uid::from_raw(metadata.uid());
+    let allowed_owner = owner.is_root()
+        || run_as.map(|run_as| run_as.uid == owner).unwrap_or(false);
+
+    // Only the owners of the entries of directories with the sticky bit
+    // (like /tmp) can replace them, and those entries are checked as well
+    let sticky = metadata.is_dir() && metadata.mode() & 0o1000 != 0;
+
+    Ok(allowed_owner && (sticky || metadata.mode() & 0o022 == 0))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::Arc;
+
+    use nix::unistd::{chown, geteuid, Uid};
+    use users;
+
+    use common::prelude::*;
+    use scripts::test_utils::*;
+    use scripts::Script;
+
+    use super::{check_script, RunAs};
+
+    #[test]
+    fn test_run_as_lookup() {
+        let current = users::get_user_by_uid(geteuid().as_raw()).unwrap();
+        let name = current.name().to_str().unwrap();
+
+        assert!(RunAs::new(None, None).unwrap().is_none());
+
+        // The primary group of the user is used by default
+        let run_as = RunAs::new(Some(name), None).unwrap().unwrap();
+        assert_eq!(run_as.name(), name);
+        assert_eq!(run_as.uid, geteuid());
+        assert_eq!(run_as.gid.as_raw(), current.primary_group_id());
+        assert!(run_as.groups.contains(&run_as.gid));
+        assert!(!run_as.needs_switch());
+
+        // The current user is used if only the group is provided
+        let group = users::get_group_by_gid(current.primary_group_id())
+            .unwrap();
+        let run_as = RunAs::new(None, group.name().to_str()).unwrap().unwrap();
+        assert_eq!(run_as.name(), name);
+        assert_eq!(run_as.gid.as_raw(), current.primary_group_id());
+
+        let error = RunAs::new(Some("fisher-missing-user"), None).unwrap_err();
+        if let ErrorKind::UserNotFound(ref user) = *error.kind() {
+            assert_eq!(user, "fisher-missing-user");
+        } else {
+            panic!("wrong error: {}", error);
+        }
+
+        let error = RunAs::new(Some(name), Some("fisher-missing-group"))
+            .unwrap_err();
+        if let ErrorKind::GroupNotFound(ref group) = *error.kind() {
+            assert_eq!(group, "fisher-missing-group");
+        } else {
+            panic!("wrong error: {}", error);
+        }
+    }
+
+    #[test]
+    fn test_check_switch() {
+        let current = users::get_user_by_uid(geteuid().as_raw()).unwrap();
+        let name = current.name().to_str().unwrap();
+
+        // Switching to the current user is always allowed
+        RunAs::new(Some(name), None).unwrap().unwrap().check_switch().unwrap();
+
+        // Only root can switch to other users
+        let nobody = RunAs::new(Some("nobody"), None).unwrap().unwrap();
+        if geteuid().is_root() {
+            nobody.check_switch().unwrap();
+            return;
+        }
+
+        let error = nobody.check_switch().unwrap_err();
+        if let ErrorKind::UserSwitchNotAllowed(ref user) = *error.kind() {
+            assert_eq!(user, "nobody");
+        } else {
+            panic!("wrong error: {}", error);
+        }
+
+        // Scripts asking for another user can't be loaded
+        test_wrapper(|env| {
+            env.create_script(
+                "nobody.sh",
+                &["#!/bin/bash", r#"## Fisher: {"user": "nobody"}"#],
+            )?;
+            assert!(env.load_script("nobody.sh").is_err());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_check_script() {
+        // Only scripts executed by root are checked
+        if !geteuid().is_root() {
+            return;
+        }
+
+        test_wrapper(|env| {
+            env.create_script("script.sh", &["#!/bin/bash"])?;
+            let path = env.scripts_dir().join("script.sh");
+            let exec = path.to_str().unwrap();
+
+            let nobody = RunAs::new(Some("nobody"), None)?.unwrap();
+            let set_mode = |mode| {
+                fs::set_permissions(&path, fs::Permissions::from_mode(mode))
+            };
+
+            // Scripts owned by root can be executed by anyone
+            check_script(exec, None)?;
+            check_script(exec, Some(&nobody))?;
+
+            // Scripts writable by other users can't be executed
+            for mode in &[0o775, 0o757] {
+                set_mode(*mode)?;
+                assert!(check_script(exec, None).is_err());
+                assert!(check_script(exec, Some(&nobody)).is_err());
+            }
+            set_mode(0o755)?;
+
+            // Scripts owned by a user can only be executed as that user
+            chown(&path, Some(nobody.uid), None)?;
+            assert!(check_script(exec, None).is_err());
+            check_script(exec, Some(&nobody))?;
+
+            // The default user is used if the script doesn't choose one
+            let state = env.state();
+            let load = |run_as| {
+                Script::load("script.sh".into(), exec.into(), &state, run_as)
+            };
+            assert!(load(None).is_err());
+            load(Some(&Arc::new(nobody.clone())))?;
+            chown(&path, Some(Uid::from_raw(0)), None)?;
+
+            // Scripts in directories writable by other users can't be
+            // executed either
+            let dir = env.scripts_dir();
+            let permissions = fs::metadata(&dir)?.permissions();
+            for mode in &[0o775, 0o757] {
+                fs::set_permissions(&dir, fs::Permissions::from_mode(*mode))?;
+                assert_err!(
+                    check_script(exec, None),
+                    ErrorKind::UnsafeScriptsDirectory(..)
+                );
+            }
+
+            // The same applies to every directory above the script, unless
+            // the sticky bit is set
+            let nested = dir.join("nested");
+            fs::create_dir(&nested)?;
+            fs::copy(&path, nested.join("script.sh"))?;
+            let nested_exec = nested.join("script.sh");
+            let nested_exec = nested_exec.to_str().unwrap();
+            fs::set_permissions(&dir, fs::Permissions::from_mode(0o777))?;
+            assert_err!(
+                check_script(nested_exec, None),
+                ErrorKind::UnsafeScriptsDirectory(..)
+            );
+            fs::set_permissions(&dir, fs::Permissions::from_mode(0o1777))?;
+            check_script(nested_exec, None)?;
+            fs::set_permissions(&dir, permissions)?;
+
+            Ok(())
+        });
+    }
+}
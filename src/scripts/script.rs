@@ -13,18 +13,24 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::sync::Arc;
+use std::time::Duration;
 
 use regex::Regex;
 use serde_json;
 
+use common::config::{LimitsConfig, RateLimitConfig};
 use common::prelude::*;
 use common::state::{IdKind, State, UniqueId};
 
 use providers::Provider;
-use requests::{Request, RequestType};
+use requests::{InvalidReason, Request, RequestType};
+use scripts::run_as::{self, RunAs};
+use scripts::JobOutput;
+use utils::TimeString;
 
 #[derive(Debug, Clone)]
 pub struct ScriptProvider {
@@ -43,6 +49,20 @@ lazy_static! {
 struct Preferences {
     priority: Option<isize>,
     parallel: Option<bool>,
+    max_parallel: Option<usize>,
+    sync: Option<bool>,
+    status_codes: Option<HashMap<i32, u16>>,
+    rate_limit: Option<RateLimitConfig>,
+    max_queued: Option<usize>,
+    coalesce: Option<CoalesceMode>,
+    coalesce_key: Option<String>,
+    debounce: Option<TimeString>,
+    group: Option<String>,
+    then: Option<Vec<String>>,
+    after_success: Option<Vec<String>>,
+    user: Option<String>,
+    user_group: Option<String>,
+    limits: Option<LimitsConfig>,
 }
 
 impl Preferences {
@@ -50,9 +70,43 @@ impl Preferences {
         Preferences {
             priority: None,
             parallel: None,
+            max_parallel: None,
+            sync: None,
+            status_codes: None,
+            rate_limit: None,
+            max_queued: None,
+            coalesce: None,
+            coalesce_key: None,
+            debounce: None,
+            group: None,
+            then: None,
+            after_success: None,
+            user: None,
+            user_group: None,
+            limits: None,
         }
     }
 
+    fn validate(&self) -> Result<()> {
+        if self.max_parallel == Some(0) {
+            return Err(ErrorKind::InvalidMaxParallel.into());
+        }
+
+        if let Some(ref key) = self.coalesce_key {
+            CoalesceKey::parse(key)?;
+        }
+
+        if let Some(ref status_codes) = self.status_codes {
+            for status in status_codes.values() {
+                if *status < 100 || *status > 599 {
+                    return Err(ErrorKind::InvalidStatusCode(*status).into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     #[inline]
     fn priority(&self) -> isize {
         self.priority.unwrap_or(0)
@@ -62,6 +116,92 @@ impl Preferences {
     fn parallel(&self) -> bool {
         self.parallel.unwrap_or(true)
     }
+
+    #[inline]
+    fn max_parallel(&self) -> Option<usize> {
+        // Scripts which can't be parallel run one job at a time
+        if self.parallel() {
+            self.max_parallel
+        } else {
+            Some(1)
+        }
+    }
+
+    #[inline]
+    fn sync(&self) -> bool {
+        self.sync.unwrap_or(false)
+    }
+
+    #[inline]
+    fn coalesce(&self) -> bool {
+        // Debouncing doesn't make sense without replacing the older jobs,
+        // and setting a key means the jobs should be coalesced with it
+        self.coalesce.is_some()
+            || self.coalesce_key.is_some()
+            || self.debounce.is_some()
+    }
+
+    #[inline]
+    fn debounce(&self) -> Option<Duration> {
+        self.debounce
+            .as_ref()
+            .map(|debounce| Duration::from_secs(debounce.as_u64()))
+    }
+}
+
+/// How the jobs of a script are coalesced.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum CoalesceMode {
+    /// Newer jobs replace the older ones still in the queue.
+    Latest,
+}
+
+/// Where the key used to coalesce the jobs of a script comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoalesceKey {
+    Param(String),
+    Header(String),
+    Json(String),
+}
+
+impl CoalesceKey {
+    fn parse(key: &str) -> Result<CoalesceKey> {
+        let mut parts = key.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some("param"), Some(name)) if !name.is_empty() => {
+                Ok(CoalesceKey::Param(name.into()))
+            }
+            (Some("header"), Some(name)) if !name.is_empty() => {
+                Ok(CoalesceKey::Header(name.into()))
+            }
+            (Some("json"), Some(pointer)) if pointer.starts_with('/') => {
+                Ok(CoalesceKey::Json(pointer.into()))
+            }
+            _ => Err(ErrorKind::InvalidCoalesceKey(key.into()).into()),
+        }
+    }
+
+    /// Extract the value of the key from the request, if it's present.
+    pub fn extract(&self, req: &Request) -> Option<String> {
+        let req = req.web().ok()?;
+        match *self {
+            CoalesceKey::Param(ref name) => {
+                req.params.get(name).map(|value| value.to_string())
+            }
+            CoalesceKey::Header(ref name) => {
+                req.headers.get(name).map(|value| value.to_string())
+            }
+            CoalesceKey::Json(ref pointer) => {
+                let body: serde_json::Value =
+                    serde_json::from_slice(&req.body).ok()?;
+                match body.pointer(pointer)? {
+                    serde_json::Value::String(ref value) => Some(value.clone()),
+                    other => Some(other.to_string()),
+                }
+            }
+        }
+    }
 }
 
 struct LoadHeadersOutput {
@@ -88,7 +228,12 @@ fn load_headers(file: &str) -> Result<LoadHeadersOutput> {
 
         if preferences.is_none() {
             if let Some(cap) = PREFERENCES_HEADER_RE.captures(&content) {
-                preferences = Some(serde_json::from_str(&cap[1])?);
+                let parsed: Preferences = serde_json::from_str(&cap[1])?;
+                parsed.validate().chain_err(|| {
+                    ErrorKind::ScriptParsingError(file.into(), line_number)
+                })?;
+
+                preferences = Some(parsed);
                 continue; // Don't capture anything else for this line
             }
         }
@@ -127,6 +272,19 @@ pub struct Script {
     exec: String,
     priority: isize,
     parallel: bool,
+    max_parallel: Option<usize>,
+    sync: bool,
+    status_codes: HashMap<i32, u16>,
+    rate_limit: Option<RateLimitConfig>,
+    max_queued: Option<usize>,
+    coalesce: bool,
+    coalesce_key: Option<CoalesceKey>,
+    debounce: Option<Duration>,
+    group: Option<String>,
+    then: Vec<String>,
+    after_success: Vec<String>,
+    run_as: Option<Arc<RunAs>>,
+    limits: LimitsConfig,
     pub(crate) providers: Vec<Arc<Provider>>,
 }
 
@@ -135,8 +293,21 @@ impl Script {
         name: String,
         exec: String,
         state: &Arc<State>,
+        default_run_as: Option<&Arc<RunAs>>,
     ) -> Result<Self> {
-        let headers = load_headers(&exec)?;
+        let mut headers = load_headers(&exec)?;
+
+        let run_as = RunAs::new(
+            headers.preferences.user.as_deref(),
+            headers.preferences.user_group.as_deref(),
+        )?;
+        if let Some(ref run_as) = run_as {
+            run_as.check_switch()?;
+        }
+        run_as::check_script(
+            &exec,
+            run_as.as_ref().or_else(|| default_run_as.map(|r| &**r)),
+        )?;
 
         Ok(Script {
             id: state.next_id(IdKind::HookId),
@@ -144,6 +315,30 @@ impl Script {
             exec,
             priority: headers.preferences.priority(),
             parallel: headers.preferences.parallel(),
+            max_parallel: headers.preferences.max_parallel(),
+            sync: headers.preferences.sync(),
+            status_codes: headers
+                .preferences
+                .status_codes
+                .take()
+                .unwrap_or_default(),
+            rate_limit: headers.preferences.rate_limit.take(),
+            max_queued: headers.preferences.max_queued,
+            coalesce: headers.preferences.coalesce(),
+            coalesce_key: match headers.preferences.coalesce_key {
+                Some(ref key) => Some(CoalesceKey::parse(key)?),
+                None => None,
+            },
+            debounce: headers.preferences.debounce(),
+            group: headers.preferences.group.take(),
+            then: headers.preferences.then.take().unwrap_or_default(),
+            after_success: headers
+                .preferences
+                .after_success
+                .take()
+                .unwrap_or_default(),
+            run_as: run_as.map(Arc::new),
+            limits: headers.preferences.limits.take().unwrap_or_default(),
             providers: headers.providers,
         })
     }
@@ -154,14 +349,23 @@ impl Script {
     ) -> (RequestType, Option<Arc<Provider>>) {
         if !self.providers.is_empty() {
             // Check every provider if they're present
+            let mut reasons = Vec::new();
             for provider in &self.providers {
-                let result = provider.validate(req);
-
-                if result != RequestType::Invalid {
-                    return (result, Some(provider.clone()));
+                match provider.validate(req) {
+                    RequestType::Invalid(reason) => {
+                        reasons.push((provider.name().to_string(), reason));
+                    }
+                    result => return (result, Some(provider.clone())),
                 }
             }
-            (RequestType::Invalid, None)
+
+            // Avoid repeating the provider name if there is only one
+            let reason = if reasons.len() == 1 {
+                reasons.pop().unwrap().1
+            } else {
+                InvalidReason::NoProviderMatched(reasons)
+            };
+            (RequestType::Invalid(reason), None)
         } else {
             (RequestType::ExecuteHook, None)
         }
@@ -178,6 +382,76 @@ impl Script {
     pub fn priority(&self) -> isize {
         self.priority
     }
+
+    /// Get the names of all the scripts started after this one.
+    pub fn followups(&self) -> impl Iterator<Item = &str> {
+        self.then
+            .iter()
+            .chain(self.after_success.iter())
+            .map(|name| name.as_str())
+    }
+
+    /// Get the names of the scripts to start after a job of this script
+    /// ended.
+    pub fn followups_after(&self, success: bool) -> Vec<&str> {
+        let mut names =
+            self.then.iter().map(|name| name.as_str()).collect::<Vec<_>>();
+        if success {
+            names.extend(self.after_success.iter().map(|name| name.as_str()));
+        }
+        names
+    }
+
+    /// Get the user and the group the script is executed as, if they're
+    /// different from the default ones.
+    pub fn run_as(&self) -> Option<&Arc<RunAs>> {
+        self.run_as.as_ref()
+    }
+
+    /// Get the resource limits of the jobs, overriding the default ones.
+    pub fn limits(&self) -> &LimitsConfig {
+        &self.limits
+    }
+
+    /// Check if the request should wait for the script to complete, and
+    /// receive its output.
+    pub fn sync(&self) -> bool {
+        self.sync
+    }
+
+    /// Get the maximum number of valid requests accepted in an interval.
+    pub fn rate_limit(&self) -> Option<&RateLimitConfig> {
+        self.rate_limit.as_ref()
+    }
+
+    /// Get the key the jobs caused by the request are coalesced with, if
+    /// the jobs of this script should be coalesced.
+    pub fn coalesce_key(&self, req: &Request) -> Option<String> {
+        if !self.coalesce {
+            return None;
+        }
+
+        // Jobs without a value for the key are coalesced together
+        Some(match self.coalesce_key {
+            Some(ref key) => key.extract(req).unwrap_or_default(),
+            None => String::new(),
+        })
+    }
+
+    /// Get the HTTP status code to respond with after a synchronous run.
+    pub fn status_code(&self, output: &JobOutput) -> u16 {
+        if let Some(code) = output.exit_code {
+            if let Some(status) = self.status_codes.get(&code) {
+                return *status;
+            }
+        }
+
+        if output.success {
+            200
+        } else {
+            500
+        }
+    }
 }
 
 impl ScriptTrait for Script {
@@ -187,16 +461,41 @@ impl ScriptTrait for Script {
         self.id
     }
 
+    fn name(&self) -> &str {
+        &self.name
+    }
+
     fn can_be_parallel(&self) -> bool {
         self.parallel
     }
+
+    fn max_parallel(&self) -> Option<usize> {
+        self.max_parallel
+    }
+
+    fn max_queued(&self) -> Option<usize> {
+        self.max_queued
+    }
+
+    fn debounce(&self) -> Option<Duration> {
+        self.debounce
+    }
+
+    fn group(&self) -> Option<&str> {
+        self.group.as_deref()
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use common::prelude::*;
-    use requests::{Request, RequestType};
+    use requests::{InvalidReason, Request, RequestType};
     use scripts::test_utils::*;
+    use utils::testing::dummy_job_output;
+
+    use super::CoalesceKey;
 
     #[test]
     fn test_scripts_are_loaded_properly() {
@@ -305,6 +604,231 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_sync_scripts() {
+        test_wrapper(|env| {
+            env.create_script(
+                "sync.sh",
+                &[
+                    r#"#!/bin/bash"#,
+                    r#"## Fisher: {"sync": true, "status_codes": {"2": 404}}"#,
+                ],
+            )?;
+            env.create_script(
+                "invalid.sh",
+                &[
+                    r#"#!/bin/bash"#,
+                    r#"## Fisher: {"sync": true, "status_codes": {"2": 42}}"#,
+                ],
+            )?;
+
+            let script = env.load_script("sync.sh")?;
+            assert!(script.sync());
+
+            // The status code depends on the exit code
+            let mut output = dummy_job_output();
+            assert_eq!(script.status_code(&output), 200);
+            output.success = false;
+            output.exit_code = Some(1);
+            assert_eq!(script.status_code(&output), 500);
+            output.exit_code = Some(2);
+            assert_eq!(script.status_code(&output), 404);
+            output.exit_code = None;
+            output.signal = Some(9);
+            assert_eq!(script.status_code(&output), 500);
+
+            // Scripts aren't synchronous by default
+            env.create_script("async.sh", &[r#"#!/bin/bash"#])?;
+            assert!(!env.load_script("async.sh")?.sync());
+
+            // Invalid status codes are rejected
+            assert!(env.load_script("invalid.sh").is_err());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_limits() {
+        test_wrapper(|env| {
+            env.create_script(
+                "limited.sh",
+                &[
+                    r#"#!/bin/bash"#,
+                    r#"## Fisher: {"rate_limit": "5/1m", "max_queued": 1}"#,
+                ],
+            )?;
+            env.create_script(
+                "invalid.sh",
+                &[r#"#!/bin/bash"#, r#"## Fisher: {"rate_limit": "5/1/m"}"#],
+            )?;
+
+            let script = env.load_script("limited.sh")?;
+            let limit = script.rate_limit().unwrap();
+            assert_eq!(limit.allowed, 5);
+            assert_eq!(limit.interval.as_u64(), 60);
+            assert_eq!(script.max_queued(), Some(1));
+
+            // Scripts aren't limited by default
+            env.create_script("unlimited.sh", &[r#"#!/bin/bash"#])?;
+            let script = env.load_script("unlimited.sh")?;
+            assert!(script.rate_limit().is_none());
+            assert!(script.max_queued().is_none());
+            assert!(script.group().is_none());
+            assert!(script.max_parallel().is_none());
+
+            env.create_script(
+                "serial.sh",
+                &[r#"#!/bin/bash"#, r#"## Fisher: {"parallel": false}"#],
+            )?;
+            assert_eq!(env.load_script("serial.sh")?.max_parallel(), Some(1));
+
+            env.create_script(
+                "capped.sh",
+                &[r#"#!/bin/bash"#, r#"## Fisher: {"max_parallel": 3}"#],
+            )?;
+            assert_eq!(env.load_script("capped.sh")?.max_parallel(), Some(3));
+
+            env.create_script(
+                "zero.sh",
+                &[r#"#!/bin/bash"#, r#"## Fisher: {"max_parallel": 0}"#],
+            )?;
+            assert!(env.load_script("zero.sh").is_err());
+
+            env.create_script(
+                "grouped.sh",
+                &[r#"#!/bin/bash"#, r#"## Fisher: {"group": "database"}"#],
+            )?;
+            let script = env.load_script("grouped.sh")?;
+            assert_eq!(script.group(), Some("database"));
+
+            assert!(env.load_script("invalid.sh").is_err());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_followups() {
+        test_wrapper(|env| {
+            env.create_script(
+                "build.sh",
+                &[
+                    r#"#!/bin/bash"#,
+                    concat!(
+                        r#"## Fisher: {"then": ["notify.sh"], "#,
+                        r#""after_success": ["test.sh"]}"#,
+                    ),
+                ],
+            )?;
+
+            let script = env.load_script("build.sh")?;
+            assert_eq!(
+                script.followups().collect::<Vec<_>>(),
+                vec!["notify.sh", "test.sh"]
+            );
+            assert_eq!(
+                script.followups_after(true),
+                vec!["notify.sh", "test.sh"]
+            );
+            assert_eq!(script.followups_after(false), vec!["notify.sh"]);
+
+            // Scripts don't start other scripts by default
+            env.create_script("plain.sh", &[r#"#!/bin/bash"#])?;
+            let script = env.load_script("plain.sh")?;
+            assert_eq!(script.followups().count(), 0);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_coalesce() {
+        test_wrapper(|env| {
+            env.create_script(
+                "latest.sh",
+                &[r#"#!/bin/bash"#, r#"## Fisher: {"coalesce": "latest"}"#],
+            )?;
+            env.create_script(
+                "keyed.sh",
+                &[
+                    r#"#!/bin/bash"#,
+                    concat!(
+                        r#"## Fisher: {"debounce": "30s", "#,
+                        r#""coalesce_key": "json:/ref"}"#,
+                    ),
+                ],
+            )?;
+            env.create_script(
+                "invalid.sh",
+                &[r#"#!/bin/bash"#, r#"## Fisher: {"coalesce_key": "ref"}"#],
+            )?;
+
+            let mut req = dummy_web_request();
+            req.body = br#"{"ref": "refs/heads/master"}"#.to_vec();
+            let req = Request::Web(req);
+
+            // Without a key all the jobs are coalesced together
+            let latest = env.load_script("latest.sh")?;
+            assert_eq!(latest.coalesce_key(&req), Some(String::new()));
+            assert_eq!(latest.debounce(), None);
+
+            // Debounced scripts are coalesced too
+            let keyed = env.load_script("keyed.sh")?;
+            assert_eq!(keyed.debounce(), Some(Duration::from_secs(30)));
+            assert_eq!(
+                keyed.coalesce_key(&req),
+                Some("refs/heads/master".into())
+            );
+            let other = Request::Web(dummy_web_request());
+            assert_eq!(keyed.coalesce_key(&other), Some(String::new()));
+
+            // Setting only the key coalesces the jobs as well
+            env.create_script(
+                "key.sh",
+                &[
+                    r#"#!/bin/bash"#,
+                    r#"## Fisher: {"coalesce_key": "json:/ref"}"#,
+                ],
+            )?;
+            assert_eq!(
+                env.load_script("key.sh")?.coalesce_key(&req),
+                Some("refs/heads/master".into())
+            );
+
+            // Scripts aren't coalesced by default
+            env.create_script("plain.sh", &[r#"#!/bin/bash"#])?;
+            assert_eq!(env.load_script("plain.sh")?.coalesce_key(&req), None);
+
+            assert!(env.load_script("invalid.sh").is_err());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_coalesce_key_extract() {
+        let mut req = dummy_web_request();
+        req.params.append("branch", "master");
+        req.headers.append("X-Branch", "develop");
+        req.body = br#"{"a": {"b": "c", "d": 1}}"#.to_vec();
+        let req = Request::Web(req);
+
+        let extract = |key: &str| {
+            CoalesceKey::parse(key).unwrap().extract(&req)
+        };
+        assert_eq!(extract("param:branch"), Some("master".into()));
+        assert_eq!(extract("header:x-branch"), Some("develop".into()));
+        assert_eq!(extract("json:/a/b"), Some("c".into()));
+        assert_eq!(extract("json:/a/d"), Some("1".into()));
+        assert_eq!(extract("json:/missing"), None);
+        assert_eq!(extract("param:missing"), None);
+
+        for invalid in &["ref", "param:", "json:ref", "body:/ref"] {
+            assert!(CoalesceKey::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
     #[test]
     fn test_requests_can_be_validated_against_scripts() {
         test_wrapper(|env| {
@@ -351,10 +875,30 @@ mod tests {
 
             // Validate the request against the scripts
             assert!(single.validate(&req).0 == RequestType::ExecuteHook);
-            assert!(failing.validate(&req).0 == RequestType::Invalid);
             assert!(multiple1.validate(&req).0 == RequestType::ExecuteHook);
             assert!(multiple2.validate(&req).0 == RequestType::ExecuteHook);
 
+            // The reason of the failure is returned
+            assert_eq!(
+                failing.validate(&req).0,
+                RequestType::Invalid(InvalidReason::MissingSecret)
+            );
+
+            let mut req = dummy_web_request();
+            req.params.append("secret", "wrong");
+            let reason = InvalidReason::NoProviderMatched(vec![
+                ("Testing".into(), InvalidReason::WrongSecret),
+                ("Standalone".into(), InvalidReason::WrongSecret),
+            ]);
+            assert_eq!(
+                multiple1.validate(&Request::Web(req)).0,
+                RequestType::Invalid(reason.clone())
+            );
+            assert_eq!(
+                reason.to_string(),
+                "Testing: wrong secret; Standalone: wrong secret"
+            );
+
             Ok(())
         });
     }
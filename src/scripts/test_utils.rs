@@ -13,7 +13,6 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::collections::HashMap;
 use std::fs;
 use std::io::Write;
 use std::net::{IpAddr, Ipv4Addr};
@@ -26,6 +25,7 @@ use tempdir::TempDir;
 use common::prelude::*;
 use common::state::State;
 use scripts::Script;
+use utils::{Headers, Params};
 use web::WebRequest;
 
 
@@ -93,17 +93,18 @@ impl TestEnv {
 
     pub fn load_script(&self, name: &str) -> Result<Script> {
         let path = self.scripts_dir().join(name).to_str().unwrap().to_string();
-        Ok(Script::load(name.into(), path, &self.state)?)
+        Ok(Script::load(name.into(), path, &self.state, None)?)
     }
 }
 
 
 pub fn dummy_web_request() -> WebRequest {
     WebRequest {
-        headers: HashMap::new(),
-        params: HashMap::new(),
+        id: "0123456789abcdef".into(),
+        headers: Headers::new(),
+        params: Params::new(),
         source: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
-        body: String::new(),
+        body: Vec::new(),
     }
 }
 
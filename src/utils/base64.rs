@@ -0,0 +1,95 @@
+// Copyright (C) 2019 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use common::prelude::*;
+
+fn decode_char(input: &str, i: usize, byte: u8) -> Result<u32> {
+    Ok(match byte {
+        b'A'..=b'Z' => byte - b'A',
+        b'a'..=b'z' => byte - b'a' + 26,
+        b'0'..=b'9' => byte - b'0' + 52,
+        b'+' => 62,
+        b'/' => 63,
+        _ => {
+            return Err(ErrorKind::Base64InvalidChar(
+                input[i..].chars().next().unwrap(),
+            )
+            .into());
+        }
+    } as u32)
+}
+
+pub fn from_base64(input: &str) -> Result<Vec<u8>> {
+    // Padding is optional, but if it's present the length must be right
+    let trimmed = input.trim_end_matches('=');
+    let padding = input.len() - trimmed.len();
+    let expected_padding = (4 - trimmed.len() % 4) % 4;
+    if trimmed.len() % 4 == 1 || (padding != 0 && padding != expected_padding)
+    {
+        return Err(ErrorKind::Base64InvalidLength.into());
+    }
+
+    let mut result = Vec::with_capacity(trimmed.len() * 3 / 4);
+
+    let mut buffer: u32 = 0;
+    let mut pending: u8 = 0;
+    for (i, byte) in trimmed.bytes().enumerate() {
+        buffer = (buffer << 6) | decode_char(input, i, byte)?;
+        pending += 1;
+
+        if pending == 4 {
+            result.push((buffer >> 16) as u8);
+            result.push((buffer >> 8) as u8);
+            result.push(buffer as u8);
+
+            buffer = 0;
+            pending = 0;
+        }
+    }
+
+    // Flush the remaining partial group
+    match pending {
+        2 => result.push((buffer >> 4) as u8),
+        3 => {
+            result.push((buffer >> 10) as u8);
+            result.push((buffer >> 2) as u8);
+        }
+        _ => {}
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use common::prelude::*;
+
+    use super::from_base64;
+
+    #[test]
+    fn test_from_base64() {
+        assert_eq!(from_base64("").unwrap(), b"");
+        assert_eq!(from_base64("aGVsbG8=").unwrap(), b"hello");
+        assert_eq!(from_base64("aGVsbG8").unwrap(), b"hello");
+        assert_eq!(from_base64("aGVsbG8h").unwrap(), b"hello!");
+        assert_eq!(from_base64("aGk=").unwrap(), b"hi");
+        assert_eq!(from_base64("dXNlcjpwYXNz").unwrap(), b"user:pass");
+        assert_eq!(from_base64("+/8=").unwrap(), &[0xfb, 0xff]);
+        assert_err!(from_base64("aGVsb"), ErrorKind::Base64InvalidLength);
+        assert_err!(from_base64("aGk==="), ErrorKind::Base64InvalidLength);
+        assert_err!(from_base64("aGk=="), ErrorKind::Base64InvalidLength);
+        assert_err!(from_base64("aGVs*G8="), ErrorKind::Base64InvalidChar('*'));
+    }
+}
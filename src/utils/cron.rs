@@ -0,0 +1,222 @@
+// Copyright (C) 2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+use common::prelude::*;
+use utils::UtcDate;
+
+/// A schedule in the crontab format, evaluated in UTC.
+///
+/// Schedules are made of five fields (minute, hour, day of the month, month
+/// and day of the week), each one containing `*`, a number, a range like
+/// `1-5` or a list of them like `1,15`. Ranges and `*` can be followed by a
+/// step, like `*/15`. The `@hourly`, `@daily`, `@weekly`, `@monthly` and
+/// `@yearly` shortcuts are also supported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    source: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl CronSchedule {
+    pub fn parse(input: &str) -> Result<Self> {
+        CronSchedule::parse_inner(input).ok_or_else(|| {
+            ErrorKind::ProviderCronInvalidSchedule(input.into()).into()
+        })
+    }
+
+    fn parse_inner(input: &str) -> Option<Self> {
+        let expanded = match input.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" => "0 0 1 1 *",
+            other => other,
+        };
+
+        let fields = expanded.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 5 {
+            return None;
+        }
+
+        // Both 0 and 7 mean Sunday in the day of the week
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays & !(1 << 7)) | 1;
+        }
+
+        Some(CronSchedule {
+            source: input.into(),
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            any_day: fields[2].starts_with('*'),
+            any_weekday: fields[4].starts_with('*'),
+        })
+    }
+
+    /// Check if the schedule matches the minute of the date.
+    pub fn matches(&self, date: &UtcDate) -> bool {
+        let day = self.days & (1 << date.day) != 0;
+        let weekday = self.weekdays & (1 << date.weekday) != 0;
+
+        // Like cron, if both the day of the month and the day of the week
+        // are restricted, matching either of them is enough
+        let day_matches = if self.any_day || self.any_weekday {
+            day && weekday
+        } else {
+            day || weekday
+        };
+
+        self.minutes & (1 << date.minute) != 0
+            && self.hours & (1 << date.hour) != 0
+            && self.months & (1 << date.month) != 0
+            && day_matches
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+}
+
+/// Parse a field of the schedule, returning the bitmask of the values it
+/// matches.
+fn parse_field(field: &str, min: u32, max: u32) -> Option<u64> {
+    let mut result = 0;
+
+    for part in field.split(',') {
+        let (range, step) = match part.find('/') {
+            Some(pos) => (&part[..pos], part[pos + 1..].parse::<u32>().ok()?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return None;
+        }
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some(pos) = range.find('-') {
+            (
+                range[..pos].parse::<u32>().ok()?,
+                range[pos + 1..].parse::<u32>().ok()?,
+            )
+        } else {
+            let start = range.parse::<u32>().ok()?;
+            // A single value with a step goes until the end of the range
+            (start, if part.contains('/') { max } else { start })
+        };
+
+        if start < min || end > max || start > end {
+            return None;
+        }
+
+        let mut value = start;
+        while value <= end {
+            result |= 1 << value;
+            // Huge steps match only the start of the range
+            value = match value.checked_add(step) {
+                Some(next) => next,
+                None => break,
+            };
+        }
+    }
+
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use common::prelude::*;
+    use utils::UtcDate;
+
+    use super::CronSchedule;
+
+    fn date(secs: u64) -> UtcDate {
+        UtcDate::new(UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn test_parse() {
+        for valid in &[
+            "* * * * *",
+            "0 3 * * *",
+            "*/15 * * * *",
+            "0 9-17/2 * * 1-5",
+            "30 4 1,15 * 7",
+            "5/10 * * * *",
+            "@daily",
+        ] {
+            assert!(CronSchedule::parse(valid).is_ok(), "{}", valid);
+        }
+
+        for invalid in &[
+            "",
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+            "@often",
+        ] {
+            assert_err!(
+                CronSchedule::parse(invalid),
+                ErrorKind::ProviderCronInvalidSchedule(..)
+            );
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        let matches = |schedule: &str, secs| {
+            CronSchedule::parse(schedule).unwrap().matches(&date(secs))
+        };
+
+        // 2017-10-18T03:00:00Z, a Wednesday
+        let night = 1_508_295_600;
+        assert!(matches("0 3 * * *", night));
+        assert!(!matches("0 3 * * *", night + 60));
+        assert!(matches("*/15 * * * *", night + 45 * 60));
+        assert!(!matches("*/15 * * * *", night + 50 * 60));
+        assert!(matches("0/4294967295 * * * *", night));
+        assert!(!matches("0/4294967295 * * * *", night + 60));
+        assert!(matches("@daily", night - 3 * 3600));
+
+        // Days of the week, with both 0 and 7 meaning Sunday
+        assert!(matches("0 3 * * 1-5", night));
+        assert!(!matches("0 3 * * 0,6", night));
+        assert!(matches("0 3 * * 7", night + 4 * 86400));
+        assert!(matches("0 3 * * 0", night + 4 * 86400));
+
+        // Either the day of the month or the day of the week must match
+        assert!(matches("0 3 1 * 3", night));
+        assert!(matches("0 3 18 * 1", night));
+        assert!(!matches("0 3 1 * 1", night));
+        assert!(!matches("0 3 1 * *", night));
+    }
+}
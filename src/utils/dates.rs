@@ -0,0 +1,162 @@
+// Copyright (C) 2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static MONTHS: &[&str] = &[
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct",
+    "Nov", "Dec",
+];
+
+static WEEKDAYS: &[&str] = &["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+/// A point in time, split into its UTC calendar components.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct UtcDate {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub millis: u32,
+    /// The day of the week, starting from 0 for Sunday.
+    pub weekday: u32,
+}
+
+impl UtcDate {
+    pub fn new(time: SystemTime) -> Self {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let secs = since_epoch.as_secs();
+
+        // Convert the days since the epoch into a civil date, see
+        // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let days = (secs / 86400) as i64 + 719_468;
+        let era = days / 146_097;
+        let day_of_era = days - era * 146_097;
+        let year_of_era = (day_of_era - day_of_era / 1460
+            + day_of_era / 36524
+            - day_of_era / 146_096)
+            / 365;
+        let day_of_year = day_of_era
+            - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        UtcDate {
+            year,
+            month: month as u32,
+            day: day as u32,
+            hour: (secs % 86400 / 3600) as u32,
+            minute: (secs % 3600 / 60) as u32,
+            second: (secs % 60) as u32,
+            millis: since_epoch.subsec_millis(),
+            // The 1st of January 1970 was a Thursday
+            weekday: ((secs / 86400 + 4) % 7) as u32,
+        }
+    }
+
+    pub fn now() -> Self {
+        UtcDate::new(SystemTime::now())
+    }
+
+    /// Format the date as a RFC 3339 timestamp, with milliseconds.
+    pub fn rfc3339(&self) -> String {
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            self.year,
+            self.month,
+            self.day,
+            self.hour,
+            self.minute,
+            self.second,
+            self.millis,
+        )
+    }
+
+    /// Format the date as used by the Common Log Format.
+    pub fn clf(&self) -> String {
+        format!(
+            "{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000",
+            self.day,
+            MONTHS[(self.month - 1) as usize],
+            self.year,
+            self.hour,
+            self.minute,
+            self.second,
+        )
+    }
+
+    /// Format the date as used by the HTTP headers.
+    pub fn http(&self) -> String {
+        format!(
+            "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+            WEEKDAYS[self.weekday as usize],
+            self.day,
+            MONTHS[(self.month - 1) as usize],
+            self.year,
+            self.hour,
+            self.minute,
+            self.second,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use super::UtcDate;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_rfc3339() {
+        let rfc3339 = |time| UtcDate::new(time).rfc3339();
+
+        assert_eq!(rfc3339(at(0)), "1970-01-01T00:00:00.000Z");
+        assert_eq!(rfc3339(at(951_782_400)), "2000-02-29T00:00:00.000Z");
+        assert_eq!(rfc3339(at(1_508_330_096)), "2017-10-18T12:34:56.000Z");
+        assert_eq!(
+            rfc3339(at(1_508_330_096) + Duration::from_millis(42)),
+            "2017-10-18T12:34:56.042Z"
+        );
+    }
+
+    #[test]
+    fn test_clf() {
+        let clf = |time| UtcDate::new(time).clf();
+
+        assert_eq!(clf(at(0)), "01/Jan/1970:00:00:00 +0000");
+        assert_eq!(clf(at(1_508_330_096)), "18/Oct/2017:12:34:56 +0000");
+        assert_eq!(clf(at(1_514_764_799)), "31/Dec/2017:23:59:59 +0000");
+    }
+
+    #[test]
+    fn test_http() {
+        let http = |time| UtcDate::new(time).http();
+
+        assert_eq!(http(at(0)), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(http(at(1_508_330_096)), "Wed, 18 Oct 2017 12:34:56 GMT");
+    }
+}
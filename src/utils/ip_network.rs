@@ -0,0 +1,158 @@
+// Copyright (C) 2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+use std::result::Result as StdResult;
+use std::str::FromStr;
+
+use serde::de::{Deserialize, Deserializer, Error as DeError, Visitor};
+
+use common::prelude::*;
+
+/// A range of IP addresses, in the CIDR notation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    /// Check if the address is part of this network.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // Sockets listening on IPv6 receive IPv4 clients as mapped addresses
+        let ip = match ip {
+            IpAddr::V6(v6) => match v6.segments() {
+                [0, 0, 0, 0, 0, 0xffff, high, low] => IpAddr::V4(
+                    Ipv4Addr::from((u32::from(high) << 16) | u32::from(low)),
+                ),
+                _ => ip,
+            },
+            IpAddr::V4(..) => ip,
+        };
+
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix));
+                let mask = mask.unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix));
+                let mask = mask.unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<IpNetwork> {
+        let invalid = || Error::from(ErrorKind::InvalidIpNetwork(s.into()));
+
+        let mut parts = s.splitn(2, '/');
+        let addr: IpAddr =
+            parts.next().unwrap_or("").parse().map_err(|_| invalid())?;
+
+        // A single address is a network with only that address
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match parts.next() {
+            Some(prefix) => prefix.parse().map_err(|_| invalid())?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(invalid());
+        }
+
+        Ok(IpNetwork { addr, prefix })
+    }
+}
+
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+struct IpNetworkVisitor;
+
+impl<'de> Visitor<'de> for IpNetworkVisitor {
+    type Value = IpNetwork;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an IP address or a network in CIDR notation")
+    }
+
+    fn visit_str<E: DeError>(self, s: &str) -> StdResult<IpNetwork, E> {
+        s.parse().map_err(|e: Error| E::custom(e.to_string()))
+    }
+}
+
+impl<'de> Deserialize<'de> for IpNetwork {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> StdResult<IpNetwork, D::Error> {
+        deserializer.deserialize_str(IpNetworkVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::IpNetwork;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_ip_network() {
+        let parse = |s: &str| s.parse::<IpNetwork>().ok();
+
+        assert_eq!(parse("10.0.0.0/8").unwrap().to_string(), "10.0.0.0/8");
+        assert_eq!(parse("127.0.0.1").unwrap().to_string(), "127.0.0.1/32");
+        assert_eq!(parse("fd00::/8").unwrap().to_string(), "fd00::/8");
+        assert_eq!(parse("::1").unwrap().to_string(), "::1/128");
+
+        assert_eq!(parse("10.0.0.0/33"), None);
+        assert_eq!(parse("fd00::/129"), None);
+        assert_eq!(parse("10.0.0.0/a"), None);
+        assert_eq!(parse("localhost"), None);
+    }
+
+    #[test]
+    fn test_ip_network_contains() {
+        let net = "10.0.0.0/8".parse::<IpNetwork>().unwrap();
+        assert!(net.contains(ip("10.1.2.3")));
+        assert!(net.contains(ip("::ffff:10.1.2.3")));
+        assert!(!net.contains(ip("11.0.0.1")));
+        assert!(!net.contains(ip("fd00::1")));
+
+        let net = "fd00::/8".parse::<IpNetwork>().unwrap();
+        assert!(net.contains(ip("fd12::1")));
+        assert!(!net.contains(ip("fe80::1")));
+
+        let single = "127.0.0.1".parse::<IpNetwork>().unwrap();
+        assert!(single.contains(ip("127.0.0.1")));
+        assert!(!single.contains(ip("127.0.0.2")));
+
+        let all = "0.0.0.0/0".parse::<IpNetwork>().unwrap();
+        assert!(all.contains(ip("192.0.2.1")));
+    }
+}
@@ -0,0 +1,127 @@
+// Copyright (C) 2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::result::Result as StdResult;
+use std::str::FromStr;
+
+use serde::de::{Deserialize, Deserializer, Error as DeError, Visitor};
+
+use common::prelude::*;
+
+const UNIX_PREFIX: &str = "unix:";
+const SYSTEMD: &str = "systemd";
+
+/// An address the HTTP server can listen on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    /// A TCP socket, either IPv4 or IPv6.
+    Tcp(SocketAddr),
+    /// A Unix domain socket, at the provided path.
+    Unix(PathBuf),
+    /// The sockets passed by systemd with socket activation.
+    Systemd,
+}
+
+impl FromStr for ListenAddr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ListenAddr> {
+        if s == SYSTEMD {
+            Ok(ListenAddr::Systemd)
+        } else if let Some(path) = s.strip_prefix(UNIX_PREFIX) {
+            if path.is_empty() {
+                Err(ErrorKind::InvalidListenAddr(s.into()).into())
+            } else {
+                Ok(ListenAddr::Unix(path.into()))
+            }
+        } else {
+            s.parse()
+                .map(ListenAddr::Tcp)
+                .map_err(|_| ErrorKind::InvalidListenAddr(s.into()).into())
+        }
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ListenAddr::Tcp(ref addr) => write!(f, "{}", addr),
+            ListenAddr::Unix(ref path) => {
+                write!(f, "{}{}", UNIX_PREFIX, path.display())
+            }
+            ListenAddr::Systemd => write!(f, "{}", SYSTEMD),
+        }
+    }
+}
+
+struct ListenAddrVisitor;
+
+impl<'de> Visitor<'de> for ListenAddrVisitor {
+    type Value = ListenAddr;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a socket address, a Unix socket or `systemd`")
+    }
+
+    fn visit_str<E: DeError>(self, s: &str) -> StdResult<ListenAddr, E> {
+        s.parse().map_err(|e: Error| E::custom(e.to_string()))
+    }
+}
+
+impl<'de> Deserialize<'de> for ListenAddr {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> StdResult<ListenAddr, D::Error> {
+        deserializer.deserialize_str(ListenAddrVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ListenAddr;
+
+    #[test]
+    fn test_parse_listen_addr() {
+        let parse = |s: &str| s.parse::<ListenAddr>().ok();
+
+        assert_eq!(
+            parse("127.0.0.1:8000"),
+            Some(ListenAddr::Tcp("127.0.0.1:8000".parse().unwrap()))
+        );
+        assert_eq!(
+            parse("[::1]:8000"),
+            Some(ListenAddr::Tcp("[::1]:8000".parse().unwrap()))
+        );
+        assert_eq!(
+            parse("unix:/run/fisher.sock"),
+            Some(ListenAddr::Unix("/run/fisher.sock".into()))
+        );
+        assert_eq!(parse("systemd"), Some(ListenAddr::Systemd));
+
+        assert_eq!(parse("unix:"), None);
+        assert_eq!(parse("127.0.0.1"), None);
+        assert_eq!(parse("localhost:8000"), None);
+    }
+
+    #[test]
+    fn test_display_listen_addr() {
+        for addr in &["[::]:8000", "unix:/run/fisher.sock", "systemd"] {
+            assert_eq!(addr.parse::<ListenAddr>().unwrap().to_string(), *addr);
+        }
+    }
+}
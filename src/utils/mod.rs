@@ -20,13 +20,27 @@ pub mod testing;
 mod parse_env;
 
 mod net;
+mod base64;
+mod cron;
+mod dates;
 mod hex;
+mod ip_network;
+mod listen_addr;
+mod multi_map;
 mod parse_time;
+mod random;
 
 
 #[cfg(test)]
 pub use utils::parse_env::parse_env;
 
-pub use utils::net::parse_forwarded_for;
+pub use utils::net::{parse_forwarded, parse_forwarded_for};
+pub use utils::base64::from_base64;
+pub use utils::cron::CronSchedule;
+pub use utils::dates::UtcDate;
 pub use utils::hex::from_hex;
+pub use utils::ip_network::IpNetwork;
+pub use utils::listen_addr::ListenAddr;
+pub use utils::multi_map::{Headers, Params};
 pub use utils::parse_time::{parse_time, TimeString};
+pub use utils::random::random_id;
@@ -0,0 +1,168 @@
+// Copyright (C) 2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fmt;
+use std::iter::FromIterator;
+use std::marker::PhantomData;
+use std::ops::Index;
+
+/// How the keys of a `MultiMap` are compared.
+pub trait KeyComparison {
+    fn equal(a: &str, b: &str) -> bool;
+}
+
+/// Keys are equal only if they're exactly the same.
+pub struct CaseSensitive;
+
+impl KeyComparison for CaseSensitive {
+    fn equal(a: &str, b: &str) -> bool {
+        a == b
+    }
+}
+
+/// Keys are equal even if their ASCII case differs.
+pub struct CaseInsensitive;
+
+impl KeyComparison for CaseInsensitive {
+    fn equal(a: &str, b: &str) -> bool {
+        a.eq_ignore_ascii_case(b)
+    }
+}
+
+/// A map which can contain multiple values for the same key, preserving the
+/// order they were added in.
+pub struct MultiMap<C: KeyComparison> {
+    entries: Vec<(String, String)>,
+    _comparison: PhantomData<C>,
+}
+
+/// The headers of an HTTP request, looked up without considering the case.
+pub type Headers = MultiMap<CaseInsensitive>;
+
+/// The parameters in a query string.
+pub type Params = MultiMap<CaseSensitive>;
+
+impl<C: KeyComparison> MultiMap<C> {
+    pub fn new() -> Self {
+        MultiMap {
+            entries: Vec::new(),
+            _comparison: PhantomData,
+        }
+    }
+
+    /// Add a value to the key, keeping the existing ones.
+    pub fn append<K, V>(&mut self, key: K, value: V)
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.entries.push((key.into(), value.into()));
+    }
+
+    /// Get the first value of the key.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(k, _)| C::equal(k, key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Get all the values of the key, in the order they were added.
+    pub fn get_all<'a>(
+        &'a self,
+        key: &'a str,
+    ) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(k, _)| C::equal(k, key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+}
+
+impl<C: KeyComparison> Default for MultiMap<C> {
+    fn default() -> Self {
+        MultiMap::new()
+    }
+}
+
+impl<C: KeyComparison> Clone for MultiMap<C> {
+    fn clone(&self) -> Self {
+        MultiMap {
+            entries: self.entries.clone(),
+            _comparison: PhantomData,
+        }
+    }
+}
+
+impl<C: KeyComparison> fmt::Debug for MultiMap<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.entries.iter()).finish()
+    }
+}
+
+impl<'a, C: KeyComparison> Index<&'a str> for MultiMap<C> {
+    type Output = str;
+
+    fn index(&self, key: &'a str) -> &str {
+        self.get(key)
+            .unwrap_or_else(|| panic!("missing key in the map: {}", key))
+    }
+}
+
+impl<C: KeyComparison, K: Into<String>, V: Into<String>> FromIterator<(K, V)>
+    for MultiMap<C>
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = MultiMap::new();
+        for (key, value) in iter {
+            map.append(key, value);
+        }
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Headers, Params};
+
+    #[test]
+    fn test_multiple_values() {
+        let mut params = Params::new();
+        params.append("tag", "a");
+        params.append("other", "value");
+        params.append("tag", "b");
+
+        assert_eq!(params.get("tag"), Some("a"));
+        assert_eq!(params.get_all("tag").collect::<Vec<_>>(), vec!["a", "b"]);
+        assert_eq!(&params["other"], "value");
+        assert!(!params.contains_key("missing"));
+    }
+
+    #[test]
+    fn test_case_sensitivity() {
+        let mut headers = Headers::new();
+        headers.append("X-GitHub-Event", "push");
+        assert_eq!(headers.get("x-github-event"), Some("push"));
+        assert_eq!(&headers["X-GITHUB-EVENT"], "push");
+
+        let mut params = Params::new();
+        params.append("Secret", "a");
+        assert!(params.get("secret").is_none());
+    }
+}
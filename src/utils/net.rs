@@ -13,29 +13,72 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::collections::HashMap;
 use std::net::IpAddr;
 
 use common::prelude::*;
+use utils::Headers;
 
 
-pub type Headers = HashMap<String, String>;
+/// Parse the addresses in the `X-Forwarded-For` headers. Each address is
+/// parsed on its own, so the valid ones can be used even if others aren't.
+pub fn parse_forwarded_for(headers: &Headers) -> Vec<Result<IpAddr>> {
+    let mut result = vec![];
 
+    // Repeated headers are equivalent to a single one with all the values
+    for header in headers.get_all("X-Forwarded-For") {
+        // Convert everything to instances of IpAddr
+        for address in header.split(',') {
+            let ip = address.trim().parse::<IpAddr>();
+            result.push(ip.map_err(Error::from));
+        }
+    }
 
-pub fn parse_forwarded_for(headers: &Headers) -> Result<Vec<IpAddr>> {
-    let mut result = vec![];
+    result
+}
 
-    if let Some(header) = headers.get("X-Forwarded-For".into()) {
-        // Parse the header content
-        let splitted: Vec<&str> = header.split(',').collect();
 
-        // Convert everything to instances of IpAddr
-        for address in &splitted {
-            result.push(address.trim().parse::<IpAddr>()?);
+/// Parse the addresses in the `for` parameters of the `Forwarded` headers,
+/// as defined in RFC 7239. Each element is parsed on its own.
+pub fn parse_forwarded(headers: &Headers) -> Vec<Result<IpAddr>> {
+    let mut result = vec![];
+
+    for header in headers.get_all("Forwarded") {
+        // Each proxy adds an element, separated by commas
+        for element in header.split(',') {
+            let node = element
+                .split(';')
+                .filter_map(|pair| {
+                    let mut parts = pair.splitn(2, '=');
+                    let name = parts.next().unwrap_or("").trim();
+                    let value = parts.next()?.trim();
+                    if name.eq_ignore_ascii_case("for") {
+                        Some(value.trim_matches('"'))
+                    } else {
+                        None
+                    }
+                })
+                .next();
+
+            // Obfuscated and unknown nodes can't be used to identify clients
+            let ip = node.and_then(parse_forwarded_node).ok_or_else(|| {
+                ErrorKind::InvalidForwardedHeader(element.trim().into()).into()
+            });
+            result.push(ip);
         }
     }
 
-    Ok(result)
+    result
+}
+
+
+fn parse_forwarded_node(node: &str) -> Option<IpAddr> {
+    // IPv6 addresses are enclosed in brackets, and both kinds of addresses
+    // might be followed by the port
+    if let Some(rest) = node.strip_prefix('[') {
+        rest.split(']').next()?.parse().ok()
+    } else {
+        node.split(':').next()?.parse().ok()
+    }
 }
 
 
@@ -43,39 +86,95 @@ pub fn parse_forwarded_for(headers: &Headers) -> Result<Vec<IpAddr>> {
 mod tests {
     use std::net::IpAddr;
 
-    use super::{parse_forwarded_for, Headers};
+    use super::{parse_forwarded, parse_forwarded_for};
+    use common::prelude::*;
+    use utils::Headers;
+
+
+    fn all(result: Vec<Result<IpAddr>>) -> Result<Vec<IpAddr>> {
+        result.into_iter().collect()
+    }
 
 
     #[test]
     fn test_parse_forwarded_for() {
         // Test with no headers
         assert_eq!(
-            parse_forwarded_for(&Headers::new()).unwrap(),
+            all(parse_forwarded_for(&Headers::new())).unwrap(),
             Vec::<IpAddr>::new()
         );
 
         // Test with a single IP address
         let mut headers = Headers::new();
-        headers.insert("X-Forwarded-For".into(), "127.0.0.1".into());
+        headers.append("X-Forwarded-For", "127.0.0.1");
         assert_eq!(
-            parse_forwarded_for(&headers).unwrap(),
+            all(parse_forwarded_for(&headers)).unwrap(),
             vec!["127.0.0.1".parse::<IpAddr>().unwrap()]
         );
 
         // Test with multiple IP addresses
         let mut headers = Headers::new();
-        headers.insert("X-Forwarded-For".into(), "127.0.0.1, 10.0.0.1".into());
+        headers.append("X-Forwarded-For", "127.0.0.1, 10.0.0.1");
+        assert_eq!(
+            all(parse_forwarded_for(&headers)).unwrap(),
+            vec![
+                "127.0.0.1".parse::<IpAddr>().unwrap(),
+                "10.0.0.1".parse::<IpAddr>().unwrap(),
+            ]
+        );
+
+        // Test with repeated headers, in any case
+        let mut headers = Headers::new();
+        headers.append("X-Forwarded-For", "127.0.0.1, 10.0.0.1");
+        headers.append("x-forwarded-for", "10.0.0.2");
         assert_eq!(
-            parse_forwarded_for(&headers).unwrap(),
+            all(parse_forwarded_for(&headers)).unwrap(),
             vec![
                 "127.0.0.1".parse::<IpAddr>().unwrap(),
                 "10.0.0.1".parse::<IpAddr>().unwrap(),
+                "10.0.0.2".parse::<IpAddr>().unwrap(),
             ]
         );
 
-        // Test with a non-IP address
+        // Test with a non-IP address, which doesn't affect the other ones
         let mut headers = Headers::new();
-        headers.insert("X-Forwarded-For".into(), "127.0.0.1, hey, 10.0.0.1".into());
-        assert!(parse_forwarded_for(&headers).is_err());
+        headers.append("X-Forwarded-For", "127.0.0.1, hey, 10.0.0.1");
+        let result = parse_forwarded_for(&headers);
+        assert_eq!(result.len(), 3);
+        assert!(result[0].is_ok());
+        assert!(result[1].is_err());
+        assert!(result[2].is_ok());
+    }
+
+    #[test]
+    fn test_parse_forwarded() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        // Test with no headers
+        assert!(all(parse_forwarded(&Headers::new())).unwrap().is_empty());
+
+        // Test with all the kinds of addresses, in multiple headers
+        let mut headers = Headers::new();
+        headers.append(
+            "Forwarded",
+            "for=192.0.2.60;proto=http;by=203.0.113.43, \
+             For=\"[2001:db8:cafe::17]:4711\"",
+        );
+        headers.append("forwarded", "proto=https;for=\"192.0.2.61:8080\"");
+        assert_eq!(
+            all(parse_forwarded(&headers)).unwrap(),
+            vec![
+                ip("192.0.2.60"),
+                ip("2001:db8:cafe::17"),
+                ip("192.0.2.61"),
+            ]
+        );
+
+        // Unknown or obfuscated nodes, and missing nodes, are errors
+        for value in &["for=unknown", "for=_hidden", "proto=http", "for="] {
+            let mut headers = Headers::new();
+            headers.append("Forwarded", *value);
+            assert!(all(parse_forwarded(&headers)).is_err(), "{}", value);
+        }
     }
 }
@@ -70,7 +70,7 @@ fn parse_time_inner(input: &str) -> Result<usize> {
     Ok(result)
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TimeString(u64);
 
 impl TimeString {
@@ -13,11 +13,11 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::collections::HashMap;
 use std::fs;
 use std::net::{IpAddr, Ipv4Addr};
 use std::path::PathBuf;
-use std::sync::{mpsc, Arc};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
 
 use hyper::client as hyper;
 use hyper::method::Method;
@@ -26,10 +26,12 @@ use tempdir::TempDir;
 use common::config::{HttpConfig, RateLimitConfig};
 use common::prelude::*;
 use common::state::State;
-use common::structs::HealthDetails;
+use common::structs::{HealthDetails, JobDetails, JobStatus};
 
+use requests::Request;
 use scripts::{Blueprint as HooksBlueprint, Repository as Hooks};
 use scripts::{Job, JobOutput};
+use utils::{Headers, ListenAddr, Params};
 use web::{WebApp, WebRequest};
 
 #[macro_export]
@@ -70,10 +72,11 @@ macro_rules! hashmap {
 
 pub fn dummy_web_request() -> WebRequest {
     WebRequest {
-        headers: HashMap::new(),
-        params: HashMap::new(),
+        id: "0123456789abcdef".into(),
+        headers: Headers::new(),
+        params: Params::new(),
         source: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
-        body: String::new(),
+        body: Vec::new(),
     }
 }
 
@@ -81,15 +84,22 @@ pub fn dummy_job_output() -> JobOutput {
     JobOutput {
         stdout: "hello world".into(),
         stderr: "something happened".into(),
+        response: None,
 
         success: true,
         exit_code: Some(0),
         signal: None,
+        limit_exceeded: None,
 
         script_name: "test".into(),
+        request_id: "0123456789abcdef".into(),
         request_ip: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+        request_kind: "webhook",
 
         trigger_status_hooks: true,
+
+        request: Box::new(Request::Web(dummy_web_request())),
+        provider: None,
     }
 }
 
@@ -189,6 +199,28 @@ pub fn sample_hooks() -> PathBuf {
         r#"echo "triggered!""#
     );
 
+    create_hook!(
+        tempdir,
+        "sync.sh",
+        r#"#!/bin/bash"#,
+        r#"## Fisher: {"sync": true, "status_codes": {"1": 422}}"#,
+        r#"## Fisher-Testing: {}"#,
+        r#"echo "Hello world""#,
+        r#"if [[ -n "${FISHER_TESTING_ENV}" ]]; then"#,
+        r#"    echo "${FISHER_TESTING_ENV}" > "${FISHER_RESPONSE_BODY}""#,
+        r#"fi"#,
+        r#"exit 1"#
+    );
+
+    create_hook!(
+        tempdir,
+        "limited.sh",
+        r#"#!/bin/bash"#,
+        r#"## Fisher: {"rate_limit": "2/1m"}"#,
+        r#"## Fisher-Testing: {}"#,
+        r#"echo "limited""#
+    );
+
     fs::create_dir(&tempdir.join("sub")).unwrap();
     create_hook!(
         tempdir.join("sub"),
@@ -203,6 +235,7 @@ pub fn sample_hooks() -> PathBuf {
 
 pub enum ProcessorApiCall {
     Queue(Job, isize),
+    JobDetails(String),
     HealthDetails,
     Cleanup,
     Lock,
@@ -211,12 +244,35 @@ pub enum ProcessorApiCall {
 
 pub struct FakeProcessorApi {
     sender: mpsc::Sender<ProcessorApiCall>,
+    job_status: Arc<Mutex<JobStatus>>,
 }
 
 impl ProcessorApiTrait<Hooks> for FakeProcessorApi {
-    fn queue(&self, job: Job, priority: isize) -> Result<()> {
+    fn queue(&self, job: Job, priority: isize) -> Result<JobDetails> {
+        let script = job.script_name().to_string();
         self.sender.send(ProcessorApiCall::Queue(job, priority))?;
-        Ok(())
+        Ok(JobDetails {
+            id: "0123456789abcdef".into(),
+            script,
+            status: JobStatus::Queued,
+            queue_position: Some(0),
+        })
+    }
+
+    fn job_details(&self, id: &str) -> Result<Option<JobDetails>> {
+        self.sender.send(ProcessorApiCall::JobDetails(id.into()))?;
+
+        // Only the ID returned by queue exists
+        if id == "0123456789abcdef" {
+            Ok(Some(JobDetails {
+                id: id.into(),
+                script: "example.sh".into(),
+                status: *self.job_status.lock()?,
+                queue_position: None,
+            }))
+        } else {
+            Ok(None)
+        }
     }
 
     fn health_details(&self) -> Result<HealthDetails> {
@@ -225,6 +281,8 @@ impl ProcessorApiTrait<Hooks> for FakeProcessorApi {
             queued_jobs: 1,
             busy_threads: 2,
             max_threads: 3,
+            loaded_scripts: 4,
+            ..HealthDetails::default()
         })
     }
 
@@ -246,48 +304,81 @@ impl ProcessorApiTrait<Hooks> for FakeProcessorApi {
 
 pub struct WebAppInstance {
     inst: WebApp<FakeProcessorApi>,
+    hooks: Arc<Hooks>,
+    state: Arc<State>,
 
     url: String,
     client: hyper::Client,
 
+    processor_api_sender: mpsc::Sender<ProcessorApiCall>,
     processor_api_call: mpsc::Receiver<ProcessorApiCall>,
+    job_status: Arc<Mutex<JobStatus>>,
 }
 
 impl WebAppInstance {
-    pub fn new(hooks: Arc<Hooks>, health: bool, behind_proxies: u8) -> Self {
+    pub fn new(
+        hooks: Arc<Hooks>,
+        config: &HttpConfig,
+        state: Arc<State>,
+    ) -> Self {
         let (chan_send, chan_recv) = mpsc::channel();
-        let fake_processor = FakeProcessorApi { sender: chan_send };
+        let job_status = Arc::new(Mutex::new(JobStatus::Running));
+        let fake_processor = FakeProcessorApi {
+            sender: chan_send.clone(),
+            job_status: job_status.clone(),
+        };
 
         // Start the web server
         // Create a new instance of WebApp
         let inst = WebApp::new(
-            hooks,
-            &HttpConfig {
-                behind_proxies,
-                bind: "127.0.0.1:0".parse().unwrap(),
-                rate_limit: RateLimitConfig {
-                    allowed: ::std::u64::MAX,
-                    interval: ::std::u64::MAX.into(),
-                },
-                health_endpoint: health,
-            },
+            hooks.clone(),
+            config,
             fake_processor,
-        )
-        .unwrap();
+            state.clone(),
+        ).unwrap();
 
         // Create the HTTP client
-        let url = format!("http://{}", inst.addr());
+        let url = format!("http://{}", inst.addrs()[0]);
         let client = hyper::Client::new();
 
         WebAppInstance {
             inst,
+            hooks,
+            state,
 
             url,
             client,
+            processor_api_sender: chan_send,
             processor_api_call: chan_recv,
+            job_status,
         }
     }
 
+    pub fn reload(&mut self, config: &HttpConfig) -> Result<()> {
+        let fake_processor = FakeProcessorApi {
+            sender: self.processor_api_sender.clone(),
+            job_status: self.job_status.clone(),
+        };
+        self.inst.reload(
+            self.hooks.clone(),
+            config,
+            fake_processor,
+            self.state.clone(),
+        )?;
+
+        self.url = format!("http://{}", self.inst.addrs()[0]);
+        Ok(())
+    }
+
+    pub fn addrs(&self) -> &[ListenAddr] {
+        self.inst.addrs()
+    }
+
+    /// Change the status of the job returned by the fake processor.
+    pub fn set_job_status(&self, status: JobStatus) {
+        *self.job_status.lock().unwrap() = status;
+    }
+
     pub fn request(
         &mut self,
         method: Method,
@@ -305,6 +396,12 @@ impl WebAppInstance {
         }
     }
 
+    pub fn wait_processor_input(&self) -> ProcessorApiCall {
+        self.processor_api_call
+            .recv_timeout(Duration::from_secs(5))
+            .expect("no processor input received")
+    }
+
     pub fn lock(&self) {
         self.inst.lock();
     }
@@ -319,6 +416,7 @@ impl WebAppInstance {
 }
 
 pub struct TestingEnv {
+    state: Arc<State>,
     hooks: Arc<Hooks>,
     remove_dirs: Vec<String>,
 }
@@ -333,6 +431,7 @@ impl TestingEnv {
         hooks_blueprint.collect_path(&hooks_dir, true).unwrap();
 
         TestingEnv {
+            state,
             hooks: Arc::new(hooks_blueprint.repository()),
             remove_dirs: vec![hooks_dir],
         }
@@ -349,11 +448,28 @@ impl TestingEnv {
 
     // WEB TESTING
 
+    pub fn web_config(&self, health: bool, behind_proxies: u8) -> HttpConfig {
+        HttpConfig {
+            behind_proxies,
+            bind: vec!["127.0.0.1:0".parse().unwrap()],
+            rate_limit: RateLimitConfig {
+                allowed: ::std::u64::MAX,
+                interval: ::std::u64::MAX.into(),
+            },
+            health_endpoint: health,
+            ..HttpConfig::default()
+        }
+    }
+
     pub fn start_web(
         &self,
         health: bool,
         behind_proxies: u8,
     ) -> WebAppInstance {
-        WebAppInstance::new(self.hooks.clone(), health, behind_proxies)
+        self.start_web_with_config(&self.web_config(health, behind_proxies))
+    }
+
+    pub fn start_web_with_config(&self, config: &HttpConfig) -> WebAppInstance {
+        WebAppInstance::new(self.hooks.clone(), config, self.state.clone())
     }
 }
@@ -0,0 +1,266 @@
+// Copyright (C) 2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use common::config::{AccessLogConfig, AccessLogFormat};
+use common::prelude::*;
+use requests::Request;
+use utils::UtcDate;
+use web::protocol;
+
+/// A request served by the HTTP server, as recorded in the access log.
+#[derive(Debug, Clone)]
+pub struct AccessLogEntry {
+    source: IpAddr,
+    request_line: String,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    status: u16,
+    size: usize,
+    latency: Duration,
+}
+
+impl AccessLogEntry {
+    pub fn new(
+        origin: &protocol::Request,
+        req: &Request,
+        status: u16,
+        size: usize,
+        latency: Duration,
+    ) -> Self {
+        // The query string is not logged, since it might contain secrets
+        let path = origin.url().split('?').next().unwrap_or("");
+        let request_line = format!(
+            "{} {} HTTP/{}",
+            origin.method(),
+            path,
+            origin.http_version(),
+        );
+
+        let header = |name| origin.header(name).map(str::to_string);
+
+        AccessLogEntry {
+            // This is the source IP after the proxies are taken into account
+            source: req
+                .web()
+                .map(|req| req.source)
+                .unwrap_or_else(|_| {
+                    origin
+                        .remote_addr()
+                        .map(|addr| addr.ip())
+                        .unwrap_or_else(|| IpAddr::V4(Ipv4Addr::LOCALHOST))
+                }),
+            request_line,
+            referer: header("Referer"),
+            user_agent: header("User-Agent"),
+            status,
+            size,
+            latency,
+        }
+    }
+
+    fn format(&self, format: AccessLogFormat, date: &UtcDate) -> String {
+        let mut line = format!(
+            "{} - - [{}] \"{}\" {} {}",
+            self.source,
+            date.clf(),
+            escape(&self.request_line),
+            self.status,
+            if self.size == 0 {
+                "-".to_string()
+            } else {
+                self.size.to_string()
+            },
+        );
+
+        if format == AccessLogFormat::Combined {
+            // The latency is appended in microseconds, like Apache's %D,
+            // while the Common Log Format is kept as it is
+            let micros = self.latency.as_secs() * 1_000_000
+                + u64::from(self.latency.subsec_micros());
+            line.push_str(&format!(
+                " \"{}\" \"{}\" {}",
+                escape_or_dash(&self.referer),
+                escape_or_dash(&self.user_agent),
+                micros,
+            ));
+        }
+
+        line
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn escape_or_dash(value: &Option<String>) -> String {
+    value.as_ref().map(|v| escape(v)).unwrap_or_else(|| "-".into())
+}
+
+fn open(path: &Path) -> Result<File> {
+    Ok(OpenOptions::new().create(true).append(true).open(path)?)
+}
+
+/// The access log of the HTTP server.
+#[derive(Debug)]
+pub struct AccessLog {
+    path: PathBuf,
+    format: AccessLogFormat,
+    file: Mutex<File>,
+}
+
+impl AccessLog {
+    /// Open the access log, returning `None` if it's disabled.
+    pub fn new(config: &AccessLogConfig) -> Result<Option<Self>> {
+        let path = if let Some(ref path) = config.path {
+            PathBuf::from(path)
+        } else {
+            return Ok(None);
+        };
+
+        Ok(Some(AccessLog {
+            file: Mutex::new(open(&path)?),
+            path,
+            format: config.format,
+        }))
+    }
+
+    /// Reopen the file, for example after it was rotated.
+    pub fn reopen(&self) -> Result<()> {
+        let file = open(&self.path)?;
+        *self.file.lock()? = file;
+        Ok(())
+    }
+
+    /// Record a served request in the access log.
+    pub fn record(&self, entry: &AccessLogEntry) {
+        let line = entry.format(self.format, &UtcDate::now());
+
+        let result = self
+            .file
+            .lock()
+            .map_err(Error::from)
+            .and_then(|mut file| Ok(writeln!(file, "{}", line)?));
+        if let Err(err) = result {
+            err.to_event()
+                .field("access_log", self.path.display())
+                .log();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::Read;
+    use std::net::IpAddr;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use tempdir::TempDir;
+
+    use common::config::{AccessLogConfig, AccessLogFormat};
+    use utils::UtcDate;
+
+    use super::{AccessLog, AccessLogEntry};
+
+    fn entry() -> AccessLogEntry {
+        AccessLogEntry {
+            source: "10.0.0.1".parse::<IpAddr>().unwrap(),
+            request_line: "POST /hook/deploy.sh HTTP/1.1".into(),
+            referer: None,
+            user_agent: Some("GitHub-Hookshot/\"abc\"".into()),
+            status: 200,
+            size: 15,
+            latency: Duration::from_millis(12),
+        }
+    }
+
+    fn date() -> UtcDate {
+        UtcDate::new(UNIX_EPOCH + Duration::from_secs(1_508_330_096))
+    }
+
+    #[test]
+    fn test_format_common() {
+        assert_eq!(
+            entry().format(AccessLogFormat::Common, &date()),
+            concat!(
+                "10.0.0.1 - - [18/Oct/2017:12:34:56 +0000] ",
+                "\"POST /hook/deploy.sh HTTP/1.1\" 200 15",
+            )
+        );
+
+        let mut empty = entry();
+        empty.size = 0;
+        assert!(
+            empty
+                .format(AccessLogFormat::Common, &date())
+                .ends_with(" 200 -")
+        );
+    }
+
+    #[test]
+    fn test_format_combined() {
+        assert_eq!(
+            entry().format(AccessLogFormat::Combined, &date()),
+            concat!(
+                "10.0.0.1 - - [18/Oct/2017:12:34:56 +0000] ",
+                "\"POST /hook/deploy.sh HTTP/1.1\" 200 15 ",
+                "\"-\" \"GitHub-Hookshot/\\\"abc\\\"\" 12000",
+            )
+        );
+    }
+
+    #[test]
+    fn test_reopen() {
+        let dir = TempDir::new("fisher").unwrap();
+        let path = dir.path().join("access.log");
+        let rotated = dir.path().join("access.log.1");
+
+        // The access log is disabled without a path
+        let log = AccessLog::new(&AccessLogConfig::default()).unwrap();
+        assert!(log.is_none());
+
+        let log = AccessLog::new(&AccessLogConfig {
+            path: Some(path.to_str().unwrap().into()),
+            format: AccessLogFormat::Common,
+        }).unwrap().unwrap();
+
+        let read = |path| {
+            let mut content = String::new();
+            File::open(path)
+                .unwrap()
+                .read_to_string(&mut content)
+                .unwrap();
+            content
+        };
+
+        // Rotate the log, and ensure new entries go to the new file only
+        log.record(&entry());
+        fs::rename(&path, &rotated).unwrap();
+        log.reopen().unwrap();
+        log.record(&entry());
+        log.record(&entry());
+
+        assert_eq!(read(&rotated).lines().count(), 1);
+        assert_eq!(read(&path).lines().count(), 2);
+    }
+}
@@ -14,17 +14,28 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::net::IpAddr;
-use std::sync::{Arc, Mutex};
+use std::sync::{mpsc, Arc, Mutex};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
+use serde_json;
+
+use common::config::HttpConfig;
+use common::logging;
 use common::prelude::*;
-use common::config::RateLimitConfig;
+use common::metrics::RejectReason;
+use common::state::State;
+use common::structs::{HealthDetails, JobDetails, JobStatus};
 
 use requests::{Request, RequestType};
-use scripts::{Repository, Job};
-use web::rate_limits::RateLimiter;
+use scripts::{Repository, Job, JobOutput, Script};
+use web::rate_limits::{RateLimiter, ScriptRateLimits};
 use web::responses::Response;
 
+/// The seconds clients are told to wait before retrying when the queue of a
+/// script is full, if the script never ran.
+const QUEUE_FULL_RETRY_AFTER: u64 = 60;
+
 
 #[derive(Clone)]
 pub struct WebApi<A: ProcessorApiTrait<Repository>> {
@@ -32,8 +43,13 @@ pub struct WebApi<A: ProcessorApiTrait<Repository>> {
     hooks: Arc<Repository>,
     locked: Arc<AtomicBool>,
     limiter: Arc<Mutex<RateLimiter<IpAddr>>>,
+    script_limits: Arc<Mutex<ScriptRateLimits>>,
+    state: Arc<State>,
 
     health_enabled: bool,
+    metrics_enabled: bool,
+    sync_timeout: Duration,
+    verbose_errors: bool,
 }
 
 impl<A: ProcessorApiTrait<Repository>> WebApi<A> {
@@ -41,25 +57,31 @@ impl<A: ProcessorApiTrait<Repository>> WebApi<A> {
         processor: A,
         hooks: Arc<Repository>,
         locked: Arc<AtomicBool>,
-        rate_limit_config: &RateLimitConfig,
-        health_enabled: bool,
+        state: Arc<State>,
+        limiter: Arc<Mutex<RateLimiter<IpAddr>>>,
+        script_limits: Arc<Mutex<ScriptRateLimits>>,
+        config: &HttpConfig,
     ) -> Self {
-        let limiter = Arc::new(Mutex::new(RateLimiter::new(
-            rate_limit_config.allowed,
-            rate_limit_config.interval.as_u64(),
-        )));
-
         WebApi {
             processor: Arc::new(Mutex::new(processor)),
-            hooks, locked, limiter, health_enabled,
+            hooks, locked, limiter, script_limits, state,
+
+            health_enabled: config.health_endpoint,
+            metrics_enabled: config.metrics_endpoint,
+            sync_timeout: Duration::from_secs(config.sync_timeout.as_u64()),
+            verbose_errors: config.verbose_errors,
         }
     }
 
     pub fn process_hook(&self, req: &Request, args: Vec<String>) -> Response {
         let hook_name = &args[0];
+        let metrics = self.state.metrics();
+
+        metrics.webhook_received();
 
         // Don't process hooks if the web api is locked
         if self.locked.load(Ordering::Relaxed) {
+            metrics.webhook_rejected(RejectReason::Locked);
             return Response::Unavailable;
         }
 
@@ -67,6 +89,7 @@ impl<A: ProcessorApiTrait<Repository>> WebApi<A> {
         if let Ok(r) = req.web() {
             let limited = self.limiter.lock().unwrap().is_limited(&r.source);
             if let Some(until) = limited {
+                metrics.webhook_rejected(RejectReason::RateLimited);
                 return Response::TooManyRequests(until);
             }
         }
@@ -76,44 +99,226 @@ impl<A: ProcessorApiTrait<Repository>> WebApi<A> {
         if let Some(found) = self.hooks.get_by_name(hook_name) {
             hook = found;
         } else {
+            metrics.webhook_rejected(RejectReason::NotFound);
             return Response::NotFound;
         }
 
         // Validate the hook
         let (request_type, provider) = hook.validate(req);
+        let provider_name = provider.as_ref().map(|p| p.name().to_string());
 
         // Change behavior based on the request type
         match request_type {
             // Don't do anything if it's only a ping
-            RequestType::Ping => Response::Ok,
+            RequestType::Ping => Response::HookAccepted {
+                provider: provider_name,
+                job: None,
+            },
 
             // Queue a job if the hook should be executed
             RequestType::ExecuteHook => {
-                let job = Job::new(hook.clone(), provider, req.clone());
-                self.processor
-                    .lock()
-                    .unwrap()
-                    .queue(job, hook.priority())
-                    .unwrap();
-
-                Response::Ok
+                // Scripts can limit how often they're executed
+                if let Some(limit) = hook.rate_limit() {
+                    let limited = self.script_limits.lock().unwrap().acquire(
+                        hook.name(),
+                        limit.allowed,
+                        limit.interval.as_u64(),
+                    );
+                    if let Some(until) = limited {
+                        metrics.webhook_rejected(RejectReason::RateLimited);
+                        return Response::TooManyRequests(until);
+                    }
+                }
+
+                let mut job = Job::new(hook.clone(), provider, req.clone());
+
+                // Synchronous scripts send their output back to the request
+                let mut output = None;
+                if hook.sync() {
+                    let (output_send, output_recv) = mpsc::channel();
+                    job = job.respond_to(output_send);
+                    output = Some(output_recv);
+                }
+
+                let result =
+                    self.processor.lock().unwrap().queue(job, hook.priority());
+
+                // Requests whose job wasn't queued don't count towards the
+                // rate limit of the script
+                if result.is_err() && hook.rate_limit().is_some() {
+                    self.script_limits.lock().unwrap().release(hook.name());
+                }
+                let details = match result {
+                    Ok(details) => details,
+                    Err(Error(ErrorKind::QueueFull(..), _)) => {
+                        metrics.webhook_rejected(RejectReason::QueueFull);
+
+                        // Clients can retry once a job of the script is
+                        // likely to be done, or later if it never ran
+                        let retry_after = metrics
+                            .average_duration(hook.name())
+                            .map(|average| average.as_secs() + 1)
+                            .unwrap_or(QUEUE_FULL_RETRY_AFTER);
+                        return Response::QueueFull(Duration::from_secs(
+                            retry_after,
+                        ));
+                    }
+                    Err(Error(ErrorKind::QueueOverflow, _)) => {
+                        metrics.webhook_rejected(RejectReason::QueueOverflow);
+                        return Response::QueueOverflow;
+                    }
+                    Err(error) => {
+                        error
+                            .to_event()
+                            .field("request_id", req.id())
+                            .field("script", hook.name())
+                            .log();
+                        return Response::InternalError;
+                    }
+                };
+                metrics.webhook_executed(hook.name(), provider_name.as_deref());
+
+                if let Some(output) = output {
+                    self.wait_output(&hook, &details, output)
+                } else {
+                    Response::HookAccepted {
+                        provider: provider_name,
+                        job: Some(details),
+                    }
+                }
             },
 
-            RequestType::Invalid => {
+            RequestType::Invalid(reason) => {
                 // Increment the limits for the user
                 if let Ok(r) = req.web() {
                     self.limiter.lock().unwrap().increment(r.source);
                 }
 
-                Response::Forbidden
+                logging::warning("webhook rejected")
+                    .field("request_id", req.id())
+                    .field("script", hook.name())
+                    .field("reason", &reason)
+                    .log();
+
+                metrics.webhook_rejected(RejectReason::Forbidden);
+
+                // The reason is shown to the client only if it's allowed,
+                // since it might help attackers guessing the secrets
+                if self.verbose_errors {
+                    Response::Rejected(Some(reason.to_string()))
+                } else {
+                    Response::Rejected(None)
+                }
             },
         }
     }
 
+    pub fn get_job(&self, req: &Request, args: Vec<String>) -> Response {
+        let details = self.processor.lock().unwrap().job_details(&args[0]);
+
+        match details {
+            Ok(Some(details)) => Response::JobStatus(details),
+            Ok(None) => Response::NotFound,
+            Err(error) => {
+                error.to_event().field("request_id", req.id()).log();
+                Response::InternalError
+            }
+        }
+    }
+
+    fn wait_output(
+        &self,
+        hook: &Script,
+        job: &JobDetails,
+        output: mpsc::Receiver<JobOutput>,
+    ) -> Response {
+        match output.recv_timeout(self.sync_timeout) {
+            Ok(output) => {
+                let status = hook.status_code(&output);
+                if let Some(response) = output.response {
+                    // Scripts might not write valid JSON in the file, which
+                    // is then returned as plain text
+                    let json = serde_json::from_str::<serde_json::Value>(
+                        &response,
+                    ).is_ok();
+                    Response::ScriptOutput {
+                        status,
+                        body: response,
+                        json,
+                    }
+                } else {
+                    Response::ScriptOutput {
+                        status,
+                        body: output.stdout,
+                        json: false,
+                    }
+                }
+            },
+
+            // The job is still running, but the client can't wait anymore
+            Err(mpsc::RecvTimeoutError::Timeout) => Response::GatewayTimeout,
+
+            // The job was removed from the queue before being executed, or
+            // it couldn't be executed at all
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                let details =
+                    self.processor.lock().unwrap().job_details(&job.id);
+                match details {
+                    Ok(Some(JobDetails {
+                        status: JobStatus::Superseded,
+                        ..
+                    })) => Response::JobSuperseded,
+                    Ok(Some(JobDetails {
+                        status: JobStatus::Dropped,
+                        ..
+                    })) => Response::JobDropped,
+                    _ => Response::InternalError,
+                }
+            },
+        }
+    }
+
+    fn health_details(&self) -> HealthDetails {
+        let mut details =
+            self.processor.lock().unwrap().health_details().unwrap();
+
+        // The web api is locked independently from the processor
+        details.locked |= self.locked.load(Ordering::Relaxed);
+
+        details
+    }
+
     pub fn get_health(&self, _req: &Request, _args: Vec<String>) -> Response {
         if self.health_enabled {
-            Response::HealthStatus(
-                self.processor.lock().unwrap().health_details().unwrap(),
+            Response::HealthStatus(self.health_details())
+        } else {
+            Response::Forbidden
+        }
+    }
+
+    pub fn get_live(&self, _req: &Request, _args: Vec<String>) -> Response {
+        if self.health_enabled {
+            Response::Ok
+        } else {
+            Response::Forbidden
+        }
+    }
+
+    pub fn get_ready(&self, _req: &Request, _args: Vec<String>) -> Response {
+        if self.health_enabled {
+            Response::Readiness(self.health_details())
+        } else {
+            Response::Forbidden
+        }
+    }
+
+    pub fn get_metrics(&self, _req: &Request, _args: Vec<String>) -> Response {
+        if self.metrics_enabled {
+            let health = self.health_details();
+            let rate_limited = self.limiter.lock().unwrap().len();
+
+            Response::Metrics(
+                self.state.metrics().render(&health, rate_limited),
             )
         } else {
             Response::Forbidden
@@ -13,23 +13,35 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::net::SocketAddr;
+use std::mem;
+use std::net::IpAddr;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use tiny_http::Method;
+use web::protocol::Method;
 
 use common::config::HttpConfig;
 use common::prelude::*;
+use common::state::State;
 
 use scripts::Repository;
+use utils::ListenAddr;
+use web::access_log::AccessLog;
 use web::api::WebApi;
-use web::http::HttpServer;
+use web::http::{HttpAuth, HttpServer, TlsCertificate};
+use web::listeners;
+use web::proxies::ProxySupport;
+use web::rate_limits::{RateLimiter, ScriptRateLimits};
 
 pub struct WebApp<A: ProcessorApiTrait<Repository> + 'static> {
     server: HttpServer<WebApi<A>>,
-    addr: SocketAddr,
+    addrs: Vec<ListenAddr>,
     locked: Arc<AtomicBool>,
+    limiter: Arc<Mutex<RateLimiter<IpAddr>>>,
+    script_limits: Arc<Mutex<ScriptRateLimits>>,
+    access_log: Option<Arc<AccessLog>>,
+    tls: Option<TlsCertificate>,
 }
 
 impl<A: ProcessorApiTrait<Repository>> WebApp<A> {
@@ -37,21 +49,111 @@ impl<A: ProcessorApiTrait<Repository>> WebApp<A> {
         hooks: Arc<Repository>,
         config: &HttpConfig,
         processor: A,
+        state: Arc<State>,
+    ) -> Result<Self> {
+        Self::build(hooks, config, processor, state, None)
+    }
+
+    /// Apply a new configuration, replacing the HTTP server without
+    /// refusing connections or dropping the requests in progress.
+    ///
+    /// The new sockets are bound first, then the sockets still present in
+    /// the configuration are handed over to the new server, and finally the
+    /// old server is stopped after serving the requests it received. The
+    /// rate limits are preserved, and the old server keeps listening on all
+    /// its sockets if the reload fails.
+    pub fn reload(
+        &mut self,
+        hooks: Arc<Repository>,
+        config: &HttpConfig,
+        processor: A,
+        state: Arc<State>,
+    ) -> Result<()> {
+        let new = Self::build(hooks, config, processor, state, Some(self))?;
+        let old = mem::replace(self, new);
+        old.stop();
+
+        Ok(())
+    }
+
+    fn build(
+        hooks: Arc<Repository>,
+        config: &HttpConfig,
+        processor: A,
+        state: Arc<State>,
+        previous: Option<&mut WebApp<A>>,
     ) -> Result<Self> {
         let locked = Arc::new(AtomicBool::new(false));
 
+        // Keep the rate limits of the previous server, which are changed
+        // only once the new server is listening
+        let allowed = config.rate_limit.allowed;
+        let interval = config.rate_limit.interval.as_u64();
+        let reconfigure = previous.is_some();
+        let limiter = if let Some(ref previous) = previous {
+            previous.limiter.clone()
+        } else {
+            Arc::new(Mutex::new(RateLimiter::new(allowed, interval)))
+        };
+        let script_limits = if let Some(ref previous) = previous {
+            previous.script_limits.clone()
+        } else {
+            Arc::new(Mutex::new(ScriptRateLimits::new()))
+        };
+
         // Create the web api
         let api = WebApi::new(
             processor,
             hooks,
             locked.clone(),
-            &config.rate_limit,
-            config.health_endpoint,
+            state,
+            limiter.clone(),
+            script_limits.clone(),
+            config,
         );
 
         // Create the HTTP server
-        let mut server = HttpServer::new(api, config.behind_proxies);
-        server.add_route(Method::Get, "/health", Box::new(WebApi::get_health));
+        let auth = HttpAuth::new(&config.auth)?;
+        let proxy_support = ProxySupport::from_config(config)?;
+        let mut server = HttpServer::new(api, proxy_support, auth);
+        let access_log = AccessLog::new(&config.access_log)?.map(Arc::new);
+        if let Some(ref access_log) = access_log {
+            server.set_access_log(access_log.clone());
+        }
+        server.set_workers(config.workers);
+        server.set_read_timeout(Duration::from_secs(
+            config.read_timeout.as_u64(),
+        ));
+        server.set_max_body_size(config.max_body_size);
+        let tls = TlsCertificate::load(&config.tls)?;
+        if let Some(ref tls) = tls {
+            server.set_tls(tls.clone());
+        }
+        server.add_protected_route(
+            Method::Get,
+            "/health",
+            Box::new(WebApi::get_health),
+        );
+        server.add_protected_route(
+            Method::Get,
+            "/health/live",
+            Box::new(WebApi::get_live),
+        );
+        server.add_protected_route(
+            Method::Get,
+            "/health/ready",
+            Box::new(WebApi::get_ready),
+        );
+        server.add_protected_route(
+            Method::Get,
+            "/metrics",
+            Box::new(WebApi::get_metrics),
+        );
+        server.add_protected_route(
+            Method::Get,
+            "/jobs/?",
+            Box::new(WebApi::get_job),
+        );
         server.add_route(
             Method::Get,
             "/hook/?",
@@ -63,17 +165,100 @@ impl<A: ProcessorApiTrait<Repository>> WebApp<A> {
             Box::new(WebApi::process_hook),
         );
 
-        let socket = server.listen(config.bind)?;
+        let addrs = match Self::listen(&mut server, config, previous) {
+            Ok(addrs) => addrs,
+            Err(err) => {
+                // Don't leave behind the sockets already listening
+                server.stop();
+                return Err(err);
+            }
+        };
+        if reconfigure {
+            limiter.lock()?.reconfigure(allowed, interval);
+        }
 
         Ok(WebApp {
             server,
-            addr: socket,
+            addrs,
             locked,
+            limiter,
+            script_limits,
+            access_log,
+            tls,
         })
     }
 
-    pub fn addr(&self) -> &SocketAddr {
-        &self.addr
+    fn listen(
+        server: &mut HttpServer<WebApi<A>>,
+        config: &HttpConfig,
+        mut previous: Option<&mut WebApp<A>>,
+    ) -> Result<Vec<ListenAddr>> {
+        let mut addrs = vec![Vec::new(); config.bind.len()];
+
+        // Bind the new addresses first, so nothing is changed if that fails
+        let mut take_over = Vec::new();
+        for (i, bind) in config.bind.iter().enumerate() {
+            let listening = match previous {
+                Some(ref previous) => previous.server.is_listening_on(bind),
+                None => false,
+            };
+            if listening {
+                take_over.push(i);
+                continue;
+            }
+
+            for listener in listeners::bind(bind, &config.unix_socket)? {
+                addrs[i].push(server.listen(bind, listener)?);
+            }
+        }
+
+        // The permissions might have changed in the configuration. This is
+        // done before taking over any socket, so the previous server keeps
+        // listening on all of them if it fails
+        for &i in &take_over {
+            if let ListenAddr::Unix(ref path) = config.bind[i] {
+                listeners::set_permissions(path, &config.unix_socket)?;
+            }
+        }
+
+        if let Some(ref mut previous) = previous {
+            for (pos, &i) in take_over.iter().enumerate() {
+                let bind = &config.bind[i];
+                match server.take_over(&mut previous.server, bind) {
+                    Ok(taken) => addrs[i] = taken,
+                    Err(err) => {
+                        // Give the sockets already taken over back to the
+                        // previous server, which keeps being used
+                        for &j in &take_over[..=pos] {
+                            let bind = &config.bind[j];
+                            let _ = previous.server.take_over(server, bind);
+                        }
+                        return Err(err);
+                    }
+                }
+            }
+        }
+
+        Ok(addrs.into_iter().flatten().collect())
+    }
+
+    /// The addresses the HTTP server is listening on.
+    pub fn addrs(&self) -> &[ListenAddr] {
+        &self.addrs
+    }
+
+    /// Check if the TLS certificate on disk is different from the one
+    /// currently used.
+    pub fn tls_changed(&self, config: &HttpConfig) -> Result<bool> {
+        Ok(TlsCertificate::load(&config.tls)? != self.tls)
+    }
+
+    /// Reopen the access log, for example after it was rotated.
+    pub fn reopen_access_log(&self) -> Result<()> {
+        if let Some(ref access_log) = self.access_log {
+            access_log.reopen()?;
+        }
+        Ok(())
     }
 
     pub fn lock(&self) {
@@ -91,14 +276,24 @@ impl<A: ProcessorApiTrait<Repository>> WebApp<A> {
 
 #[cfg(test)]
 mod tests {
-    use std::io::Read;
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::thread;
 
+    use hyper::client::Client;
     use hyper::header::Headers;
     use hyper::method::Method;
     use hyper::status::StatusCode;
     use serde_json;
+    use tempdir::TempDir;
 
+    use common::config::{
+        AccessLogConfig, AccessLogFormat, AuthConfig, RateLimitConfig,
+    };
     use common::prelude::*;
+    use common::structs::JobStatus;
+    use scripts::JobContext;
 
     use utils::testing::*;
 
@@ -137,12 +332,32 @@ mod tests {
         assert!(inst.processor_input().is_none());
 
         // Call the example hook with authorization
-        let res = inst
+        let mut res = inst
             .request(Method::Get, "/hook/example.sh?secret=testing")
             .send()
             .unwrap();
         assert_eq!(res.status, StatusCode::Ok);
 
+        // The response tells where to check the status of the job
+        assert_eq!(
+            res.headers.get_raw("Location"),
+            Some(&[b"/jobs/0123456789abcdef".to_vec()][..])
+        );
+        let mut content = String::new();
+        res.read_to_string(&mut content).unwrap();
+        let data = serde_json::from_str::<serde_json::Value>(&content).unwrap();
+        assert_eq!(data, json!({
+            "status": "ok",
+            "ping": false,
+            "provider": "Testing",
+            "job": {
+                "id": "0123456789abcdef",
+                "script": "example.sh",
+                "status": "queued",
+                "queue_position": 0,
+            },
+        }));
+
         // Assert a job is queued
         let input = inst.processor_input();
 
@@ -154,12 +369,18 @@ mod tests {
         }
 
         // Call the example hook simulating a Ping
-        let res = inst
+        let mut res = inst
             .request(Method::Get, "/hook/example.sh?request_type=ping")
             .send()
             .unwrap();
         assert_eq!(res.status, StatusCode::Ok);
 
+        let mut content = String::new();
+        res.read_to_string(&mut content).unwrap();
+        let data = serde_json::from_str::<serde_json::Value>(&content).unwrap();
+        assert!(data.pointer("/ping").unwrap().as_bool().unwrap());
+        assert!(data.pointer("/job").unwrap().is_null());
+
         // Even if the last request succeded, there shouldn't be any job
         assert!(inst.processor_input().is_none());
 
@@ -219,6 +440,188 @@ mod tests {
         testing_env.cleanup();
     }
 
+    #[test]
+    fn test_job_status() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web(true, 0);
+
+        let mut res = inst
+            .request(Method::Get, "/jobs/0123456789abcdef")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        let mut content = String::new();
+        res.read_to_string(&mut content).unwrap();
+        let data = serde_json::from_str::<serde_json::Value>(&content).unwrap();
+        assert_eq!(
+            data.pointer("/result/status").unwrap().as_str().unwrap(),
+            "running"
+        );
+        match inst.processor_input() {
+            Some(ProcessorApiCall::JobDetails(id)) => {
+                assert_eq!(id, "0123456789abcdef");
+            }
+            _ => panic!("Wrong processor input received"),
+        }
+
+        // Unknown jobs aren't found
+        let res = inst.request(Method::Get, "/jobs/missing").send().unwrap();
+        assert_eq!(res.status, StatusCode::NotFound);
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+    fn sync_request(
+        inst: &WebAppInstance,
+        query: &str,
+    ) -> thread::JoinHandle<(StatusCode, String, bool)> {
+        let url = format!("http://{}/hook/sync.sh{}", inst.addrs()[0], query);
+        thread::spawn(move || {
+            let mut res = Client::new().get(&url).send().unwrap();
+            let json = res.headers.get_raw("Content-Type")
+                == Some(&[b"application/json".to_vec()][..]);
+            let mut content = String::new();
+            res.read_to_string(&mut content).unwrap();
+            (res.status, content, json)
+        })
+    }
+
+    #[test]
+    fn test_sync_hook() {
+        let testing_env = TestingEnv::new();
+        let inst = testing_env.start_web(true, 0);
+
+        // The request waits for the job to be executed
+        let request = sync_request(&inst, "");
+        match inst.wait_processor_input() {
+            ProcessorApiCall::Queue(job, _) => {
+                job.execute(&JobContext::default()).unwrap();
+            }
+            _ => panic!("Wrong processor input received"),
+        }
+
+        // The exit code chose the status, and the output is the body
+        let (status, content, json) = request.join().unwrap();
+        assert_eq!(status, StatusCode::UnprocessableEntity);
+        assert_eq!(content, "Hello world\n");
+        assert!(!json);
+
+        // The response file is returned as JSON only if it's valid JSON
+        for &(env, json) in &[("%7B%22a%22%3A1%7D", true), ("a", false)] {
+            let request = sync_request(&inst, &format!("?env={}", env));
+            match inst.wait_processor_input() {
+                ProcessorApiCall::Queue(job, _) => {
+                    job.execute(&JobContext::default()).unwrap();
+                }
+                _ => panic!("Wrong processor input received"),
+            }
+            assert_eq!(request.join().unwrap().2, json);
+        }
+
+        // Jobs removed from the queue before being executed are reported
+        for &(status, code) in &[
+            (JobStatus::Superseded, StatusCode::Conflict),
+            (JobStatus::Dropped, StatusCode::ServiceUnavailable),
+        ] {
+            inst.set_job_status(status);
+            let request = sync_request(&inst, "");
+            drop(inst.wait_processor_input());
+            assert_eq!(request.join().unwrap().0, code);
+
+            // The status of the job was checked
+            match inst.wait_processor_input() {
+                ProcessorApiCall::JobDetails(..) => {}
+                _ => panic!("Wrong processor input received"),
+            }
+        }
+        inst.set_job_status(JobStatus::Running);
+
+        // The request fails if the job is never executed
+        let url = format!("http://{}/hook/sync.sh", inst.addrs()[0]);
+        let request = thread::spawn(move || {
+            Client::new().get(&url).send().unwrap().status
+        });
+        drop(inst.wait_processor_input());
+        assert_eq!(
+            request.join().unwrap(),
+            StatusCode::InternalServerError
+        );
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+    #[test]
+    fn test_script_rate_limit() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web(true, 0);
+
+        // The first two requests are allowed
+        for _ in 0..2 {
+            let res = inst
+                .request(Method::Get, "/hook/limited.sh")
+                .send()
+                .unwrap();
+            assert_eq!(res.status, StatusCode::Ok);
+            assert!(inst.processor_input().is_some());
+        }
+
+        // The third one is rejected until the limit clears
+        let res = inst
+            .request(Method::Get, "/hook/limited.sh")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::TooManyRequests);
+        assert!(res.headers.get_raw("Retry-After").is_some());
+        assert!(inst.processor_input().is_none());
+
+        // Other scripts aren't affected
+        let res = inst
+            .request(Method::Get, "/hook/example.sh")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+    #[test]
+    fn test_verbose_errors() {
+        fn rejection(inst: &mut WebAppInstance) -> serde_json::Value {
+            let mut res = inst
+                .request(Method::Get, "/hook/example.sh?secret=invalid")
+                .send()
+                .unwrap();
+            assert_eq!(res.status, StatusCode::Forbidden);
+
+            let mut content = String::new();
+            res.read_to_string(&mut content).unwrap();
+            serde_json::from_str(&content).unwrap()
+        }
+
+        // The reason isn't shown by default
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web(true, 0);
+        assert_eq!(rejection(&mut inst), json!({"status": "forbidden"}));
+        inst.stop();
+
+        // The reason is shown if verbose errors are enabled
+        let mut config = testing_env.web_config(true, 0);
+        config.verbose_errors = true;
+        let mut inst = testing_env.start_web_with_config(&config);
+        assert_eq!(rejection(&mut inst), json!({
+            "status": "forbidden",
+            "reason": "wrong secret",
+        }));
+        assert!(inst.processor_input().is_none());
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
     #[test]
     fn test_health_disabled() {
         // Create the instance with disabled health status
@@ -268,6 +671,176 @@ mod tests {
         testing_env.cleanup();
     }
 
+    #[test]
+    fn test_health_probes() {
+        let testing_env = TestingEnv::new();
+        let mut inst = testing_env.start_web(true, 0);
+
+        // The instance is alive and ready
+        let res = inst.request(Method::Get, "/health/live").send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        let res = inst.request(Method::Get, "/health/ready").send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        // A locked instance is still alive, but not ready
+        inst.lock();
+        let res = inst.request(Method::Get, "/health/live").send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        let mut res =
+            inst.request(Method::Get, "/health/ready").send().unwrap();
+        assert_eq!(res.status, StatusCode::ServiceUnavailable);
+
+        let mut content = String::new();
+        res.read_to_string(&mut content).unwrap();
+        let data = serde_json::from_str::<serde_json::Value>(&content).unwrap();
+        assert_eq!(
+            data.pointer("/status").unwrap().as_str().unwrap(),
+            "not_ready"
+        );
+        assert!(data.pointer("/result/locked").unwrap().as_bool().unwrap());
+
+        inst.unlock();
+        inst.stop();
+
+        // The probes are disabled with the health endpoint
+        let mut inst = testing_env.start_web(false, 0);
+        let res = inst.request(Method::Get, "/health/live").send().unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+        let res = inst.request(Method::Get, "/health/ready").send().unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+    #[test]
+    fn test_health_auth() {
+        // Create the instance with authentication enabled
+        let testing_env = TestingEnv::new();
+        let mut config = testing_env.web_config(true, 0);
+        config.auth = AuthConfig {
+            token: Some("secret".into()),
+            ..AuthConfig::default()
+        };
+        let mut inst = testing_env.start_web_with_config(&config);
+
+        // The health status shouldn't be available without the token
+        let res = inst.request(Method::Get, "/health").send().unwrap();
+        assert_eq!(res.status, StatusCode::Unauthorized);
+        assert!(inst.processor_input().is_none());
+
+        // The health status should be available with the token
+        let mut headers = Headers::new();
+        headers.set_raw("Authorization", vec![b"Bearer secret".to_vec()]);
+        let res = inst
+            .request(Method::Get, "/health")
+            .headers(headers)
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        // The status of the jobs is protected as well
+        let res = inst
+            .request(Method::Get, "/jobs/0123456789abcdef")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Unauthorized);
+
+        // Hooks shouldn't require authentication
+        let res = inst
+            .request(Method::Get, "/hook/example.sh")
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+    #[test]
+    fn test_metrics() {
+        let testing_env = TestingEnv::new();
+
+        // The endpoint should be disabled by default
+        let mut inst = testing_env.start_web(true, 0);
+        let res = inst.request(Method::Get, "/metrics").send().unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+        inst.stop();
+
+        // Create the instance with enabled metrics
+        let mut config = testing_env.web_config(true, 0);
+        config.metrics_endpoint = true;
+        let mut inst = testing_env.start_web_with_config(&config);
+
+        // Make some requests to record
+        for url in &["/hook/example.sh", "/hook/missing.sh"] {
+            inst.request(Method::Get, url).send().unwrap();
+        }
+
+        let mut res = inst.request(Method::Get, "/metrics").send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        let mut content = String::new();
+        res.read_to_string(&mut content).unwrap();
+        for expected in &[
+            "fisher_webhooks_received_total 2\n",
+            "fisher_webhooks_rejected_total{reason=\"not_found\"} 1\n",
+            concat!(
+                "fisher_webhooks_executed_total",
+                "{script=\"example.sh\",provider=\"Testing\"} 1\n",
+            ),
+            "fisher_queued_jobs 1\n",
+            "fisher_busy_threads 2\n",
+        ] {
+            assert!(content.contains(expected), "missing: {}", expected);
+        }
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+    #[test]
+    fn test_access_log() {
+        let testing_env = TestingEnv::new();
+        let tempdir = TempDir::new("fisher-tests").unwrap();
+        let path = tempdir.path().join("access.log");
+
+        let mut config = testing_env.web_config(true, 1);
+        config.access_log = AccessLogConfig {
+            path: Some(path.to_str().unwrap().into()),
+            format: AccessLogFormat::Combined,
+        };
+        let mut inst = testing_env.start_web_with_config(&config);
+
+        let mut headers = Headers::new();
+        headers.set_raw("X-Forwarded-For", vec![b"127.1.1.1".to_vec()]);
+        headers.set_raw("User-Agent", vec![b"Fisher-Tests".to_vec()]);
+        let url = "/hook/example.sh?ip=127.1.1.1&secret=testing";
+        let res = inst
+            .request(Method::Get, url)
+            .headers(headers)
+            .send()
+            .unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        inst.stop();
+
+        let mut content = String::new();
+        File::open(&path)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+
+        // The client IP must be the one behind the proxy, and the query
+        // string must not be logged
+        let line = content.lines().next().unwrap();
+        assert!(line.starts_with("127.1.1.1 - - ["));
+        assert!(line.contains("\"GET /hook/example.sh HTTP/1.1\" 200 "));
+        assert!(line.contains(" \"-\" \"Fisher-Tests\" "));
+        assert!(!line.contains("secret"));
+
+        testing_env.cleanup();
+    }
+
     #[test]
     fn test_behind_proxy() {
         // Create a new instance behind a proxy
@@ -300,4 +873,71 @@ mod tests {
         inst.stop();
         testing_env.cleanup();
     }
+
+    #[test]
+    fn test_reload() {
+        let testing_env = TestingEnv::new();
+        let mut config = testing_env.web_config(true, 0);
+        config.rate_limit = RateLimitConfig {
+            allowed: 1,
+            interval: 60.into(),
+        };
+        let mut inst = testing_env.start_web_with_config(&config);
+        let addrs = inst.addrs().to_vec();
+
+        // Get rate limited
+        let url = "/hook/example.sh?secret=invalid";
+        for _ in 0..2 {
+            let res = inst.request(Method::Get, url).send().unwrap();
+            assert_eq!(res.status, StatusCode::Forbidden);
+        }
+        let res = inst.request(Method::Get, url).send().unwrap();
+        assert_eq!(res.status, StatusCode::TooManyRequests);
+
+        // Disable the health endpoint, keeping the same addresses
+        config.health_endpoint = false;
+        inst.reload(&config).unwrap();
+        assert_eq!(inst.addrs(), addrs.as_slice());
+
+        // The new configuration is used, while keeping the rate limits
+        let res = inst.request(Method::Get, "/health").send().unwrap();
+        assert_eq!(res.status, StatusCode::Forbidden);
+        let res = inst.request(Method::Get, url).send().unwrap();
+        assert_eq!(res.status, StatusCode::TooManyRequests);
+
+        inst.stop();
+        testing_env.cleanup();
+    }
+
+    #[test]
+    fn test_failed_reload() {
+        let testing_env = TestingEnv::new();
+        let dir = TempDir::new("fisher").unwrap();
+        let path = dir.path().join("fisher.sock");
+
+        let mut config = testing_env.web_config(true, 0);
+        let unix = format!("unix:{}", path.display());
+        config.bind.push(unix.parse().unwrap());
+        let mut inst = testing_env.start_web_with_config(&config);
+        let addrs = inst.addrs().to_vec();
+
+        // The TCP socket comes first, but the permissions of the Unix socket
+        // can't be applied
+        config.unix_socket.group = Some("fisher-missing-group".into());
+        assert!(inst.reload(&config).is_err());
+
+        // The previous server is still listening on all the addresses
+        assert_eq!(inst.addrs(), addrs.as_slice());
+        let res = inst.request(Method::Get, "/health").send().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+
+        let mut stream = UnixStream::connect(&path).unwrap();
+        write!(stream, "GET /health HTTP/1.0\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.0 200"));
+
+        inst.stop();
+        testing_env.cleanup();
+    }
 }
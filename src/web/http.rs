@@ -13,19 +13,35 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::io::Write;
-use std::net::{Shutdown, SocketAddr, TcpStream};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
 use std::sync::mpsc;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
-
+use std::time::Duration;
+
+#[cfg(feature = "tls")]
+use openssl::pkey::PKey;
+#[cfg(feature = "tls")]
+use openssl::ssl::{SslAcceptor, SslMethod, SslVerifyMode};
+#[cfg(feature = "tls")]
+use openssl::x509::X509;
 use regex::{self, Regex};
-use tiny_http::{self, Method};
 
+use common::config::{AuthConfig, ClientAuth, TlsConfig};
+use common::logging;
 use common::prelude::*;
 use requests::Request;
+use utils::{self, ListenAddr};
+use web::access_log::{AccessLog, AccessLogEntry};
+use web::listeners::{Listener, Stream};
+use web::protocol::{self, Connection, Method, Received, Transport};
 use web::proxies::ProxySupport;
+use web::requests::WebRequest;
 use web::responses::Response;
 
 pub type RequestHandler<App> = Box<fn(&App, &Request, Vec<String>) -> Response>;
@@ -90,14 +106,238 @@ impl Route {
     }
 }
 
+/// Compare two byte strings without leaking where they differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Credentials required to call protected routes.
+#[derive(Debug, Default, Clone)]
+pub struct HttpAuth {
+    token: Option<String>,
+    basic: Option<(String, String)>,
+}
+
+impl HttpAuth {
+    pub fn new(config: &AuthConfig) -> Result<Self> {
+        let basic = match (&config.username, &config.password) {
+            (Some(user), Some(pass)) => Some((user.clone(), pass.clone())),
+            (None, None) => None,
+            _ => return Err(ErrorKind::AuthConfigIncomplete.into()),
+        };
+
+        Ok(HttpAuth {
+            token: config.token.clone(),
+            basic,
+        })
+    }
+
+    fn enabled(&self) -> bool {
+        self.token.is_some() || self.basic.is_some()
+    }
+
+    fn authorized(&self, req: &Request) -> bool {
+        // Protected routes are public if no credentials are configured
+        if !self.enabled() {
+            return true;
+        }
+
+        let req = if let Ok(req) = req.web() {
+            req
+        } else {
+            return false;
+        };
+
+        let header = req.headers.get("Authorization").unwrap_or("");
+
+        let mut parts = header.trim().splitn(2, ' ');
+        let scheme = parts.next().unwrap_or("");
+        let credentials = parts.next().unwrap_or("").trim();
+
+        if scheme.eq_ignore_ascii_case("Bearer") {
+            if let Some(ref token) = self.token {
+                return constant_time_eq(
+                    token.as_bytes(),
+                    credentials.as_bytes(),
+                );
+            }
+        } else if scheme.eq_ignore_ascii_case("Basic") {
+            if let Some((ref user, ref pass)) = self.basic {
+                if let Ok(decoded) = utils::from_base64(credentials) {
+                    let expected = format!("{}:{}", user, pass);
+                    return constant_time_eq(expected.as_bytes(), &decoded);
+                }
+            }
+        }
+
+        false
+    }
+
+    fn challenge(&self) -> Response {
+        // Prefer HTTP Basic, since browsers know how to prompt for it
+        if self.basic.is_some() {
+            Response::Unauthorized("Basic")
+        } else {
+            Response::Unauthorized("Bearer")
+        }
+    }
+}
+
+/// The certificate and the private key used to serve HTTPS, and the CAs
+/// client certificates are verified with.
+#[derive(Clone, PartialEq, Eq)]
+pub struct TlsCertificate {
+    certificate: Vec<u8>,
+    private_key: Vec<u8>,
+    client_ca: Option<Vec<u8>>,
+    client_auth: ClientAuth,
+}
+
+impl fmt::Debug for TlsCertificate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Don't leak the private key
+        write!(f, "TlsCertificate {{ .. }}")
+    }
+}
+
+impl TlsCertificate {
+    /// Load the certificate from disk, returning `None` if TLS is disabled.
+    pub fn load(config: &TlsConfig) -> Result<Option<Self>> {
+        match (&config.certificate, &config.private_key) {
+            (Some(certificate), Some(private_key)) => {
+                let client_ca = match config.client_ca {
+                    Some(ref client_ca) => Some(fs::read(client_ca)?),
+                    None => None,
+                };
+
+                let tls = TlsCertificate {
+                    certificate: fs::read(certificate)?,
+                    private_key: fs::read(private_key)?,
+                    client_ca,
+                    client_auth: config.client_auth,
+                };
+                tls.validate()?;
+                Ok(Some(tls))
+            }
+            (None, None) => Ok(None),
+            _ => Err(ErrorKind::TlsConfigIncomplete.into()),
+        }
+    }
+
+    /// Ensure the certificate can be used, since problems would otherwise be
+    /// noticed only while starting the server.
+    #[cfg(feature = "tls")]
+    fn validate(&self) -> Result<()> {
+        self.acceptor().map(|_| ())
+    }
+
+    #[cfg(not(feature = "tls"))]
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+
+    #[cfg(feature = "tls")]
+    fn acceptor(&self) -> Result<TlsAcceptor> {
+        let boxed = |err| Error::from(ErrorKind::BoxedError(Box::new(err)));
+
+        let mut builder =
+            SslAcceptor::mozilla_intermediate_v5(SslMethod::tls())
+                .map_err(boxed)?;
+
+        // The certificate might be followed by the intermediate ones
+        let mut chain =
+            X509::stack_from_pem(&self.certificate).map_err(boxed)?;
+        if chain.is_empty() {
+            return Err(ErrorKind::TlsCertificateMissing.into());
+        }
+        builder.set_certificate(&chain.remove(0)).map_err(boxed)?;
+        for certificate in chain {
+            builder.add_extra_chain_cert(certificate).map_err(boxed)?;
+        }
+
+        let key = PKey::private_key_from_pem(&self.private_key)
+            .map_err(boxed)?;
+        builder.set_private_key(&key).map_err(boxed)?;
+        builder.check_private_key().map_err(boxed)?;
+
+        if let Some(ref client_ca) = self.client_ca {
+            let cas = X509::stack_from_pem(client_ca).map_err(boxed)?;
+            if cas.is_empty() {
+                return Err(ErrorKind::TlsClientCaMissing.into());
+            }
+            for ca in cas {
+                builder.add_client_ca(&ca).map_err(boxed)?;
+                builder.cert_store_mut().add_cert(ca).map_err(boxed)?;
+            }
+
+            // Clients presenting an invalid certificate are always refused
+            let mut mode = SslVerifyMode::PEER;
+            if self.client_auth == ClientAuth::Required {
+                mode |= SslVerifyMode::FAIL_IF_NO_PEER_CERT;
+            }
+            builder.set_verify(mode);
+
+            // Resuming a session fails without a context if clients are
+            // verified
+            builder.set_session_id_context(b"fisher").map_err(boxed)?;
+        }
+
+        Ok(TlsAcceptor(builder.build()))
+    }
+
+    /// Whether the protected routes need a valid client certificate.
+    fn verifies_clients(&self) -> bool {
+        self.client_ca.is_some()
+    }
+
+    #[cfg(not(feature = "tls"))]
+    fn acceptor(&self) -> Result<TlsAcceptor> {
+        Err(ErrorKind::TlsNotSupported.into())
+    }
+}
+
+/// Encrypts the connections accepted by the server.
+#[cfg(feature = "tls")]
+struct TlsAcceptor(SslAcceptor);
+
+#[cfg(not(feature = "tls"))]
+enum TlsAcceptor {}
+
+impl TlsAcceptor {
+    /// Perform the TLS handshake, returning `None` if it fails.
+    #[cfg(feature = "tls")]
+    fn accept(&self, stream: Stream) -> Option<Transport> {
+        let stream = self.0.accept(stream).ok()?;
+        Some(Transport::Tls(Box::new(stream)))
+    }
+
+    #[cfg(not(feature = "tls"))]
+    fn accept(&self, _stream: Stream) -> Option<Transport> {
+        match *self {}
+    }
+}
+
 struct Handler<App: Send + Sync + 'static> {
     handler: RequestHandler<App>,
     route: Route,
+    protected: bool,
 }
 
 impl<App: Send + Sync + 'static> Handler<App> {
-    fn new(handler: RequestHandler<App>, route: Route) -> Self {
-        Handler { handler, route }
+    fn new(
+        handler: RequestHandler<App>,
+        route: Route,
+        protected: bool,
+    ) -> Self {
+        Handler {
+            handler,
+            route,
+            protected,
+        }
     }
 
     fn matches(&self, method: &Method, url: &str) -> Option<Vec<String>> {
@@ -109,29 +349,389 @@ impl<App: Send + Sync + 'static> Handler<App> {
     }
 }
 
-pub struct HttpServer<App: Send + Sync + 'static> {
+fn log_request(origin: &protocol::Request, req: &Request, resp: &Response) {
+    // The query string is not logged, since it might contain secrets
+    let path = origin.url().split('?').next().unwrap_or("");
+
+    let mut event = logging::info("request processed")
+        .field("request_id", req.id())
+        .field("method", origin.method())
+        .field("path", path);
+    if let Ok(web) = req.web() {
+        event = event.field("source", web.source);
+    }
+    event.field("status", resp.status()).log();
+}
+
+/// Everything needed to serve a request.
+struct Dispatcher<App: Send + Sync + 'static> {
     app: Arc<App>,
-    handlers: Arc<Mutex<Vec<Handler<App>>>>,
+    handlers: Arc<RwLock<Vec<Handler<App>>>>,
     proxy_support: Arc<ProxySupport>,
+    auth: Arc<HttpAuth>,
+    access_log: Option<Arc<AccessLog>>,
+    tls: Option<Arc<TlsAcceptor>>,
+    verify_clients: bool,
+    read_timeout: Duration,
+    max_body_size: u64,
+}
+
+impl<App: Send + Sync + 'static> Dispatcher<App> {
+    fn serve(&self, request: &mut protocol::Request) -> protocol::Response {
+        let (req, response) = self.receive(request);
+        let response = match response {
+            Some(response) => response,
+            None => self.handle(request, &req),
+        };
+        self.respond(request, &req, response)
+    }
+
+    /// Convert the request to a Fisher request, returning the response to
+    /// send if it's not valid.
+    fn receive(
+        &self,
+        request: &mut protocol::Request,
+    ) -> (Request, Option<Response>) {
+        let mut web = WebRequest::from(&*request);
+        let body = request.take_body().map(|body| web.body = body);
+
+        let mut req = Request::Web(web);
+        let fixed = self.proxy_support.fix_request(&mut req);
+
+        let response = match (body, fixed) {
+            (Err(Error(ErrorKind::RequestTimeout, _)), _) => {
+                Response::RequestTimeout
+            }
+            (Err(Error(ErrorKind::RequestBodyTooLarge(..), _)), _) => {
+                Response::PayloadTooLarge
+            }
+            (Err(e), _) | (Ok(()), Err(e)) => Response::BadRequest(e),
+            (Ok(()), Ok(())) => return (req, None),
+        };
+
+        (req, Some(response))
+    }
+
+    fn handle(&self, request: &protocol::Request, req: &Request) -> Response {
+        let method = request.method();
+        let url = request.url();
+
+        for handler in &*self.handlers.read().unwrap() {
+            if let Some(args) = handler.matches(method, url) {
+                if handler.protected {
+                    // Client certificates might be optional for the other
+                    // routes
+                    if self.verify_clients && !request.client_verified() {
+                        return Response::Forbidden;
+                    } else if !self.auth.authorized(req) {
+                        return self.auth.challenge();
+                    }
+                }
+
+                return handler.call(&self.app, req, args);
+            }
+        }
+
+        Response::NotFound
+    }
+
+    fn respond(
+        &self,
+        request: &protocol::Request,
+        req: &Request,
+        response: Response,
+    ) -> protocol::Response {
+        let body = response.body().into_bytes();
+
+        log_request(request, req, &response);
+        if let Some(ref access_log) = self.access_log {
+            access_log.record(&AccessLogEntry::new(
+                request,
+                req,
+                response.status(),
+                body.len(),
+                request.received_at().elapsed(),
+            ));
+        }
+
+        let mut raw = protocol::Response::new(response.status(), body);
 
-    should_stop: Arc<AtomicBool>,
+        // Add custom headers from the response
+        if let Some(headers) = response.headers() {
+            for header in headers {
+                raw.add_header(header);
+            }
+        }
+
+        raw.add_header(format!(
+            "Server: Fisher/{}",
+            env!("CARGO_PKG_VERSION")
+        ));
+        raw.add_header(format!("Content-Type: {}", response.content_type()));
+
+        raw
+    }
+}
 
-    listening_to: Option<SocketAddr>,
-    stop_wait: Option<mpsc::Receiver<()>>,
+type SharedDispatcher<App> = Arc<RwLock<Arc<Dispatcher<App>>>>;
+
+/// A request waiting for a worker, with the channel to send it back along
+/// with its response.
+type Job = (
+    protocol::Request,
+    mpsc::Sender<(protocol::Request, protocol::Response)>,
+);
+
+fn current<App>(dispatcher: &SharedDispatcher<App>) -> Arc<Dispatcher<App>>
+where
+    App: Send + Sync + 'static,
+{
+    // The dispatcher is fetched again for each request, since it's replaced
+    // when another HttpServer takes over the running one
+    dispatcher
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+}
+
+/// The open connections of a server, to close the idle ones when it stops.
+#[derive(Default)]
+struct Connections {
+    next_id: usize,
+    open: HashMap<usize, (Stream, bool)>,
+    stopping: bool,
+}
+
+impl Connections {
+    /// Track a new connection, returning its ID unless the server is
+    /// stopping.
+    fn open(&mut self, stream: &Stream) -> Option<usize> {
+        if self.stopping {
+            return None;
+        }
+
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        if let Ok(stream) = stream.try_clone() {
+            self.open.insert(id, (stream, true));
+        }
+        Some(id)
+    }
+
+    /// Mark a connection as waiting for a request or not, returning `false`
+    /// if it should be closed instead because the server is stopping.
+    fn set_idle(&mut self, id: usize, idle: bool) -> bool {
+        if let Some(conn) = self.open.get_mut(&id) {
+            conn.1 = idle;
+        }
+        !self.stopping
+    }
+
+    fn close(&mut self, id: usize) {
+        self.open.remove(&id);
+    }
+
+    /// Interrupt the idle connections, and prevent the others from
+    /// receiving other requests.
+    fn stop(&mut self) {
+        self.stopping = true;
+        for &(ref stream, idle) in self.open.values() {
+            if idle {
+                let _ = stream.shutdown(Shutdown::Read);
+            }
+        }
+    }
+}
+
+/// Accept connections until `stop` is closed, serving each of them in its
+/// own thread.
+fn accept_connections<App: Send + Sync + 'static>(
+    listener: Listener,
+    stop: UnixStream,
+    dispatcher: SharedDispatcher<App>,
+    connections: Arc<Mutex<Connections>>,
+    queue: mpsc::Sender<Job>,
+) {
+    loop {
+        let timeout = current(&dispatcher).read_timeout;
+        let stream = match listener.accept(&stop, timeout) {
+            Ok(Some(stream)) => stream,
+            Ok(None) => break,
+            Err(error) => {
+                logging::warning("can't accept a connection")
+                    .field("error", error)
+                    .log();
+
+                // Errors like running out of file descriptors would
+                // otherwise be retried in a busy loop
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+        };
+
+        let id = match connections.lock().unwrap().open(&stream) {
+            Some(id) => id,
+            None => continue,
+        };
+
+        let dispatcher = dispatcher.clone();
+        let connections = connections.clone();
+        let queue = queue.clone();
+        thread::spawn(move || {
+            serve_connection(stream, id, &dispatcher, &connections, queue);
+            connections.lock().unwrap().close(id);
+        });
+    }
+}
+
+/// Serve the requests sent over a connection, one after the other.
+fn serve_connection<App: Send + Sync + 'static>(
+    stream: Stream,
+    id: usize,
+    dispatcher: &SharedDispatcher<App>,
+    connections: &Mutex<Connections>,
+    queue: mpsc::Sender<Job>,
+) {
+    let remote_addr = stream.peer_addr();
+    let transport = match current(dispatcher).tls {
+        Some(ref tls) => match tls.accept(stream) {
+            Some(transport) => transport,
+            None => return,
+        },
+        None => Transport::Plain(stream),
+    };
+
+    let mut conn = Connection::new(transport, remote_addr);
+    loop {
+        let mut request = match conn.receive() {
+            Received::Request(request) => *request,
+            Received::Closed => break,
+            Received::Invalid(status) => {
+                let _ = conn.reject(status);
+                break;
+            }
+        };
+        connections.lock().unwrap().set_idle(id, false);
+
+        // The body is read here, so slow clients don't keep workers busy
+        let current = current(dispatcher);
+        let (limit, timeout) = (current.max_body_size, current.read_timeout);
+        conn.read_body(&mut request, limit, timeout);
+
+        let (reply_send, reply_recv) = mpsc::channel();
+        if queue.send((request, reply_send)).is_err() {
+            break;
+        }
+        let (request, response) = match reply_recv.recv() {
+            Ok(reply) => reply,
+            Err(..) => break,
+        };
+
+        if conn.respond(&request, &response).is_err()
+            || !conn.keep_alive()
+            || !connections.lock().unwrap().set_idle(id, true)
+        {
+            break;
+        }
+    }
+
+    // The workers stop when no connection can send them requests anymore
+    drop(queue);
+    conn.close();
+}
+
+/// A server accepting requests from a single socket.
+struct RunningServer<App: Send + Sync + 'static> {
+    /// The address in the configuration which resulted in this server.
+    bind: ListenAddr,
+    /// The address the socket is actually bound to.
+    addr: ListenAddr,
+    /// A copy of the socket, to restart the server on it.
+    socket: Listener,
+
+    dispatcher: SharedDispatcher<App>,
+    connections: Arc<Mutex<Connections>>,
+    workers: u16,
+    stop_accepting: UnixStream,
+    accepting: thread::JoinHandle<()>,
+    stopped: mpsc::Receiver<()>,
+}
+
+impl<App: Send + Sync + 'static> RunningServer<App> {
+    /// Stop the server after serving the requests it already received,
+    /// returning its socket.
+    fn stop(self) -> Listener {
+        // Closing one end of the pair wakes up the thread accepting the
+        // connections, which drops its copy of the socket when it exits
+        drop(self.stop_accepting);
+        let _ = self.accepting.join();
+
+        self.connections.lock().unwrap().stop();
+
+        // The channel is closed when all the workers exited, which happens
+        // after all the connections are closed
+        let _ = self.stopped.recv();
+
+        self.socket
+    }
+}
+
+pub struct HttpServer<App: Send + Sync + 'static> {
+    app: Arc<App>,
+    handlers: Arc<RwLock<Vec<Handler<App>>>>,
+    proxy_support: Arc<ProxySupport>,
+    auth: Arc<HttpAuth>,
+    access_log: Option<Arc<AccessLog>>,
+    tls: Option<TlsCertificate>,
+    workers: u16,
+    read_timeout: Duration,
+    max_body_size: u64,
+
+    running: Vec<RunningServer<App>>,
 }
 
 impl<App: Send + Sync + 'static> HttpServer<App> {
-    pub fn new(app: App, proxies_count: u8) -> Self {
+    pub fn new(app: App, proxy_support: ProxySupport, auth: HttpAuth) -> Self {
         HttpServer {
             app: Arc::new(app),
-            handlers: Arc::new(Mutex::new(Vec::new())),
-            proxy_support: Arc::new(ProxySupport::new(proxies_count)),
+            handlers: Arc::new(RwLock::new(Vec::new())),
+            proxy_support: Arc::new(proxy_support),
+            auth: Arc::new(auth),
+            access_log: None,
+            tls: None,
+            workers: 4,
+            read_timeout: Duration::from_secs(30),
+            max_body_size: 10 * 1024 * 1024,
+
+            running: Vec::new(),
+        }
+    }
 
-            should_stop: Arc::new(AtomicBool::new(false)),
+    /// Record all the served requests in the provided access log.
+    pub fn set_access_log(&mut self, access_log: Arc<AccessLog>) {
+        self.access_log = Some(access_log);
+    }
 
-            listening_to: None,
-            stop_wait: None,
-        }
+    /// Serve HTTPS with the provided certificate.
+    pub fn set_tls(&mut self, tls: TlsCertificate) {
+        self.tls = Some(tls);
+    }
+
+    /// Serve the requests of each socket with `count` threads.
+    pub fn set_workers(&mut self, count: u16) {
+        // At least a worker is needed to serve any request
+        self.workers = cmp::max(count, 1);
+    }
+
+    /// Limit the time clients can take to send their requests and to
+    /// receive the responses.
+    pub fn set_read_timeout(&mut self, read_timeout: Duration) {
+        self.read_timeout = read_timeout;
+    }
+
+    /// Refuse requests with a body bigger than `max_body_size` bytes.
+    pub fn set_max_body_size(&mut self, max_body_size: u64) {
+        self.max_body_size = max_body_size;
     }
 
     pub fn add_route(
@@ -139,149 +739,228 @@ impl<App: Send + Sync + 'static> HttpServer<App> {
         method: Method,
         url: &str,
         handler: RequestHandler<App>,
+    ) {
+        self.push_handler(method, url, handler, false);
+    }
+
+    /// Add a route which requires the configured credentials to be called.
+    pub fn add_protected_route(
+        &mut self,
+        method: Method,
+        url: &str,
+        handler: RequestHandler<App>,
+    ) {
+        self.push_handler(method, url, handler, true);
+    }
+
+    fn push_handler(
+        &mut self,
+        method: Method,
+        url: &str,
+        handler: RequestHandler<App>,
+        protected: bool,
     ) {
         let route = Route::new(method, url);
         self.handlers
-            .try_lock()
+            .try_write()
             .unwrap()
-            .push(Handler::new(handler, route));
+            .push(Handler::new(handler, route, protected));
     }
 
-    pub fn listen(&mut self, bind: SocketAddr) -> Result<SocketAddr> {
-        macro_rules! header {
-            ($value:expr) => {
-                $value.parse::<tiny_http::Header>().unwrap()
-            };
-        }
-
-        // This will move to the thread, and the server will be stopped when
-        // the thread exits
-        let server = tiny_http::Server::http(bind)?;
-
-        // Store the server address into the struct
-        self.listening_to = Some(server.server_addr());
+    fn dispatcher(&self) -> Result<Arc<Dispatcher<App>>> {
+        let tls = match self.tls {
+            Some(ref tls) => Some(Arc::new(tls.acceptor()?)),
+            None => None,
+        };
 
-        let (stop_send, stop_recv) = mpsc::channel();
-        self.stop_wait = Some(stop_recv);
+        Ok(Arc::new(Dispatcher {
+            app: self.app.clone(),
+            handlers: self.handlers.clone(),
+            proxy_support: self.proxy_support.clone(),
+            auth: self.auth.clone(),
+            access_log: self.access_log.clone(),
+            tls,
+            verify_clients: self
+                .tls
+                .as_ref()
+                .map_or(false, TlsCertificate::verifies_clients),
+            read_timeout: self.read_timeout,
+            max_body_size: self.max_body_size,
+        }))
+    }
 
-        let app = self.app.clone();
-        let handlers_arc = self.handlers.clone();
-        let proxy_support = self.proxy_support.clone();
-        let should_stop = self.should_stop.clone();
-        thread::spawn(move || {
-            // Get a reference to the handlers
-            let handlers = &*handlers_arc.lock().unwrap();
+    /// Start accepting requests from the listener, returning its address.
+    ///
+    /// This can be called multiple times to listen on multiple sockets, and
+    /// `bind` is the address in the configuration the listener comes from.
+    pub fn listen(
+        &mut self,
+        bind: &ListenAddr,
+        listener: Listener,
+    ) -> Result<ListenAddr> {
+        let dispatcher = Arc::new(RwLock::new(self.dispatcher()?));
+        let (stop_accepting, stop) = UnixStream::pair()?;
+
+        let socket = listener.try_clone()?;
+        let addr = listener.addr().clone();
+
+        let (queue_send, queue_recv) = mpsc::channel::<Job>();
+        let queue_recv = Arc::new(Mutex::new(queue_recv));
+        let (stop_send, stop_recv) = mpsc::channel::<()>();
+
+        for _ in 0..self.workers {
+            let queue_recv = queue_recv.clone();
+            let dispatcher = dispatcher.clone();
+            let stop_send = stop_send.clone();
+            thread::spawn(move || {
+                loop {
+                    // The lock is released as soon as a request is received
+                    let next = queue_recv
+                        .lock()
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .recv();
+                    let (mut request, reply) = match next {
+                        Ok(job) => job,
+                        // All the connections were closed
+                        Err(..) => break,
+                    };
+
+                    let response = current(&dispatcher).serve(&mut request);
+                    let _ = reply.send((request, response));
+                }
 
-            // Prepare some headers which will be sent everytime
-            let server_header = header!(format!(
-                "Server: Fisher/{}",
-                env!("CARGO_PKG_VERSION")
-            ));
-            let content_type = header!("Content-Type: application/json");
+                drop(stop_send);
+            });
+        }
 
-            let ignored_method =
-                Method::NonStandard("X_FISHER_IGNORE_THIS".parse().unwrap());
+        let connections = Arc::new(Mutex::new(Connections::default()));
+        let accepting = {
+            let dispatcher = dispatcher.clone();
+            let connections = connections.clone();
+            thread::spawn(move || {
+                accept_connections(
+                    listener,
+                    stop,
+                    dispatcher,
+                    connections,
+                    queue_send,
+                );
+            })
+        };
 
-            for mut request in server.incoming_requests() {
-                // Don't accept any request anymore
-                if should_stop.load(Ordering::Relaxed) {
-                    break;
-                }
+        self.running.push(RunningServer {
+            bind: bind.clone(),
+            addr: addr.clone(),
+            socket,
+            dispatcher,
+            connections,
+            workers: self.workers,
+            stop_accepting,
+            accepting,
+            stopped: stop_recv,
+        });
 
-                // Convert the request to a Fisher request
-                let mut req = Request::Web((&mut request).into());
-
-                let response = (|| {
-                    if *request.method() == ignored_method {
-                        // This request comes with the non-standard method used
-                        // to shut the server down -- no client should be using
-                        // it
-                        Response::Forbidden
-                    } else if let Err(e) = proxy_support.fix_request(&mut req) {
-                        Response::BadRequest(e)
-                    } else {
-                        let method = request.method();
-                        let url = request.url();
-
-                        for handler in handlers {
-                            if let Some(args) = handler.matches(method, url) {
-                                return handler.call(&app, &req, args);
-                            }
-                        }
-
-                        Response::NotFound
-                    }
-                })();
+        Ok(addr)
+    }
 
-                let mut tiny_response = tiny_http::Response::from_data(
-                    response.json().into_bytes(),
-                )
-                .with_status_code(response.status());
+    /// Check if a server is running for the `bind` address.
+    pub fn is_listening_on(&self, bind: &ListenAddr) -> bool {
+        self.running.iter().any(|running| running.bind == *bind)
+    }
 
-                // Add custom headers from the response
-                if let Some(headers) = response.headers() {
-                    for header in &headers {
-                        tiny_response.add_header(header!(header));
-                    }
+    /// Take over the sockets another server created for `bind`, returning
+    /// their addresses. No connection is refused during the process.
+    ///
+    /// If this fails, the sockets not taken over yet are left to the other
+    /// server.
+    pub fn take_over(
+        &mut self,
+        old: &mut HttpServer<App>,
+        bind: &ListenAddr,
+    ) -> Result<Vec<ListenAddr>> {
+        // Check the TLS configuration before changing any server
+        let dispatcher = self.dispatcher()?;
+
+        let (taken, kept): (Vec<_>, Vec<_>) =
+            old.running.drain(..).partition(|r| r.bind == *bind);
+        old.running = kept;
+
+        let mut addrs = Vec::new();
+        let mut taken = taken.into_iter();
+        while let Some(running) = taken.next() {
+            addrs.push(running.addr.clone());
+
+            if running.workers == self.workers {
+                // The requests received from now on are served by this
+                // server (with its TLS configuration), while the ones in
+                // progress are completed
+                *running
+                    .dispatcher
+                    .write()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()) =
+                    dispatcher.clone();
+                self.running.push(running);
+            } else {
+                // The workers can't be changed on a running server, so a
+                // new one is started on the same socket before stopping the
+                // old one: both accept connections in the meantime
+                let listened = running
+                    .socket
+                    .try_clone()
+                    .and_then(|socket| self.listen(bind, socket));
+                if let Err(err) = listened {
+                    old.running.push(running);
+                    old.running.extend(taken);
+                    return Err(err);
                 }
-
-                tiny_response.add_header(server_header.clone());
-                tiny_response.add_header(content_type.clone());
-
-                let _ = request.respond(tiny_response);
+                running.stop();
             }
+        }
 
-            stop_send.send(()).unwrap();
-        });
-
-        Ok(self.listening_to.unwrap())
+        Ok(addrs)
     }
 
+    /// Stop all the servers, after serving the requests they received.
     pub fn stop(&mut self) -> bool {
-        if self.stop_wait.is_some() {
-            // Tell the server to stop
-            self.should_stop.store(true, Ordering::Relaxed);
-
-            // Send an HTTP request to force stopping the server
-            match TcpStream::connect(self.listening_to.unwrap()) {
-                Ok(mut conn) => {
-                    (writeln!(conn, "X_FISHER_IGNORE_THIS / HTTP/1.0\r\n\r\n"))
-                        .unwrap();
-                    conn.shutdown(Shutdown::Both).unwrap();
-                }
-                Err(..) => {
-                    return false;
-                }
-            }
+        if self.running.is_empty() {
+            return false;
+        }
 
-            if let Some(ref stop_wait) = self.stop_wait {
-                // Wait for the http server to stop
-                stop_wait.recv().unwrap();
-            } else {
-                unreachable!();
+        for running in self.running.drain(..) {
+            // Sockets not created by Fisher, like the ones passed by
+            // systemd, must not be removed
+            let socket = running.stop();
+            if let Some(path) = socket.created_path() {
+                let _ = fs::remove_file(path);
             }
-
-            self.stop_wait = None;
-            self.listening_to = None;
-
-            true
-        } else {
-            false
         }
+
+        true
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::thread;
     use std::time::Duration;
 
     use hyper;
     use hyper::status::StatusCode;
-    use tiny_http::Method;
-
-    use super::{Handler, HttpServer, Route};
+    #[cfg(feature = "tls")]
+    use openssl::ssl::SslStream;
+    use web::protocol::Method;
+
+    use super::{Handler, HttpAuth, HttpServer, Route, TlsCertificate};
+    use common::config::{
+        AuthConfig, ClientAuth, TlsConfig, UnixSocketConfig,
+    };
     use requests::Request;
     use utils::testing::*;
+    use utils::ListenAddr;
+    use web::listeners;
+    use web::proxies::ProxySupport;
     use web::responses::Response;
 
     struct DummyData(Vec<String>);
@@ -300,7 +979,25 @@ mod tests {
 
     fn dummy_handler() -> Handler<DummyData> {
         let route = Route::new(Method::Get, "/?");
-        Handler::new(Box::new(dummy_handler_fn), route)
+        Handler::new(Box::new(dummy_handler_fn), route, false)
+    }
+
+    fn listen<App>(server: &mut HttpServer<App>, addr: &str) -> ListenAddr
+    where
+        App: Send + Sync + 'static,
+    {
+        let addr = addr.parse::<ListenAddr>().unwrap();
+        let config = UnixSocketConfig::default();
+        let listener = listeners::bind(&addr, &config).unwrap().pop().unwrap();
+        server.listen(&addr, listener).unwrap()
+    }
+
+    fn auth_request(header: Option<&str>) -> Request {
+        let mut req = dummy_web_request();
+        if let Some(header) = header {
+            req.headers.append("Authorization", header);
+        }
+        req.into()
     }
 
     #[test]
@@ -372,6 +1069,72 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_auth_config() {
+        // No credentials means no authentication
+        let auth = HttpAuth::new(&AuthConfig::default()).unwrap();
+        assert!(auth.authorized(&auth_request(None)));
+
+        // Both username and password are needed for HTTP Basic
+        assert!(HttpAuth::new(&AuthConfig {
+            username: Some("user".into()),
+            ..AuthConfig::default()
+        })
+        .is_err());
+        assert!(HttpAuth::new(&AuthConfig {
+            password: Some("pass".into()),
+            ..AuthConfig::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_auth_token() {
+        let auth = HttpAuth::new(&AuthConfig {
+            token: Some("secret".into()),
+            ..AuthConfig::default()
+        })
+        .unwrap();
+
+        assert!(auth.authorized(&auth_request(Some("Bearer secret"))));
+        assert!(auth.authorized(&auth_request(Some("bearer secret"))));
+        assert!(!auth.authorized(&auth_request(None)));
+        assert!(!auth.authorized(&auth_request(Some("Bearer wrong"))));
+        assert!(!auth.authorized(&auth_request(Some("Bearer secre"))));
+        assert!(!auth.authorized(&auth_request(Some("secret"))));
+
+        // HTTP Basic is not accepted if it's not configured
+        assert!(!auth.authorized(&auth_request(Some("Basic c2VjcmV0"))));
+
+        assert_eq!(
+            auth.challenge().headers(),
+            Some(vec!["WWW-Authenticate: Bearer realm=\"Fisher\"".into()])
+        );
+    }
+
+    #[test]
+    fn test_auth_basic() {
+        let auth = HttpAuth::new(&AuthConfig {
+            username: Some("user".into()),
+            password: Some("pass".into()),
+            ..AuthConfig::default()
+        })
+        .unwrap();
+
+        // "user:pass" and "user:wrong"
+        let wrong = "Basic dXNlcjp3cm9uZw==";
+        assert!(auth.authorized(&auth_request(Some("Basic dXNlcjpwYXNz"))));
+        assert!(!auth.authorized(&auth_request(Some(wrong))));
+        assert!(!auth.authorized(&auth_request(Some("Basic !!!"))));
+        assert!(!auth.authorized(&auth_request(Some("Bearer dXNlcjpwYXNz"))));
+        assert!(!auth.authorized(&auth_request(None)));
+
+        assert_eq!(
+            auth.challenge().headers(),
+            Some(vec!["WWW-Authenticate: Basic realm=\"Fisher\"".into()])
+        );
+    }
+
     #[test]
     fn test_server() {
         macro_rules! req {
@@ -381,11 +1144,16 @@ mod tests {
         }
 
         // Create the server instance
-        let mut server = HttpServer::new(DummyData(vec!["test".into()]), 0);
+        let mut server = HttpServer::new(
+            DummyData(vec!["test".into()]),
+            ProxySupport::new(0),
+            HttpAuth::default(),
+        );
         server.add_route(Method::Get, "/?", Box::new(dummy_handler_fn));
 
-        // Start the server
-        let addr = server.listen("127.0.0.1:0".parse().unwrap()).unwrap();
+        // Start the server on both IPv4 and IPv6
+        let addr = listen(&mut server, "127.0.0.1:0");
+        let addr_v6 = listen(&mut server, "[::1]:0");
 
         let url = format!("http://{}", addr);
         let mut client = hyper::Client::new();
@@ -401,8 +1169,17 @@ mod tests {
                 .unwrap();
         assert_eq!(res.status, StatusCode::Ok);
 
+        // hyper can't parse IPv6 addresses in URLs, so a raw request is sent
+        if let ListenAddr::Tcp(addr) = addr_v6 {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(stream, "GET /test HTTP/1.0\r\n\r\n").unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            assert!(response.starts_with("HTTP/1.0 200"));
+        }
+
         // Stop the server
-        server.stop();
+        assert!(server.stop());
 
         assert!(req!(
             client,
@@ -411,4 +1188,429 @@ mod tests {
         )
         .is_err());
     }
+
+    #[test]
+    fn test_slow_clients() {
+        fn send(addr: &ListenAddr, request: &str) -> TcpStream {
+            let mut stream = match *addr {
+                ListenAddr::Tcp(addr) => TcpStream::connect(addr).unwrap(),
+                _ => panic!("a TCP address was expected"),
+            };
+            stream.set_read_timeout(Some(Duration::new(5, 0))).unwrap();
+            write!(stream, "{}", request).unwrap();
+            stream
+        }
+
+        fn response(mut stream: TcpStream) -> String {
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        }
+
+        let mut server = HttpServer::new(
+            DummyData(vec!["test".into()]),
+            ProxySupport::new(0),
+            HttpAuth::default(),
+        );
+        server.add_route(Method::Post, "/?", Box::new(dummy_handler_fn));
+        server.set_workers(1);
+        server.set_read_timeout(Duration::from_millis(200));
+        server.set_max_body_size(16);
+        let addr = listen(&mut server, "127.0.0.1:0");
+
+        // A client which stops sending data is answered when the timeout
+        // expires, without having to send anything else
+        let silent = send(
+            &addr,
+            "POST /test HTTP/1.1\r\nConnection: close\r\n\
+             Transfer-Encoding: chunked\r\n\r\n",
+        );
+        assert!(response(silent).starts_with("HTTP/1.1 408"));
+
+        // The only worker is then free to serve other clients
+        let fast = send(
+            &addr,
+            "POST /test HTTP/1.1\r\nConnection: close\r\n\
+             Content-Length: 4\r\n\r\ntest",
+        );
+        assert!(response(fast).starts_with("HTTP/1.1 200"));
+
+        // A client sending the body too slowly is stopped, even if it never
+        // waits more than the timeout between two pieces of data
+        let mut slow = send(
+            &addr,
+            "POST /test HTTP/1.1\r\nConnection: close\r\n\
+             Transfer-Encoding: chunked\r\n\r\n1\r\na\r\n",
+        );
+        for _ in 0..2 {
+            thread::sleep(Duration::from_millis(150));
+            write!(slow, "1\r\na\r\n").unwrap();
+        }
+        assert!(response(slow).starts_with("HTTP/1.1 408"));
+
+        // Bodies bigger than the limit are refused, even if chunked
+        let big = send(
+            &addr,
+            "POST /test HTTP/1.1\r\nConnection: close\r\n\
+             Content-Length: 17\r\n\r\n01234567890123456",
+        );
+        assert!(response(big).starts_with("HTTP/1.1 413"));
+        let big = send(
+            &addr,
+            "POST /test HTTP/1.1\r\nConnection: close\r\n\
+             Transfer-Encoding: chunked\r\n\r\n\
+             11\r\n01234567890123456\r\n0\r\n\r\n",
+        );
+        assert!(response(big).starts_with("HTTP/1.1 413"));
+
+        // Bodies don't need to be valid UTF-8
+        let mut binary = send(
+            &addr,
+            "POST /test HTTP/1.1\r\nConnection: close\r\n\
+             Content-Length: 2\r\n\r\n",
+        );
+        binary.write_all(&[0xff, 0xfe]).unwrap();
+        assert!(response(binary).starts_with("HTTP/1.1 200"));
+
+        assert!(server.stop());
+    }
+
+    #[test]
+    fn test_tls_config() {
+        // TLS is disabled without a certificate
+        assert!(TlsCertificate::load(&TlsConfig::default())
+            .unwrap()
+            .is_none());
+
+        // Both the certificate and the private key are needed
+        assert!(TlsCertificate::load(&TlsConfig {
+            certificate: Some("cert.pem".into()),
+            ..TlsConfig::default()
+        })
+        .is_err());
+
+        // Missing files are an error
+        assert!(TlsCertificate::load(&TlsConfig {
+            certificate: Some("/missing/cert.pem".into()),
+            private_key: Some("/missing/key.pem".into()),
+            ..TlsConfig::default()
+        })
+        .is_err());
+
+        // Invalid certificates are rejected while loading them
+        #[cfg(feature = "tls")]
+        {
+            use std::fs;
+            use tempdir::TempDir;
+
+            let dir = TempDir::new("fisher").unwrap();
+            let cert = dir.path().join("cert.pem");
+            let key = dir.path().join("key.pem");
+            fs::write(&cert, "not a certificate").unwrap();
+            fs::write(&key, self_signed().private_key).unwrap();
+
+            assert!(TlsCertificate::load(&TlsConfig {
+                certificate: Some(cert.to_str().unwrap().into()),
+                private_key: Some(key.to_str().unwrap().into()),
+                ..TlsConfig::default()
+            })
+            .is_err());
+
+            // So are client CA files without certificates
+            let valid = self_signed();
+            fs::write(&cert, &valid.certificate).unwrap();
+            fs::write(&key, &valid.private_key).unwrap();
+            let ca = dir.path().join("ca.pem");
+            fs::write(&ca, "").unwrap();
+
+            assert!(TlsCertificate::load(&TlsConfig {
+                certificate: Some(cert.to_str().unwrap().into()),
+                private_key: Some(key.to_str().unwrap().into()),
+                client_ca: Some(ca.to_str().unwrap().into()),
+                ..TlsConfig::default()
+            })
+            .is_err());
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    fn self_signed() -> TlsCertificate {
+        signed_by("localhost", None)
+    }
+
+    /// Create a certificate signed by `issuer`, or a self-signed one which
+    /// can sign other certificates.
+    #[cfg(feature = "tls")]
+    fn signed_by(
+        common_name: &str,
+        issuer: Option<&TlsCertificate>,
+    ) -> TlsCertificate {
+        use openssl::asn1::Asn1Time;
+        use openssl::ec::{EcGroup, EcKey};
+        use openssl::hash::MessageDigest;
+        use openssl::nid::Nid;
+        use openssl::pkey::PKey;
+        use openssl::x509::extension::BasicConstraints;
+        use openssl::x509::{X509NameBuilder, X509};
+
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", common_name).unwrap();
+        let name = name.build();
+
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+
+        if let Some(issuer) = issuer {
+            let issuer_cert = X509::from_pem(&issuer.certificate).unwrap();
+            let issuer_key =
+                PKey::private_key_from_pem(&issuer.private_key).unwrap();
+            cert.set_issuer_name(issuer_cert.subject_name()).unwrap();
+            cert.sign(&issuer_key, MessageDigest::sha256()).unwrap();
+        } else {
+            let ca = BasicConstraints::new().critical().ca().build().unwrap();
+            cert.append_extension(ca).unwrap();
+            cert.set_issuer_name(&name).unwrap();
+            cert.sign(&key, MessageDigest::sha256()).unwrap();
+        }
+
+        TlsCertificate {
+            certificate: cert.build().to_pem().unwrap(),
+            private_key: key.private_key_to_pem_pkcs8().unwrap(),
+            client_ca: None,
+            client_auth: ClientAuth::Required,
+        }
+    }
+
+    #[test]
+    fn test_take_over() {
+        let mut old = HttpServer::new(
+            DummyData(vec!["old".into()]),
+            ProxySupport::new(0),
+            HttpAuth::default(),
+        );
+        old.add_route(Method::Get, "/?", Box::new(dummy_handler_fn));
+        let bind = "127.0.0.1:0".parse::<ListenAddr>().unwrap();
+        let addr = listen(&mut old, "127.0.0.1:0");
+
+        let request = |addr: &ListenAddr, tls: bool| {
+            let addr = match *addr {
+                ListenAddr::Tcp(addr) => addr,
+                _ => panic!("a TCP socket was expected"),
+            };
+            let stream = TcpStream::connect(addr).unwrap();
+            let mut response = String::new();
+            if tls {
+                tls_request(stream, &mut response);
+            } else {
+                let mut stream = stream;
+                write!(stream, "GET /new HTTP/1.0\r\n\r\n").unwrap();
+                stream.read_to_string(&mut response).unwrap();
+            }
+            response
+        };
+        assert!(request(&addr, false).starts_with("HTTP/1.0 403"));
+
+        // The new server takes over the socket of the old one
+        let mut new = HttpServer::new(
+            DummyData(vec!["new".into()]),
+            ProxySupport::new(0),
+            HttpAuth::default(),
+        );
+        new.add_route(Method::Get, "/?", Box::new(dummy_handler_fn));
+        assert!(old.is_listening_on(&bind));
+        assert_eq!(new.take_over(&mut old, &bind).unwrap(), vec![addr.clone()]);
+        assert!(!old.is_listening_on(&bind));
+        assert!(new.is_listening_on(&bind));
+        assert!(!old.stop());
+
+        assert!(request(&addr, false).starts_with("HTTP/1.0 200"));
+
+        // Enabling TLS restarts the server on the same socket
+        #[cfg(feature = "tls")]
+        {
+            let mut tls = HttpServer::new(
+                DummyData(vec!["new".into()]),
+                ProxySupport::new(0),
+                HttpAuth::default(),
+            );
+            tls.add_route(Method::Get, "/?", Box::new(dummy_handler_fn));
+            tls.set_tls(self_signed());
+            tls.take_over(&mut new, &bind).unwrap();
+
+            assert!(request(&addr, true).starts_with("HTTP/1.0 200"));
+            assert!(tls.stop());
+        }
+
+        new.stop();
+    }
+
+    #[cfg(not(feature = "tls"))]
+    fn tls_request(_stream: TcpStream, _response: &mut String) {
+        unreachable!();
+    }
+
+    #[cfg(feature = "tls")]
+    fn tls_request(stream: TcpStream, response: &mut String) {
+        let mut stream = tls_connect(stream, None).unwrap();
+        write!(stream, "GET /new HTTP/1.0\r\n\r\n").unwrap();
+        stream.read_to_string(response).unwrap();
+    }
+
+    #[test]
+    fn test_unix_server() {
+        use std::os::unix::net::UnixStream;
+
+        use tempdir::TempDir;
+
+        let dir = TempDir::new("fisher").unwrap();
+        let path = dir.path().join("fisher.sock");
+
+        let mut server = HttpServer::new(
+            DummyData(vec!["test".into()]),
+            ProxySupport::new(0),
+            HttpAuth::default(),
+        );
+        server.add_route(Method::Get, "/?", Box::new(dummy_handler_fn));
+        let addr = format!("unix:{}", path.display());
+        assert_eq!(
+            listen(&mut server, &addr),
+            ListenAddr::Unix(path.clone())
+        );
+
+        let mut stream = UnixStream::connect(&path).unwrap();
+        write!(stream, "GET /test HTTP/1.0\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.0 200"));
+
+        // The socket is removed when the server stops
+        assert!(server.stop());
+        assert!(!path.exists());
+    }
+
+    #[cfg(feature = "tls")]
+    fn tls_connect(
+        conn: TcpStream,
+        client: Option<&TlsCertificate>,
+    ) -> Option<SslStream<TcpStream>> {
+        use openssl::pkey::PKey;
+        use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+        use openssl::x509::X509;
+
+        let mut builder = SslConnector::builder(SslMethod::tls()).unwrap();
+        builder.set_verify(SslVerifyMode::NONE);
+        if let Some(client) = client {
+            let cert = X509::from_pem(&client.certificate).unwrap();
+            let key = PKey::private_key_from_pem(&client.private_key).unwrap();
+            builder.set_certificate(&cert).unwrap();
+            builder.set_private_key(&key).unwrap();
+        }
+
+        builder
+            .build()
+            .configure()
+            .unwrap()
+            .verify_hostname(false)
+            .connect("localhost", conn)
+            .ok()
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn test_tls_server() {
+        let mut server = HttpServer::new(
+            DummyData(vec!["test".into()]),
+            ProxySupport::new(0),
+            HttpAuth::default(),
+        );
+        server.add_route(Method::Get, "/?", Box::new(dummy_handler_fn));
+        server.set_tls(self_signed());
+        let addr = match listen(&mut server, "127.0.0.1:0") {
+            ListenAddr::Tcp(addr) => addr,
+            _ => panic!("a TCP socket was expected"),
+        };
+
+        // Make a request over TLS
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut stream = tls_connect(stream, None).unwrap();
+        write!(stream, "GET /test HTTP/1.0\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.0 200"));
+
+        // The server must also be able to stop
+        assert!(server.stop());
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn test_tls_client_certificates() {
+        let ca = self_signed();
+        let client = signed_by("client", Some(&ca));
+        let unknown = signed_by("client", Some(&self_signed()));
+        let request = |addr, url, cert| {
+            let mut response = String::new();
+            let stream = TcpStream::connect(addr).unwrap();
+            // With TLS 1.3 the server might refuse the client only after
+            // the handshake seems to be done on the client side
+            if let Some(mut stream) = tls_connect(stream, cert) {
+                if write!(stream, "GET {} HTTP/1.0\r\n\r\n", url).is_ok() {
+                    let _ = stream.read_to_string(&mut response);
+                }
+            }
+            response
+        };
+
+        for &mode in &[ClientAuth::Optional, ClientAuth::Required] {
+            let mut server = HttpServer::new(
+                DummyData(vec!["test".into()]),
+                ProxySupport::new(0),
+                HttpAuth::default(),
+            );
+            server.add_protected_route(
+                Method::Get,
+                "/protected/?",
+                Box::new(dummy_handler_fn),
+            );
+            server.add_route(Method::Get, "/?", Box::new(dummy_handler_fn));
+
+            let mut tls = self_signed();
+            tls.client_ca = Some(ca.certificate.clone());
+            tls.client_auth = mode;
+            server.set_tls(tls);
+            let addr = match listen(&mut server, "127.0.0.1:0") {
+                ListenAddr::Tcp(addr) => addr,
+                _ => panic!("a TCP socket was expected"),
+            };
+
+            // Clients with a valid certificate can call every route
+            let response = request(addr, "/protected/test", Some(&client));
+            assert!(response.starts_with("HTTP/1.0 200"));
+
+            // Other clients can call only the public routes, if allowed
+            let public = request(addr, "/test", None);
+            let protected = request(addr, "/protected/test", None);
+            if mode == ClientAuth::Optional {
+                assert!(public.starts_with("HTTP/1.0 200"));
+                assert!(protected.starts_with("HTTP/1.0 403"));
+            } else {
+                assert_eq!(public, "");
+                assert_eq!(protected, "");
+            }
+
+            // Certificates not signed by the CA are always refused
+            assert_eq!(request(addr, "/test", Some(&unknown)), "");
+
+            assert!(server.stop());
+        }
+    }
 }
@@ -0,0 +1,485 @@
+// Copyright (C) 2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Sockets the HTTP server accepts connections from.
+
+use std::env;
+use std::fs::{self, Permissions};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+use nix::poll::{poll, EventFlags, PollFd};
+use nix::sys::socket::{getsockname, SockAddr};
+use nix::unistd::{chown, Gid};
+use users;
+
+use common::config::UnixSocketConfig;
+use common::prelude::*;
+use utils::ListenAddr;
+
+/// The first file descriptor passed by systemd, see sd_listen_fds(3).
+const SYSTEMD_FIRST_FD: RawFd = 3;
+
+lazy_static! {
+    // The sockets passed by systemd can be taken only once, so they're kept
+    // around for when the HTTP server is restarted
+    static ref SYSTEMD_SOCKETS: Mutex<Option<Vec<Socket>>> = Mutex::new(None);
+}
+
+enum Socket {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Socket {
+    fn try_clone(&self) -> Result<Socket> {
+        Ok(match *self {
+            Socket::Tcp(ref socket) => Socket::Tcp(socket.try_clone()?),
+            Socket::Unix(ref socket) => Socket::Unix(socket.try_clone()?),
+        })
+    }
+
+    fn as_raw_fd(&self) -> RawFd {
+        match *self {
+            Socket::Tcp(ref socket) => socket.as_raw_fd(),
+            Socket::Unix(ref socket) => socket.as_raw_fd(),
+        }
+    }
+
+    fn set_nonblocking(&self) -> Result<()> {
+        match *self {
+            Socket::Tcp(ref socket) => socket.set_nonblocking(true)?,
+            Socket::Unix(ref socket) => socket.set_nonblocking(true)?,
+        }
+        Ok(())
+    }
+
+    fn accept(&self) -> io::Result<Stream> {
+        Ok(match *self {
+            Socket::Tcp(ref socket) => Stream::Tcp(socket.accept()?.0),
+            Socket::Unix(ref socket) => Stream::Unix(socket.accept()?.0),
+        })
+    }
+
+    fn addr(&self) -> Result<ListenAddr> {
+        Ok(match *self {
+            Socket::Tcp(ref socket) => ListenAddr::Tcp(socket.local_addr()?),
+            Socket::Unix(ref socket) => {
+                match socket.local_addr()?.as_pathname() {
+                    Some(path) => ListenAddr::Unix(path.into()),
+                    // Abstract sockets can only come from systemd
+                    None => ListenAddr::Systemd,
+                }
+            }
+        })
+    }
+}
+
+/// A connection accepted from a listener.
+pub enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Stream {
+    /// Create a new handle to the same connection.
+    pub fn try_clone(&self) -> Result<Stream> {
+        Ok(match *self {
+            Stream::Tcp(ref stream) => Stream::Tcp(stream.try_clone()?),
+            Stream::Unix(ref stream) => Stream::Unix(stream.try_clone()?),
+        })
+    }
+
+    /// The address of the client, if it's connected over TCP.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        match *self {
+            Stream::Tcp(ref stream) => stream.peer_addr().ok(),
+            Stream::Unix(..) => None,
+        }
+    }
+
+    /// Limit the time each read and each write can take.
+    pub fn set_timeouts(&self, timeout: Duration) -> io::Result<()> {
+        // A zero timeout would be refused, and it means no timeout here
+        let timeout = Some(timeout).filter(|t| *t != Duration::from_secs(0));
+        match *self {
+            Stream::Tcp(ref stream) => {
+                stream.set_read_timeout(timeout)?;
+                stream.set_write_timeout(timeout)
+            }
+            Stream::Unix(ref stream) => {
+                stream.set_read_timeout(timeout)?;
+                stream.set_write_timeout(timeout)
+            }
+        }
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match *self {
+            Stream::Tcp(ref stream) => stream.shutdown(how),
+            Stream::Unix(ref stream) => stream.shutdown(how),
+        }
+    }
+
+    fn set_blocking(&self) -> io::Result<()> {
+        match *self {
+            Stream::Tcp(ref stream) => stream.set_nonblocking(false),
+            Stream::Unix(ref stream) => stream.set_nonblocking(false),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Tcp(ref mut stream) => stream.read(buf),
+            Stream::Unix(ref mut stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Tcp(ref mut stream) => stream.write(buf),
+            Stream::Unix(ref mut stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Stream::Tcp(ref mut stream) => stream.flush(),
+            Stream::Unix(ref mut stream) => stream.flush(),
+        }
+    }
+}
+
+/// A socket ready to accept connections.
+pub struct Listener {
+    socket: Socket,
+    addr: ListenAddr,
+    created_path: Option<PathBuf>,
+}
+
+impl Listener {
+    fn new(socket: Socket, created_path: Option<PathBuf>) -> Result<Self> {
+        // The socket might be shared by multiple servers while one takes
+        // over the other, so waiting for a connection must never block
+        socket.set_nonblocking()?;
+
+        Ok(Listener {
+            addr: socket.addr()?,
+            socket,
+            created_path,
+        })
+    }
+
+    /// Create a new handle to the same socket.
+    pub fn try_clone(&self) -> Result<Listener> {
+        Ok(Listener {
+            socket: self.socket.try_clone()?,
+            addr: self.addr.clone(),
+            created_path: self.created_path.clone(),
+        })
+    }
+
+    /// The address the socket is bound to.
+    pub fn addr(&self) -> &ListenAddr {
+        &self.addr
+    }
+
+    /// The path of the Unix socket created by Fisher, which should be removed
+    /// when it's not used anymore.
+    pub fn created_path(&self) -> Option<&Path> {
+        self.created_path.as_deref()
+    }
+
+    /// Wait for a new connection, applying `timeout` to each read and write
+    /// on it. `None` is returned as soon as `stop` becomes readable, for
+    /// example because its other end was closed.
+    pub fn accept(
+        &self,
+        stop: &UnixStream,
+        timeout: Duration,
+    ) -> Result<Option<Stream>> {
+        loop {
+            let mut fds = [
+                PollFd::new(self.socket.as_raw_fd(), EventFlags::POLLIN),
+                PollFd::new(stop.as_raw_fd(), EventFlags::POLLIN),
+            ];
+            match poll(&mut fds, -1) {
+                Err(::nix::Error::Sys(Errno::EINTR)) => continue,
+                result => result?,
+            };
+            let stopped = fds[1].revents().unwrap_or_else(EventFlags::empty);
+            if !stopped.is_empty() {
+                return Ok(None);
+            }
+
+            let stream = match self.socket.accept() {
+                Ok(stream) => stream,
+                // Another server sharing the socket got the connection, or
+                // the client gave up before it was accepted
+                Err(ref err)
+                    if err.kind() == io::ErrorKind::WouldBlock
+                        || err.kind() == io::ErrorKind::ConnectionAborted
+                        || err.kind() == io::ErrorKind::Interrupted =>
+                {
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
+
+            // Some platforms copy the flags of the socket to its connections
+            stream.set_blocking()?;
+            stream.set_timeouts(timeout)?;
+            return Ok(Some(stream));
+        }
+    }
+}
+
+/// Bind the provided address, returning the sockets to listen on.
+pub fn bind(
+    addr: &ListenAddr,
+    unix_config: &UnixSocketConfig,
+) -> Result<Vec<Listener>> {
+    match *addr {
+        ListenAddr::Tcp(addr) => {
+            let socket = Socket::Tcp(TcpListener::bind(addr)?);
+            Ok(vec![Listener::new(socket, None)?])
+        }
+        ListenAddr::Unix(ref path) => Ok(vec![bind_unix(path, unix_config)?]),
+        ListenAddr::Systemd => systemd_listeners(),
+    }
+}
+
+fn bind_unix(path: &Path, config: &UnixSocketConfig) -> Result<Listener> {
+    // Remove the socket left behind by a previous instance, but never
+    // remove anything else
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            fs::remove_file(path)?;
+        } else {
+            return Err(ErrorKind::UnixSocketPathInUse(
+                path.to_string_lossy().into(),
+            ).into());
+        }
+    }
+
+    let socket = UnixListener::bind(path)?;
+    if let Err(err) = set_permissions(path, config) {
+        let _ = fs::remove_file(path);
+        return Err(err);
+    }
+
+    Listener::new(Socket::Unix(socket), Some(path.into()))
+}
+
+/// Apply the configured permissions to a Unix socket.
+pub fn set_permissions(path: &Path, config: &UnixSocketConfig) -> Result<()> {
+    let mode = u32::from_str_radix(&config.mode, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| ErrorKind::InvalidUnixSocketMode(config.mode.clone()))?;
+    let gid = if let Some(ref name) = config.group {
+        let group = users::get_group_by_name(name)
+            .ok_or_else(|| ErrorKind::UnixSocketGroupNotFound(name.clone()))?;
+        Some(Gid::from_raw(group.gid()))
+    } else {
+        None
+    };
+
+    fs::set_permissions(path, Permissions::from_mode(mode))?;
+    if gid.is_some() {
+        chown(path, None, gid)?;
+    }
+
+    Ok(())
+}
+
+/// Count the sockets systemd passed to this process.
+fn listen_fds(listen_pid: Option<&str>, listen_fds: Option<&str>) -> RawFd {
+    // The variables might have been set for another process
+    let pid = listen_pid.and_then(|pid| pid.parse::<u32>().ok());
+    if pid != Some(process::id()) {
+        return 0;
+    }
+
+    listen_fds
+        .and_then(|count| count.parse::<RawFd>().ok())
+        .unwrap_or(0)
+}
+
+fn take_systemd_sockets() -> Result<Vec<Socket>> {
+    let count = listen_fds(
+        env::var("LISTEN_PID").ok().as_deref(),
+        env::var("LISTEN_FDS").ok().as_deref(),
+    );
+    if count <= 0 {
+        return Err(ErrorKind::SystemdSocketsMissing.into());
+    }
+
+    // The variables are not valid for the child processes
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    let mut sockets = Vec::new();
+    for fd in SYSTEMD_FIRST_FD..SYSTEMD_FIRST_FD + count {
+        // Don't leak the sockets to the scripts
+        fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+
+        sockets.push(match getsockname(fd)? {
+            SockAddr::Inet(..) => {
+                Socket::Tcp(unsafe { TcpListener::from_raw_fd(fd) })
+            }
+            SockAddr::Unix(..) => {
+                Socket::Unix(unsafe { UnixListener::from_raw_fd(fd) })
+            }
+            _ => return Err(ErrorKind::SystemdSocketUnsupported(fd).into()),
+        });
+    }
+
+    Ok(sockets)
+}
+
+fn systemd_listeners() -> Result<Vec<Listener>> {
+    let mut cache = SYSTEMD_SOCKETS.lock()?;
+    if cache.is_none() {
+        *cache = Some(take_systemd_sockets()?);
+    }
+
+    let mut result = Vec::new();
+    for socket in cache.as_ref().unwrap() {
+        result.push(Listener::new(socket.try_clone()?, None)?);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::net::TcpStream;
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixStream;
+    use std::process;
+    use std::time::Duration;
+
+    use tempdir::TempDir;
+
+    use common::config::UnixSocketConfig;
+    use utils::ListenAddr;
+
+    use super::{bind, listen_fds, Stream};
+
+    #[test]
+    fn test_bind_tcp() {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let listeners =
+            bind(&addr, &UnixSocketConfig::default()).unwrap();
+
+        assert_eq!(listeners.len(), 1);
+        if let ListenAddr::Tcp(addr) = *listeners[0].addr() {
+            assert!(addr.port() != 0);
+        } else {
+            panic!("a TCP socket was expected");
+        }
+        assert!(listeners[0].created_path().is_none());
+    }
+
+    #[test]
+    fn test_bind_unix() {
+        let dir = TempDir::new("fisher").unwrap();
+        let path = dir.path().join("fisher.sock");
+        let addr = ListenAddr::Unix(path.clone());
+
+        let config = UnixSocketConfig {
+            mode: "0600".into(),
+            group: None,
+        };
+        let listeners = bind(&addr, &config).unwrap();
+        assert_eq!(listeners[0].addr(), &addr);
+        assert_eq!(listeners[0].created_path(), Some(path.as_path()));
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o600);
+
+        // Stale sockets are replaced
+        drop(listeners);
+        assert!(bind(&addr, &config).is_ok());
+
+        // Invalid modes are rejected
+        assert!(bind(&addr, &UnixSocketConfig {
+            mode: "rw-rw----".into(),
+            group: None,
+        }).is_err());
+
+        // Other files are never removed
+        let file = dir.path().join("file");
+        File::create(&file).unwrap();
+        assert!(bind(&ListenAddr::Unix(file.clone()), &config).is_err());
+        assert!(file.exists());
+    }
+
+    #[test]
+    fn test_listen_fds() {
+        let pid = process::id().to_string();
+        let pid = Some(pid.as_str());
+
+        assert_eq!(listen_fds(pid, Some("2")), 2);
+        assert_eq!(listen_fds(pid, None), 0);
+        assert_eq!(listen_fds(pid, Some("a")), 0);
+        assert_eq!(listen_fds(None, Some("2")), 0);
+        assert_eq!(listen_fds(Some("1"), Some("2")), 0);
+    }
+
+    #[test]
+    fn test_accept() {
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let config = UnixSocketConfig::default();
+        let listener = bind(&addr, &config).unwrap().pop().unwrap();
+        let addr = match *listener.addr() {
+            ListenAddr::Tcp(addr) => addr,
+            _ => panic!("a TCP socket was expected"),
+        };
+        let (stop, stop_handle) = UnixStream::pair().unwrap();
+
+        // The timeouts are applied to the accepted connections only
+        let client = TcpStream::connect(addr).unwrap();
+        let timeout = Duration::from_secs(10);
+        let accepted = listener.accept(&stop, timeout).unwrap().unwrap();
+        let accepted = match accepted {
+            Stream::Tcp(stream) => stream,
+            Stream::Unix(..) => panic!("a TCP connection was expected"),
+        };
+        assert_eq!(accepted.peer_addr().unwrap(), client.local_addr().unwrap());
+        assert_eq!(accepted.read_timeout().unwrap(), Some(timeout));
+        assert_eq!(accepted.write_timeout().unwrap(), Some(timeout));
+        assert_eq!(client.read_timeout().unwrap(), None);
+
+        // Closing the other end of the stop socket stops waiting
+        drop(stop_handle);
+        assert!(listener.accept(&stop, timeout).unwrap().is_none());
+    }
+}
@@ -13,8 +13,11 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+mod access_log;
 mod http;
 mod app;
+mod listeners;
+mod protocol;
 mod rate_limits;
 mod requests;
 mod responses;
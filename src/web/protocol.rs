@@ -0,0 +1,982 @@
+// Copyright (C) 2017 Pietro Albini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The subset of HTTP/1.1 spoken by the built-in server.
+
+use std::cmp;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::mem;
+use std::net::{Shutdown, SocketAddr};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "tls")]
+use openssl::ssl::SslStream;
+#[cfg(feature = "tls")]
+use openssl::x509::X509VerifyResult;
+
+use common::prelude::*;
+use utils::UtcDate;
+use web::listeners::Stream;
+
+/// The maximum size of the request line and the headers, combined.
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+/// The maximum number of headers in a request.
+const MAX_HEADERS: usize = 128;
+/// The maximum size of the lines of chunked bodies.
+const MAX_CHUNK_LINE: usize = 4096;
+/// How long the data sent by a client is discarded before closing the
+/// connection.
+const LINGER: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Patch,
+    Options,
+    Other(String),
+}
+
+impl<'a> From<&'a str> for Method {
+    fn from(method: &'a str) -> Method {
+        match method {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "PATCH" => Method::Patch,
+            "OPTIONS" => Method::Options,
+            other => Method::Other(other.into()),
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Patch => "PATCH",
+            Method::Options => "OPTIONS",
+            Method::Other(ref other) => other,
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Version {
+    Http10,
+    Http11,
+}
+
+impl Version {
+    fn as_str(self) -> &'static str {
+        match self {
+            Version::Http10 => "1.0",
+            Version::Http11 => "1.1",
+        }
+    }
+}
+
+/// How the end of the body is found, and how much of it is left to read.
+#[derive(Debug, PartialEq, Eq)]
+enum Framing {
+    Length(u64),
+    Chunked(Chunk),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Chunk {
+    Size,
+    Data(u64),
+    End,
+    Trailer,
+}
+
+/// A connection to a client, encrypted or not.
+pub enum Transport {
+    Plain(Stream),
+    #[cfg(feature = "tls")]
+    Tls(Box<SslStream<Stream>>),
+}
+
+impl Transport {
+    fn stream(&self) -> &Stream {
+        match *self {
+            Transport::Plain(ref stream) => stream,
+            #[cfg(feature = "tls")]
+            Transport::Tls(ref stream) => stream.get_ref(),
+        }
+    }
+
+    /// Whether the client presented a valid certificate.
+    fn client_verified(&self) -> bool {
+        match *self {
+            Transport::Plain(..) => false,
+            #[cfg(feature = "tls")]
+            Transport::Tls(ref stream) => {
+                let ssl = stream.ssl();
+                ssl.peer_certificate().is_some()
+                    && ssl.verify_result() == X509VerifyResult::OK
+            }
+        }
+    }
+
+    /// Tell the client nothing else will be sent.
+    fn shutdown(&mut self) {
+        #[cfg(feature = "tls")]
+        {
+            if let Transport::Tls(ref mut stream) = *self {
+                let _ = stream.shutdown();
+            }
+        }
+        let _ = self.stream().shutdown(Shutdown::Write);
+    }
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Transport::Plain(ref mut stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            Transport::Tls(ref mut stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Transport::Plain(ref mut stream) => stream.write(buf),
+            #[cfg(feature = "tls")]
+            Transport::Tls(ref mut stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Transport::Plain(ref mut stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            Transport::Tls(ref mut stream) => stream.flush(),
+        }
+    }
+}
+
+/// A request received from a client.
+pub struct Request {
+    method: Method,
+    url: String,
+    version: Version,
+    headers: Vec<(String, String)>,
+    remote_addr: Option<SocketAddr>,
+    client_verified: bool,
+    received_at: Instant,
+
+    framing: Framing,
+    keep_alive: bool,
+    expects_continue: bool,
+    body: Result<Vec<u8>>,
+}
+
+impl Request {
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn http_version(&self) -> &'static str {
+        self.version.as_str()
+    }
+
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    /// The value of the first header called `name`, in any case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|header| header.0.eq_ignore_ascii_case(name))
+            .map(|header| header.1.as_str())
+    }
+
+    /// The address of the client, if it's connected over TCP.
+    pub fn remote_addr(&self) -> Option<&SocketAddr> {
+        self.remote_addr.as_ref()
+    }
+
+    /// Whether the client presented a valid TLS certificate.
+    pub fn client_verified(&self) -> bool {
+        self.client_verified
+    }
+
+    /// When the head of the request was received.
+    pub fn received_at(&self) -> Instant {
+        self.received_at
+    }
+
+    /// Take the body read by `Connection::read_body`, or the reason why it
+    /// couldn't be read.
+    pub fn take_body(&mut self) -> Result<Vec<u8>> {
+        mem::replace(&mut self.body, Ok(Vec::new()))
+    }
+}
+
+/// A response to send to a client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    status: u16,
+    headers: Vec<String>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, body: Vec<u8>) -> Self {
+        Response {
+            status,
+            headers: Vec::new(),
+            body,
+        }
+    }
+
+    /// Add a header, formatted as `Name: value`.
+    pub fn add_header(&mut self, header: String) {
+        self.headers.push(header);
+    }
+}
+
+/// What was received from a client waiting for a request.
+pub enum Received {
+    Request(Box<Request>),
+    /// The connection was closed, or the client stopped sending data.
+    Closed,
+    /// The request is malformed, and it should be refused with this status.
+    Invalid(u16),
+}
+
+/// A connection to a client, which sends its requests one after the other.
+pub struct Connection {
+    reader: BufReader<Transport>,
+    remote_addr: Option<SocketAddr>,
+    client_verified: bool,
+    keep_alive: bool,
+}
+
+impl Connection {
+    pub fn new(transport: Transport, remote_addr: Option<SocketAddr>) -> Self {
+        Connection {
+            client_verified: transport.client_verified(),
+            reader: BufReader::new(transport),
+            remote_addr,
+            keep_alive: true,
+        }
+    }
+
+    /// Whether another request can be received from the connection.
+    pub fn keep_alive(&self) -> bool {
+        self.keep_alive
+    }
+
+    /// Wait for the client to send the head of a request.
+    pub fn receive(&mut self) -> Received {
+        // Waiting for the next request is not an error, even if the client
+        // never sends it
+        match self.reader.fill_buf() {
+            Ok(available) if !available.is_empty() => {}
+            _ => return Received::Closed,
+        }
+
+        let mut budget = MAX_HEAD_SIZE;
+        let mut lines = Vec::new();
+        loop {
+            if budget == 0 || lines.len() > MAX_HEADERS {
+                return Received::Invalid(431);
+            }
+
+            let line = match read_line(&mut self.reader, budget) {
+                Ok(line) => line,
+                Err(ref err) if err.kind() == io::ErrorKind::InvalidData => {
+                    return Received::Invalid(431);
+                }
+                Err(ref err)
+                    if err.kind() == io::ErrorKind::WouldBlock
+                        || err.kind() == io::ErrorKind::TimedOut =>
+                {
+                    return Received::Invalid(408);
+                }
+                Err(..) => return Received::Closed,
+            };
+            budget = budget.saturating_sub(line.len() + 1);
+
+            if !line.is_empty() {
+                lines.push(line);
+            } else if !lines.is_empty() {
+                break;
+            }
+            // Empty lines before the request line are ignored
+        }
+
+        match parse_head(lines) {
+            Ok(mut request) => {
+                request.remote_addr = self.remote_addr;
+                request.client_verified = self.client_verified;
+                Received::Request(Box::new(request))
+            }
+            Err(status) => Received::Invalid(status),
+        }
+    }
+
+    /// Read the body of the request, refusing it if it's larger than
+    /// `limit` bytes or if it's not received within `timeout`.
+    pub fn read_body(
+        &mut self,
+        request: &mut Request,
+        limit: u64,
+        timeout: Duration,
+    ) {
+        request.body = self.body(request, limit, timeout);
+    }
+
+    fn body(
+        &mut self,
+        request: &mut Request,
+        limit: u64,
+        timeout: Duration,
+    ) -> Result<Vec<u8>> {
+        // Don't even start reading if the client announced a bigger body
+        if let Framing::Length(length) = request.framing {
+            if length > limit {
+                return Err(ErrorKind::RequestBodyTooLarge(limit).into());
+            }
+        }
+
+        // The client waits for the permission to send the body
+        if request.expects_continue && request.framing != Framing::Length(0) {
+            let stream = self.reader.get_mut();
+            stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+            stream.flush()?;
+        }
+
+        // The body might be chunked, so the limit is enforced while reading
+        let started = Instant::now();
+        let mut reader = Body {
+            reader: &mut self.reader,
+            framing: &mut request.framing,
+        }
+        .take(limit.saturating_add(1));
+
+        // The timeouts of the socket only limit the time of each read, so
+        // the time the whole body takes is checked here
+        let mut body = Vec::new();
+        let mut buffer = [0; 8192];
+        loop {
+            let read = match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {
+                    continue;
+                }
+                Err(ref err)
+                    if err.kind() == io::ErrorKind::WouldBlock
+                        || err.kind() == io::ErrorKind::TimedOut =>
+                {
+                    return Err(ErrorKind::RequestTimeout.into());
+                }
+                Err(err) => return Err(err.into()),
+            };
+
+            body.extend_from_slice(&buffer[..read]);
+            if body.len() as u64 > limit {
+                return Err(ErrorKind::RequestBodyTooLarge(limit).into());
+            } else if started.elapsed() > timeout {
+                return Err(ErrorKind::RequestTimeout.into());
+            }
+        }
+
+        Ok(body)
+    }
+
+    /// Send the response to a request.
+    pub fn respond(
+        &mut self,
+        request: &Request,
+        response: &Response,
+    ) -> io::Result<()> {
+        // If the body wasn't read the next request would start in the
+        // middle of it, so the connection can't be reused
+        let complete = request.framing == Framing::Length(0);
+        self.keep_alive = request.keep_alive && complete;
+
+        let head = request.method == Method::Head;
+        self.write_response(request.version, head, response)
+    }
+
+    /// Refuse a request which couldn't be received.
+    pub fn reject(&mut self, status: u16) -> io::Result<()> {
+        self.keep_alive = false;
+        let response = Response::new(status, Vec::new());
+        self.write_response(Version::Http11, false, &response)
+    }
+
+    fn write_response(
+        &mut self,
+        version: Version,
+        head: bool,
+        response: &Response,
+    ) -> io::Result<()> {
+        let mut data = format!(
+            "HTTP/{} {} {}\r\n",
+            version.as_str(),
+            response.status,
+            reason(response.status),
+        );
+        for header in &response.headers {
+            // Headers spanning multiple lines would corrupt the response
+            if !header.contains(&['\r', '\n'][..]) {
+                data.push_str(header);
+                data.push_str("\r\n");
+            }
+        }
+        data.push_str(&format!("Date: {}\r\n", UtcDate::now().http()));
+
+        let has_body = !matches!(response.status, 100..=199 | 204 | 304);
+        if has_body {
+            data.push_str(&format!(
+                "Content-Length: {}\r\n",
+                response.body.len()
+            ));
+        }
+
+        if !self.keep_alive {
+            data.push_str("Connection: close\r\n");
+        } else if version == Version::Http10 {
+            data.push_str("Connection: keep-alive\r\n");
+        }
+        data.push_str("\r\n");
+
+        let mut data = data.into_bytes();
+        if has_body && !head {
+            data.extend_from_slice(&response.body);
+        }
+
+        let stream = self.reader.get_mut();
+        stream.write_all(&data)?;
+        stream.flush()
+    }
+
+    /// Close the connection. Closing a socket before reading all the data
+    /// sent by the client resets it, possibly before the client received
+    /// the response, so the rest of the data is discarded first.
+    pub fn close(mut self) {
+        self.reader.get_mut().shutdown();
+
+        let deadline = Instant::now() + LINGER;
+        let _ = self.reader.get_ref().stream().set_timeouts(LINGER);
+
+        let mut buffer = [0; 8192];
+        while Instant::now() < deadline {
+            match self.reader.read(&mut buffer) {
+                Ok(0) | Err(..) => break,
+                Ok(..) => {}
+            }
+        }
+    }
+}
+
+/// Reads the body of a request, removing the chunked encoding.
+struct Body<'a> {
+    reader: &'a mut BufReader<Transport>,
+    framing: &'a mut Framing,
+}
+
+impl<'a> Read for Body<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match *self.framing {
+                Framing::Length(0) => return Ok(0),
+                Framing::Length(ref mut left)
+                | Framing::Chunked(Chunk::Data(ref mut left)) => {
+                    let max = cmp::min(buf.len() as u64, *left) as usize;
+                    let read = self.reader.read(&mut buf[..max])?;
+                    if read == 0 && max != 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+
+                    *left -= read as u64;
+                    if *left == 0 {
+                        if let Framing::Chunked(..) = *self.framing {
+                            *self.framing = Framing::Chunked(Chunk::End);
+                        }
+                    }
+                    return Ok(read);
+                }
+                Framing::Chunked(Chunk::Size) => {
+                    let line = read_line(self.reader, MAX_CHUNK_LINE)?;
+
+                    // Chunk extensions are ignored
+                    let size = line.split(|c| *c == b';').next().unwrap();
+                    let size = parse_hex(size).ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "invalid chunk size",
+                        )
+                    })?;
+
+                    *self.framing = Framing::Chunked(if size == 0 {
+                        Chunk::Trailer
+                    } else {
+                        Chunk::Data(size)
+                    });
+                }
+                Framing::Chunked(Chunk::End) => {
+                    // Each chunk is followed by an empty line
+                    read_line(self.reader, 0)?;
+                    *self.framing = Framing::Chunked(Chunk::Size);
+                }
+                Framing::Chunked(Chunk::Trailer) => {
+                    // The trailer fields are ignored
+                    if read_line(self.reader, MAX_CHUNK_LINE)?.is_empty() {
+                        *self.framing = Framing::Length(0);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Read a line, without its terminator, refusing lines longer than `limit`.
+fn read_line<R: BufRead>(reader: &mut R, limit: usize) -> io::Result<Vec<u8>> {
+    let mut line = Vec::new();
+    loop {
+        let (found, used) = {
+            let available = match reader.fill_buf() {
+                Ok(available) => available,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            if available.is_empty() {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+
+            match available.iter().position(|c| *c == b'\n') {
+                Some(end) => {
+                    line.extend_from_slice(&available[..end]);
+                    (true, end + 1)
+                }
+                None => {
+                    line.extend_from_slice(available);
+                    (false, available.len())
+                }
+            }
+        };
+        reader.consume(used);
+
+        if found && line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        if line.len() > limit {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "line too long",
+            ));
+        } else if found {
+            return Ok(line);
+        }
+    }
+}
+
+fn parse_hex(digits: &[u8]) -> Option<u64> {
+    let digits = trim(digits);
+    if digits.is_empty() {
+        return None;
+    }
+
+    digits.iter().try_fold(0u64, |acc, digit| {
+        let value = (*digit as char).to_digit(16)?;
+        acc.checked_mul(16)?.checked_add(u64::from(value))
+    })
+}
+
+fn trim(mut value: &[u8]) -> &[u8] {
+    while let Some((&c, rest)) = value.split_first() {
+        if c != b' ' && c != b'\t' {
+            break;
+        }
+        value = rest;
+    }
+    while let Some((&c, rest)) = value.split_last() {
+        if c != b' ' && c != b'\t' {
+            break;
+        }
+        value = rest;
+    }
+    value
+}
+
+fn is_token(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c)
+}
+
+/// The comma-separated values of all the headers called `name`.
+fn list<'a>(headers: &'a [(String, String)], name: &str) -> Vec<&'a str> {
+    headers
+        .iter()
+        .filter(|header| header.0.eq_ignore_ascii_case(name))
+        .flat_map(|header| header.1.split(','))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+fn parse_head(lines: Vec<Vec<u8>>) -> ::std::result::Result<Request, u16> {
+    let mut lines = lines.into_iter();
+
+    let request_line = String::from_utf8(lines.next().unwrap_or_default())
+        .map_err(|_| 400u16)?;
+    let parts = request_line.split(' ').collect::<Vec<_>>();
+    if parts.len() != 3
+        || parts[0].is_empty()
+        || !parts[0].bytes().all(is_token)
+        || parts[1].is_empty()
+        || parts[1].bytes().any(|c| c.is_ascii_control())
+    {
+        return Err(400);
+    }
+    let version = match parts[2] {
+        "HTTP/1.1" => Version::Http11,
+        "HTTP/1.0" => Version::Http10,
+        other if other.starts_with("HTTP/") => return Err(505),
+        _ => return Err(400),
+    };
+
+    let mut headers = Vec::new();
+    for line in lines {
+        // Headers spanning multiple lines are obsolete
+        if line[0] == b' ' || line[0] == b'\t' {
+            return Err(400);
+        }
+
+        let line = String::from_utf8(line).map_err(|_| 400u16)?;
+        let colon = line.find(':').ok_or(400u16)?;
+        let name = &line[..colon];
+        let value = line[colon + 1..].trim_matches(|c| c == ' ' || c == '\t');
+        if name.is_empty()
+            || !name.bytes().all(is_token)
+            || value.bytes().any(|c| c.is_ascii_control() && c != b'\t')
+        {
+            return Err(400);
+        }
+
+        headers.push((name.to_string(), value.to_string()));
+    }
+
+    let codings = list(&headers, "Transfer-Encoding");
+    let lengths = list(&headers, "Content-Length");
+    let framing = if !codings.is_empty() {
+        // Only chunked bodies are supported, and sending a length along with
+        // them is a sign of request smuggling
+        if codings.len() != 1 || !codings[0].eq_ignore_ascii_case("chunked") {
+            return Err(501);
+        } else if !lengths.is_empty() {
+            return Err(400);
+        }
+        Framing::Chunked(Chunk::Size)
+    } else if let Some(length) = lengths.first() {
+        if !length.bytes().all(|c| c.is_ascii_digit())
+            || lengths.iter().any(|other| other != length)
+        {
+            return Err(400);
+        }
+        Framing::Length(length.parse().map_err(|_| 400u16)?)
+    } else {
+        Framing::Length(0)
+    };
+
+    let connection = list(&headers, "Connection");
+    let has = |option| {
+        connection.iter().any(|value| value.eq_ignore_ascii_case(option))
+    };
+    let keep_alive = !has("close")
+        && (version == Version::Http11 || has("keep-alive"));
+
+    let expects_continue = version == Version::Http11
+        && list(&headers, "Expect")
+            .iter()
+            .any(|value| value.eq_ignore_ascii_case("100-continue"));
+
+    Ok(Request {
+        method: Method::from(parts[0]),
+        url: parts[1].to_string(),
+        version,
+        headers,
+        remote_addr: None,
+        client_verified: false,
+        received_at: Instant::now(),
+
+        framing,
+        keep_alive,
+        expects_continue,
+        body: Ok(Vec::new()),
+    })
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        422 => "Unprocessable Entity",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+    use std::time::Duration;
+
+    use common::prelude::*;
+    use web::listeners::Stream;
+
+    use super::{Connection, Method, Received, Request, Response, Transport};
+
+    fn connection() -> (Connection, UnixStream) {
+        let (server, client) = UnixStream::pair().unwrap();
+        server.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let transport = Transport::Plain(Stream::Unix(server));
+        (Connection::new(transport, None), client)
+    }
+
+    fn receive(conn: &mut Connection) -> Request {
+        match conn.receive() {
+            Received::Request(request) => *request,
+            Received::Closed => panic!("the connection was closed"),
+            Received::Invalid(status) => panic!("invalid request: {}", status),
+        }
+    }
+
+    fn body(conn: &mut Connection, request: &mut Request) -> Result<Vec<u8>> {
+        conn.read_body(request, 16, Duration::from_secs(1));
+        request.take_body()
+    }
+
+    fn invalid(head: &str) -> u16 {
+        let (mut conn, mut client) = connection();
+        write!(client, "{}", head).unwrap();
+        match conn.receive() {
+            Received::Invalid(status) => status,
+            _ => panic!("the request was expected to be invalid"),
+        }
+    }
+
+    #[test]
+    fn test_receive() {
+        let (mut conn, mut client) = connection();
+        write!(
+            client,
+            "\r\nPOST /hook/a.sh?b=c HTTP/1.1\r\nHost: localhost\r\n\
+             X-Test:  a, b \r\nx-test: c\r\nContent-Length: 4\r\n\r\ntest",
+        )
+        .unwrap();
+
+        let mut request = receive(&mut conn);
+        assert_eq!(request.method(), &Method::Post);
+        assert_eq!(request.url(), "/hook/a.sh?b=c");
+        assert_eq!(request.http_version(), "1.1");
+        assert_eq!(request.header("X-TEST"), Some("a, b"));
+        assert_eq!(request.headers().len(), 4);
+        assert_eq!(body(&mut conn, &mut request).unwrap(), b"test".to_vec());
+
+        // The connection is kept open for the next request
+        conn.respond(&request, &Response::new(200, b"ok".to_vec()))
+            .unwrap();
+        assert!(conn.keep_alive());
+
+        write!(client, "GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        let request = receive(&mut conn);
+        conn.respond(&request, &Response::new(200, b"ok".to_vec()))
+            .unwrap();
+        assert!(!conn.keep_alive());
+
+        drop(client);
+        assert!(matches!(conn.receive(), Received::Closed));
+    }
+
+    #[test]
+    fn test_invalid_requests() {
+        assert_eq!(invalid("GET /\r\n\r\n"), 400);
+        assert_eq!(invalid("GET  / HTTP/1.1\r\n\r\n"), 400);
+        assert_eq!(invalid("GET / HTTP/2.0\r\n\r\n"), 505);
+        assert_eq!(invalid("GET / HTTP/1.1\r\nNo colon\r\n\r\n"), 400);
+        assert_eq!(invalid("GET / HTTP/1.1\r\nA: b\r\n folded\r\n\r\n"), 400);
+        assert_eq!(
+            invalid("GET / HTTP/1.1\r\nContent-Length: 1, 2\r\n\r\n"),
+            400
+        );
+        assert_eq!(
+            invalid("GET / HTTP/1.1\r\nContent-Length: -1\r\n\r\n"),
+            400
+        );
+        assert_eq!(
+            invalid("GET / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n"),
+            501
+        );
+        assert_eq!(
+            invalid(
+                "GET / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\
+                 Content-Length: 1\r\n\r\n"
+            ),
+            400
+        );
+
+        let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(65536));
+        let (mut conn, client) = connection();
+        let writer = thread::spawn(move || {
+            let mut client = client;
+            let _ = write!(client, "{}", long);
+        });
+        assert!(matches!(conn.receive(), Received::Invalid(431)));
+        drop(conn);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn test_chunked_body() {
+        let (mut conn, mut client) = connection();
+        write!(
+            client,
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+             4;ext=1\r\ntest\r\n2\r\n!!\r\n0\r\nTrailer: a\r\n\r\n",
+        )
+        .unwrap();
+        let mut request = receive(&mut conn);
+        assert_eq!(body(&mut conn, &mut request).unwrap(), b"test!!".to_vec());
+
+        // Invalid chunk sizes are refused
+        write!(
+            client,
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+             ffffffffffffffffff\r\n",
+        )
+        .unwrap();
+        let mut request = receive(&mut conn);
+        assert!(body(&mut conn, &mut request).is_err());
+
+        // The connection can't be reused if the body wasn't read
+        conn.respond(&request, &Response::new(400, Vec::new()))
+            .unwrap();
+        assert!(!conn.keep_alive());
+    }
+
+    #[test]
+    fn test_body_limits() {
+        let (mut conn, mut client) = connection();
+        write!(client, "POST / HTTP/1.1\r\nContent-Length: 17\r\n\r\n")
+            .unwrap();
+        let mut request = receive(&mut conn);
+        assert_err!(
+            body(&mut conn, &mut request),
+            ErrorKind::RequestBodyTooLarge(16)
+        );
+
+        let (mut conn, mut client) = connection();
+        write!(client, "POST / HTTP/1.1\r\nContent-Length: 4\r\n\r\nte")
+            .unwrap();
+        let mut request = receive(&mut conn);
+        assert_err!(body(&mut conn, &mut request), ErrorKind::RequestTimeout);
+    }
+
+    #[test]
+    fn test_expect_continue() {
+        let (mut conn, mut client) = connection();
+        write!(
+            client,
+            "POST / HTTP/1.1\r\nExpect: 100-continue\r\n\
+             Content-Length: 4\r\n\r\n",
+        )
+        .unwrap();
+        let mut request = receive(&mut conn);
+
+        let sender = thread::spawn(move || {
+            let mut buffer = [0; 25];
+            client.read_exact(&mut buffer).unwrap();
+            assert_eq!(&buffer, b"HTTP/1.1 100 Continue\r\n\r\n");
+            write!(client, "test").unwrap();
+            client
+        });
+        assert_eq!(body(&mut conn, &mut request).unwrap(), b"test".to_vec());
+        sender.join().unwrap();
+    }
+
+    #[test]
+    fn test_respond() {
+        let (mut conn, mut client) = connection();
+        write!(client, "HEAD / HTTP/1.0\r\n\r\n").unwrap();
+        let request = receive(&mut conn);
+
+        let mut response = Response::new(404, b"not found".to_vec());
+        response.add_header("X-Test: a".into());
+        response.add_header("X-Injected: a\r\nX-Other: b".into());
+        conn.respond(&request, &response).unwrap();
+        conn.close();
+
+        let mut received = String::new();
+        client.read_to_string(&mut received).unwrap();
+        assert!(received.starts_with("HTTP/1.0 404 Not Found\r\n"));
+        assert!(received.contains("\r\nX-Test: a\r\n"));
+        assert!(!received.contains("X-Other"));
+        assert!(received.contains("\r\nContent-Length: 9\r\n"));
+        assert!(received.contains("\r\nConnection: close\r\n"));
+
+        // The body is not sent in response to HEAD requests
+        assert!(received.ends_with("\r\n\r\n"));
+    }
+}
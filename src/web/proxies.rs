@@ -15,31 +15,73 @@
 
 use std::net::IpAddr;
 
+use common::config::{HttpConfig, ProxyHeader};
 use common::prelude::*;
 use requests::Request;
-use utils;
+use utils::{self, Headers, IpNetwork};
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct ProxySupport {
     behind: u8,
+    trusted: Vec<IpNetwork>,
+    header: ProxyHeader,
 }
 
 impl ProxySupport {
+    /// Trust the provided number of proxies in front of Fisher.
     pub fn new(behind: u8) -> Self {
-        ProxySupport { behind }
+        ProxySupport {
+            behind,
+            trusted: Vec::new(),
+            header: ProxyHeader::XForwardedFor,
+        }
+    }
+
+    /// Trust all the proxies in the provided networks, regardless of how
+    /// many of them forwarded the request.
+    pub fn trusting(networks: Vec<IpNetwork>) -> Self {
+        ProxySupport {
+            behind: 0,
+            trusted: networks,
+            header: ProxyHeader::XForwardedFor,
+        }
+    }
+
+    /// Read the forwarded addresses from another header.
+    pub fn with_header(mut self, header: ProxyHeader) -> Self {
+        self.header = header;
+        self
+    }
+
+    pub fn from_config(config: &HttpConfig) -> Result<Self> {
+        let support = match (config.behind_proxies, &config.trusted_proxies) {
+            (0, trusted) => ProxySupport::trusting(trusted.clone()),
+            (behind, trusted) if trusted.is_empty() => {
+                ProxySupport::new(behind)
+            }
+            _ => return Err(ErrorKind::ProxyConfigConflict.into()),
+        };
+        Ok(support.with_header(config.proxy_header))
     }
 
     pub fn source_ip(&self, req: &Request) -> Result<IpAddr> {
         let req = req.web()?;
         let original = req.source;
 
+        if !self.trusted.is_empty() {
+            return Ok(self.source_ip_trusting(original, &req.headers));
+        }
+
         // Return the original IP if the proxy support is disabled
         if self.behind == 0 {
             return Ok(original);
         }
 
-        // Parse the X-Forwarded-For header
-        let mut forwarded_ips = utils::parse_forwarded_for(&req.headers)?;
+        // Parse the header set by the proxies
+        let mut forwarded_ips = self
+            .forwarded_ips(&req.headers)
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
 
         // Return an error if there was no header
         if forwarded_ips.is_empty() {
@@ -59,6 +101,54 @@ impl ProxySupport {
         }
     }
 
+    fn source_ip_trusting(
+        &self,
+        original: IpAddr,
+        headers: &Headers,
+    ) -> IpAddr {
+        // Anyone can send the headers, so they're ignored if the request
+        // doesn't come from a trusted proxy
+        if !self.is_trusted(original) {
+            return original;
+        }
+
+        // Walk the chain from the closest proxy, until an address which
+        // isn't a trusted proxy is found. Addresses which can't be parsed
+        // also end the chain, and the last trusted proxy is the client then
+        let mut source = original;
+        for ip in self.forwarded_ips(headers).into_iter().rev() {
+            source = match ip {
+                Ok(ip) => ip,
+                Err(..) => break,
+            };
+            if !self.is_trusted(source) {
+                break;
+            }
+        }
+
+        source
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted.iter().any(|network| network.contains(ip))
+    }
+
+    /// Get the addresses forwarded by the proxies, from the farthest to the
+    /// closest one.
+    fn forwarded_ips(&self, headers: &Headers) -> Vec<Result<IpAddr>> {
+        match self.header {
+            ProxyHeader::XForwardedFor => utils::parse_forwarded_for(headers),
+            ProxyHeader::Forwarded => utils::parse_forwarded(headers),
+            ProxyHeader::XRealIp => {
+                let mut result = Vec::new();
+                if let Some(ip) = headers.get("X-Real-IP") {
+                    result.push(ip.trim().parse().map_err(Error::from));
+                }
+                result
+            }
+        }
+    }
+
     pub fn fix_request(&self, req: &mut Request) -> Result<()> {
         let fixed_ip = self.source_ip(req)?;
 
@@ -76,6 +166,7 @@ mod tests {
     use std::net::IpAddr;
     use std::str::FromStr;
 
+    use common::config::{HttpConfig, ProxyHeader};
     use common::prelude::*;
     use requests::Request;
     use utils::testing::*;
@@ -93,9 +184,7 @@ mod tests {
         ($fwd_for:expr) => {{
             let mut req = req!();
             if let Request::Web(ref mut inner) = req {
-                inner
-                    .headers
-                    .insert("X-Forwarded-For".into(), $fwd_for.into());
+                inner.headers.append("X-Forwarded-For", $fwd_for);
             }
             req
         }};
@@ -145,6 +234,103 @@ mod tests {
         assert_err!(p.source_ip(&req!("invalid")), ErrorKind::AddrParse(..));
     }
 
+    #[test]
+    fn test_other_headers() {
+        fn req_with(header: &str, value: &str) -> Request {
+            let mut req = dummy_web_request();
+            req.source = IpAddr::from_str("127.1.1.1").unwrap();
+            req.headers.append(header, value);
+            Request::Web(req)
+        }
+
+        let p = ProxySupport::new(1).with_header(ProxyHeader::Forwarded);
+        assert_eq!(
+            p.source_ip(&req_with("Forwarded", "for=127.2.2.2;proto=https"))
+                .unwrap(),
+            IpAddr::from_str("127.2.2.2").unwrap()
+        );
+        // The other headers are ignored
+        assert_err!(
+            p.source_ip(&req_with("X-Forwarded-For", "127.2.2.2")),
+            ErrorKind::NotBehindProxy
+        );
+
+        let p = ProxySupport::new(1).with_header(ProxyHeader::XRealIp);
+        assert_eq!(
+            p.source_ip(&req_with("X-Real-IP", "127.2.2.2")).unwrap(),
+            IpAddr::from_str("127.2.2.2").unwrap()
+        );
+        assert_err!(
+            p.source_ip(&req_with("X-Forwarded-For", "127.2.2.2")),
+            ErrorKind::NotBehindProxy
+        );
+    }
+
+    #[test]
+    fn test_trusted_proxies() {
+        macro_rules! assert_ip {
+            ($proxy:expr, $req:expr, $expected:expr) => {{
+                assert_eq!(
+                    $proxy.source_ip(&$req).unwrap(),
+                    IpAddr::from_str($expected).unwrap()
+                );
+            }};
+        }
+
+        let networks = vec!["127.1.0.0/16".parse().unwrap()];
+        let p = ProxySupport::trusting(networks);
+
+        // Requests from trusted proxies might not be forwarded
+        assert_ip!(p, req!(), "127.1.1.1");
+
+        // The closest untrusted address is the client, regardless of how many
+        // proxies forwarded the request
+        assert_ip!(p, req!("127.2.2.2"), "127.2.2.2");
+        assert_ip!(p, req!("127.2.2.2, 127.1.2.2"), "127.2.2.2");
+        assert_ip!(p, req!("127.3.3.3, 127.2.2.2, 127.1.2.2"), "127.2.2.2");
+
+        // If all the addresses are trusted the farthest one is the client
+        assert_ip!(p, req!("127.1.3.3, 127.1.2.2"), "127.1.3.3");
+
+        // Invalid addresses end the chain, without rejecting the request
+        assert_ip!(p, req!("invalid"), "127.1.1.1");
+        assert_ip!(p, req!("127.2.2.2, invalid, 127.1.2.2"), "127.1.2.2");
+        assert_ip!(p, req!("invalid, 127.2.2.2, 127.1.2.2"), "127.2.2.2");
+
+        // The headers sent by untrusted clients are ignored
+        let mut req = req!("127.1.2.2");
+        if let Request::Web(ref mut inner) = req {
+            inner.source = IpAddr::from_str("127.2.2.2").unwrap();
+        }
+        assert_ip!(p, req, "127.2.2.2");
+    }
+
+    #[test]
+    fn test_from_config() {
+        let mut config = HttpConfig {
+            behind_proxies: 1,
+            ..HttpConfig::default()
+        };
+        assert_eq!(
+            ProxySupport::from_config(&config).unwrap(),
+            ProxySupport::new(1)
+        );
+
+        config.trusted_proxies = vec!["10.0.0.0/8".parse().unwrap()];
+        assert_err!(
+            ProxySupport::from_config(&config),
+            ErrorKind::ProxyConfigConflict
+        );
+
+        config.behind_proxies = 0;
+        config.proxy_header = ProxyHeader::Forwarded;
+        assert_eq!(
+            ProxySupport::from_config(&config).unwrap(),
+            ProxySupport::trusting(config.trusted_proxies.clone())
+                .with_header(ProxyHeader::Forwarded)
+        );
+    }
+
     #[test]
     fn test_fix_request() {
         let proxy = ProxySupport::new(1);
@@ -49,13 +49,21 @@ pub struct RateLimiter<Id: Hash + Eq + PartialEq> {
 
 impl<Id: Hash + Eq + PartialEq> RateLimiter<Id> {
     pub fn new(allowed: u64, interval: u64) -> Self {
-        RateLimiter {
+        let mut limiter = RateLimiter {
             data: HashMap::new(),
-            incr_step: Duration::from_millis(
-                (interval as f64 / allowed as f64 * 1000.0) as u64
-            ),
-            limit_after: Duration::new(interval, 0),
-        }
+            incr_step: Duration::new(0, 0),
+            limit_after: Duration::new(0, 0),
+        };
+        limiter.reconfigure(allowed, interval);
+        limiter
+    }
+
+    /// Change the limits, keeping track of the requests already counted.
+    pub fn reconfigure(&mut self, allowed: u64, interval: u64) {
+        self.incr_step = Duration::from_millis(
+            (interval as f64 / allowed as f64 * 1000.0) as u64
+        );
+        self.limit_after = Duration::new(interval, 0);
     }
 
     pub fn is_limited(&mut self, id: &Id) -> Option<Duration> {
@@ -92,6 +100,10 @@ impl<Id: Hash + Eq + PartialEq> RateLimiter<Id> {
         }
     }
 
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
     pub fn increment(&mut self, id: Id) {
         let item = self.data.entry(id).or_insert(LimitStatus::Unlimited);
 
@@ -101,6 +113,79 @@ impl<Id: Hash + Eq + PartialEq> RateLimiter<Id> {
             *item = LimitStatus::ClearsAt(Instant::now(), self.incr_step);
         }
     }
+
+    /// Count a request only if it doesn't exceed the limit, returning how
+    /// long the user should wait otherwise.
+    pub fn try_increment(&mut self, id: Id) -> Option<Duration> {
+        let now = Instant::now();
+        let remaining = match self.data.get(&id) {
+            Some(&LimitStatus::ClearsAt(start, duration)) => duration
+                .checked_sub(now.duration_since(start))
+                .unwrap_or_default(),
+            _ => Duration::new(0, 0),
+        };
+
+        let clears_in = remaining + self.incr_step;
+        if clears_in > self.limit_after {
+            return Some(clears_in - self.limit_after);
+        }
+
+        self.data.insert(id, LimitStatus::ClearsAt(now, clears_in));
+        None
+    }
+
+    /// Stop counting a request counted before, if it wasn't processed in the
+    /// end.
+    pub fn decrement(&mut self, id: &Id) {
+        if let Some(status) = self.data.get_mut(id) {
+            if let LimitStatus::ClearsAt(start, duration) = *status {
+                let duration =
+                    duration.checked_sub(self.incr_step).unwrap_or_default();
+                *status = LimitStatus::ClearsAt(start, duration);
+            }
+        }
+    }
+}
+
+
+/// The rate limits of the valid requests sent to each script, configured by
+/// the scripts themselves.
+#[derive(Debug, Default)]
+pub struct ScriptRateLimits {
+    limiters: HashMap<String, RateLimiter<()>>,
+}
+
+impl ScriptRateLimits {
+    pub fn new() -> Self {
+        ScriptRateLimits {
+            limiters: HashMap::new(),
+        }
+    }
+
+    /// Count a request to the script, returning how long the client should
+    /// wait if the limit was exceeded.
+    pub fn acquire(
+        &mut self,
+        script: &str,
+        allowed: u64,
+        interval: u64,
+    ) -> Option<Duration> {
+        let limiter = self
+            .limiters
+            .entry(script.to_string())
+            .or_insert_with(|| RateLimiter::new(allowed, interval));
+
+        // The limit might have changed after the script was reloaded
+        limiter.reconfigure(allowed, interval);
+        limiter.try_increment(())
+    }
+
+    /// Give back a request counted by `acquire`, if its job wasn't queued.
+    pub fn release(&mut self, script: &str) {
+        if let Some(limiter) = self.limiters.get_mut(script) {
+            limiter.decrement(&());
+        }
+    }
 }
 
 
@@ -109,7 +194,7 @@ mod tests {
     use std::thread;
     use std::time::Duration;
 
-    use super::RateLimiter;
+    use super::{RateLimiter, ScriptRateLimits};
 
 
     #[test]
@@ -129,6 +214,71 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_rate_limiter_reconfigure() {
+        let mut limiter = RateLimiter::<u8>::new(10, 1);
+        for _ in 0..10 {
+            limiter.increment(1);
+        }
+
+        // The requests already counted are kept after reconfiguring
+        limiter.reconfigure(5, 1);
+        assert!(limiter.is_limited(&1).is_none());
+        limiter.increment(1);
+        assert!(limiter.is_limited(&1).is_some());
+
+        // A new user starts from zero
+        limiter.increment(2);
+        assert!(limiter.is_limited(&2).is_none());
+    }
+
+
+    #[test]
+    fn test_try_increment() {
+        let mut limiter = RateLimiter::<u8>::new(10, 1);
+        for _ in 0..10 {
+            assert!(limiter.try_increment(1).is_none());
+        }
+
+        // The rejected requests aren't counted
+        let retry = limiter.try_increment(1).unwrap();
+        assert!(retry > Duration::new(0, 0));
+        assert!(retry <= Duration::from_millis(100));
+        assert!(limiter.try_increment(1).unwrap() <= retry);
+
+        // Other users are not affected
+        assert!(limiter.try_increment(2).is_none());
+    }
+
+
+    #[test]
+    fn test_script_rate_limits() {
+        let mut limits = ScriptRateLimits::new();
+
+        for _ in 0..5 {
+            assert!(limits.acquire("a.sh", 5, 60).is_none());
+        }
+        let retry = limits.acquire("a.sh", 5, 60).unwrap();
+        assert!(retry > Duration::from_secs(11));
+        assert!(retry <= Duration::from_secs(12));
+
+        // Each script has its own limit
+        assert!(limits.acquire("b.sh", 5, 60).is_none());
+
+        // The new limit is applied if the script changes it
+        assert!(limits.acquire("b.sh", 1, 60).is_some());
+
+        // Released requests aren't counted anymore
+        limits.release("a.sh");
+        assert!(limits.acquire("a.sh", 5, 60).is_none());
+        assert!(limits.acquire("a.sh", 5, 60).is_some());
+
+        // Releasing unknown scripts does nothing
+        limits.release("c.sh");
+        assert!(limits.acquire("c.sh", 1, 60).is_none());
+    }
+
+
     #[test]
     #[ignore]
     fn test_rate_limiter_slow() {
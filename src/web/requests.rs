@@ -13,37 +13,36 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::collections::HashMap;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr};
 
-use tiny_http;
 use url::form_urlencoded;
 
+use utils::{self, Headers, Params};
+use web::protocol;
+
 #[derive(Debug, Clone)]
 pub struct WebRequest {
+    pub id: String,
     pub source: IpAddr,
-    pub headers: HashMap<String, String>,
-    pub params: HashMap<String, String>,
-    pub body: String,
+    pub headers: Headers,
+    pub params: Params,
+    pub body: Vec<u8>,
 }
 
-impl<'a> From<&'a mut tiny_http::Request> for WebRequest {
-    fn from(origin: &'a mut tiny_http::Request) -> WebRequest {
-        // Get the source IP
-        let source = origin.remote_addr().ip();
+impl<'a> From<&'a protocol::Request> for WebRequest {
+    fn from(origin: &'a protocol::Request) -> WebRequest {
+        // Get the source IP, connections to Unix sockets are always local
+        let source = origin
+            .remote_addr()
+            .map(|addr| addr.ip())
+            .unwrap_or_else(|| IpAddr::V4(Ipv4Addr::LOCALHOST));
 
         // Get the headers
-        let mut headers = HashMap::new();
-        for header in origin.headers() {
-            headers.insert(
-                header.field.as_str().as_str().to_string(),
-                header.value.as_str().to_string(),
-            );
-        }
-
-        // Get the body
-        let mut body = String::new();
-        origin.as_reader().read_to_string(&mut body).unwrap();
+        let headers = origin
+            .headers()
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
 
         // Get the querystring
         let url = origin.url();
@@ -51,22 +50,19 @@ impl<'a> From<&'a mut tiny_http::Request> for WebRequest {
             let query = url.rsplitn(2, '?').nth(0).unwrap();
             params_from_query(query)
         } else {
-            HashMap::new()
+            Params::new()
         };
 
         WebRequest {
+            id: utils::random_id(),
             source,
             headers,
             params,
-            body,
+            body: Vec::new(),
         }
     }
 }
 
-pub fn params_from_query(query: &str) -> HashMap<String, String> {
-    let mut hashmap = HashMap::new();
-    for (a, b) in form_urlencoded::parse(query.as_bytes()).into_owned() {
-        hashmap.insert(a, b);
-    }
-    hashmap
+pub fn params_from_query(query: &str) -> Params {
+    form_urlencoded::parse(query.as_bytes()).into_owned().collect()
 }
@@ -18,38 +18,81 @@ use std::time::Duration;
 use serde_json;
 
 use common::prelude::*;
-use common::structs::HealthDetails;
+use common::structs::{HealthDetails, JobDetails};
 
 
 #[derive(Debug)]
 pub enum Response {
     NotFound,
     Forbidden,
+    Rejected(Option<String>),
+    Unauthorized(&'static str),
     BadRequest(Error),
+    RequestTimeout,
+    PayloadTooLarge,
     TooManyRequests(Duration),
+    QueueFull(Duration),
+    JobSuperseded,
+    JobDropped,
+    QueueOverflow,
     Unavailable,
+    InternalError,
+    GatewayTimeout,
     Ok,
+    HookAccepted { provider: Option<String>, job: Option<JobDetails> },
+    JobStatus(JobDetails),
     HealthStatus(HealthDetails),
+    Readiness(HealthDetails),
+    Metrics(String),
+    ScriptOutput { status: u16, body: String, json: bool },
 }
 
 impl Response {
     pub fn status(&self) -> u16 {
         match *self {
             Response::NotFound => 404,
-            Response::Forbidden => 403,
+            Response::Forbidden | Response::Rejected(..) => 403,
+            Response::Unauthorized(..) => 401,
             Response::BadRequest(..) => 400,
-            Response::TooManyRequests(..) => 429,
-            Response::Unavailable => 503,
+            Response::RequestTimeout => 408,
+            Response::JobSuperseded => 409,
+            Response::PayloadTooLarge => 413,
+            Response::TooManyRequests(..) | Response::QueueFull(..) => 429,
+            Response::Unavailable
+            | Response::QueueOverflow
+            | Response::JobDropped => 503,
+            Response::InternalError => 500,
+            Response::GatewayTimeout => 504,
+            Response::ScriptOutput { status, .. } => status,
+            Response::Readiness(ref details) if !details.ready() => 503,
             _ => 200,
         }
     }
 
     pub fn json(&self) -> String {
         serde_json::to_string(&match *self {
+            Response::HookAccepted { ref provider, ref job } => json!({
+                "status": "ok",
+                "ping": job.is_none(),
+                "provider": provider,
+                "job": job,
+            }),
+            Response::JobStatus(ref details) => json!({
+                "status": "ok",
+                "result": details,
+            }),
             Response::HealthStatus(ref details) => json!({
                 "status": "ok",
                 "result": details,
             }),
+            Response::Readiness(ref details) => json!({
+                "status": if details.ready() { "ok" } else { "not_ready" },
+                "result": details,
+            }),
+            Response::Rejected(Some(ref reason)) => json!({
+                "status": "forbidden",
+                "reason": reason,
+            }),
             Response::BadRequest(ref error) => json!({
                 "status": "bad_request",
                 "error_msg": format!("{}", error),
@@ -58,26 +101,76 @@ impl Response {
                 "status": "too_many_requests",
                 "retry_after": until.as_secs(),
             }),
+            Response::QueueFull(ref until) => json!({
+                "status": "queue_full",
+                "retry_after": until.as_secs(),
+            }),
             _ => json!({
                 "status": match *self {
                     Response::NotFound => "not_found",
-                    Response::Forbidden => "forbidden",
+                    Response::Forbidden | Response::Rejected(..) => {
+                        "forbidden"
+                    }
+                    Response::Unauthorized(..) => "unauthorized",
                     Response::BadRequest(..) => "bad_request",
+                    Response::RequestTimeout => "request_timeout",
+                    Response::PayloadTooLarge => "payload_too_large",
                     Response::TooManyRequests(..) => "too_many_requests",
+                    Response::QueueFull(..) => "queue_full",
+                    Response::QueueOverflow => "queue_overflow",
+                    Response::JobSuperseded => "superseded",
+                    Response::JobDropped => "dropped",
                     Response::Unavailable => "unavailable",
-                    Response::Ok | Response::HealthStatus(..) => "ok",
+                    Response::InternalError => "internal_error",
+                    Response::GatewayTimeout => "gateway_timeout",
+                    Response::Ok
+                    | Response::HookAccepted { .. }
+                    | Response::JobStatus(..)
+                    | Response::HealthStatus(..)
+                    | Response::Readiness(..)
+                    | Response::Metrics(..)
+                    | Response::ScriptOutput { .. } => "ok",
                 },
             }),
         }).unwrap()
     }
 
+    pub fn body(&self) -> String {
+        match *self {
+            Response::Metrics(ref rendered) => rendered.clone(),
+            Response::ScriptOutput { ref body, .. } => body.clone(),
+            _ => self.json(),
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match *self {
+            Response::Metrics(..) => "text/plain; version=0.0.4",
+            Response::ScriptOutput { json: false, .. } => {
+                "text/plain; charset=utf-8"
+            }
+            _ => "application/json",
+        }
+    }
+
     pub fn headers(&self) -> Option<Vec<String>> {
         match *self {
-            Response::TooManyRequests(ref duration) => {
+            Response::TooManyRequests(ref duration)
+            | Response::QueueFull(ref duration) => {
                 Some(vec![
                     format!("Retry-After: {}", duration.as_secs()),
                 ])
             },
+            Response::Unauthorized(scheme) => {
+                Some(vec![
+                    format!("WWW-Authenticate: {} realm=\"Fisher\"", scheme),
+                ])
+            },
+            Response::HookAccepted { job: Some(ref job), .. } => {
+                Some(vec![
+                    format!("Location: /jobs/{}", job.id),
+                ])
+            },
             _ => None,
         }
     }
@@ -91,7 +184,7 @@ mod tests {
     use serde_json;
 
     use common::prelude::*;
-    use common::structs::HealthDetails;
+    use common::structs::{HealthDetails, JobDetails, JobStatus};
 
     use super::Response;
 
@@ -138,6 +231,38 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_rejected() {
+        let response = Response::Rejected(None);
+        assert_eq!(response.status(), 403);
+        assert_eq!(j(response.json()), json!({"status": "forbidden"}));
+
+        let response = Response::Rejected(Some("wrong secret".into()));
+        assert_eq!(response.status(), 403);
+        assert_eq!(j(response.json()), json!({
+            "status": "forbidden",
+            "reason": "wrong secret",
+        }));
+    }
+
+
+    #[test]
+    fn test_unauthorized() {
+        let response = Response::Unauthorized("Basic");
+        assert_eq!(response.status(), 401);
+
+        // Ensure the client is told how to authenticate
+        assert_eq!(response.headers(), Some(vec![
+            "WWW-Authenticate: Basic realm=\"Fisher\"".into(),
+        ]));
+
+        // Ensure the response is correct
+        assert_eq!(j(response.json()), json!({
+            "status": "unauthorized",
+        }));
+    }
+
+
     #[test]
     fn test_bad_request() {
         // This is just a dummy error
@@ -165,6 +290,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_request_timeout() {
+        let response = Response::RequestTimeout;
+        assert_eq!(response.status(), 408);
+        assert!(response.headers().is_none());
+
+        assert_eq!(j(response.json()), json!({
+            "status": "request_timeout",
+        }));
+    }
+
+    #[test]
+    fn test_payload_too_large() {
+        let response = Response::PayloadTooLarge;
+        assert_eq!(response.status(), 413);
+        assert!(response.headers().is_none());
+
+        assert_eq!(j(response.json()), json!({
+            "status": "payload_too_large",
+        }));
+    }
+
     #[test]
     fn test_too_many_requests() {
         let response = Response::TooManyRequests(Duration::from_secs(10));
@@ -182,6 +329,53 @@ mod tests {
         }));
     }
 
+    #[test]
+    fn test_queue_full() {
+        let response = Response::QueueFull(Duration::from_secs(60));
+        assert_eq!(response.status(), 429);
+        assert_eq!(response.headers(), Some(vec![
+            "Retry-After: 60".into(),
+        ]));
+
+        assert_eq!(j(response.json()), json!({
+            "status": "queue_full",
+            "retry_after": 60,
+        }));
+    }
+
+    #[test]
+    fn test_queue_overflow() {
+        let response = Response::QueueOverflow;
+        assert_eq!(response.status(), 503);
+        assert!(response.headers().is_none());
+
+        assert_eq!(j(response.json()), json!({
+            "status": "queue_overflow",
+        }));
+    }
+
+
+    #[test]
+    fn test_job_superseded() {
+        let response = Response::JobSuperseded;
+        assert_eq!(response.status(), 409);
+        assert!(response.headers().is_none());
+
+        assert_eq!(j(response.json()), json!({
+            "status": "superseded",
+        }));
+    }
+
+    #[test]
+    fn test_job_dropped() {
+        let response = Response::JobDropped;
+        assert_eq!(response.status(), 503);
+        assert!(response.headers().is_none());
+
+        assert_eq!(j(response.json()), json!({
+            "status": "dropped",
+        }));
+    }
 
     #[test]
     fn test_unavailable() {
@@ -201,6 +395,18 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_gateway_timeout() {
+        let response = Response::GatewayTimeout;
+        assert_eq!(response.status(), 504);
+        assert!(response.headers().is_none());
+
+        assert_eq!(j(response.json()), json!({
+            "status": "gateway_timeout",
+        }));
+    }
+
+
     #[test]
     fn test_ok() {
         let response = Response::Ok;
@@ -219,12 +425,93 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_hook_accepted() {
+        let response = Response::HookAccepted {
+            provider: Some("GitHub".into()),
+            job: Some(JobDetails {
+                id: "0123456789abcdef".into(),
+                script: "deploy.sh".into(),
+                status: JobStatus::Queued,
+                queue_position: Some(2),
+            }),
+        };
+        assert_eq!(response.status(), 200);
+
+        // The client is told where to check the status of the job
+        assert_eq!(response.headers(), Some(vec![
+            "Location: /jobs/0123456789abcdef".into(),
+        ]));
+
+        assert_eq!(j(response.json()), json!({
+            "status": "ok",
+            "ping": false,
+            "provider": "GitHub",
+            "job": {
+                "id": "0123456789abcdef",
+                "script": "deploy.sh",
+                "status": "queued",
+                "queue_position": 2,
+            },
+        }));
+
+        // Pings don't queue any job
+        let response = Response::HookAccepted {
+            provider: None,
+            job: None,
+        };
+        assert!(response.headers().is_none());
+        assert_eq!(j(response.json()), json!({
+            "status": "ok",
+            "ping": true,
+            "provider": null,
+            "job": null,
+        }));
+    }
+
+
+    #[test]
+    fn test_metrics() {
+        let response = Response::Metrics("fisher_queued_jobs 1\n".into());
+        assert_eq!(response.status(), 200);
+        assert!(response.headers().is_none());
+
+        // The metrics must be returned as-is, as plain text
+        assert_eq!(response.content_type(), "text/plain; version=0.0.4");
+        assert_eq!(response.body(), "fisher_queued_jobs 1\n");
+    }
+
+
+    #[test]
+    fn test_script_output() {
+        // The output of the script is returned as-is
+        let response = Response::ScriptOutput {
+            status: 201,
+            body: "Hello world\n".into(),
+            json: false,
+        };
+        assert_eq!(response.status(), 201);
+        assert_eq!(response.content_type(), "text/plain; charset=utf-8");
+        assert_eq!(response.body(), "Hello world\n");
+
+        let response = Response::ScriptOutput {
+            status: 200,
+            body: r#"{"text": "hi"}"#.into(),
+            json: true,
+        };
+        assert_eq!(response.content_type(), "application/json");
+        assert_eq!(response.body(), r#"{"text": "hi"}"#);
+    }
+
+
     #[test]
     fn test_health_status() {
         let response = Response::HealthStatus(HealthDetails {
             queued_jobs: 1,
             busy_threads: 2,
             max_threads: 3,
+            loaded_scripts: 4,
+            ..HealthDetails::default()
         });
 
         // The result must be an object
@@ -256,6 +543,46 @@ mod tests {
         assert_eq!(
             result.get("max_threads").unwrap().as_u64().unwrap(),
             3 as u64
-        )
+        );
+        assert_eq!(
+            result.get("loaded_scripts").unwrap().as_u64().unwrap(),
+            4 as u64
+        );
+        assert!(result.get("oldest_queued_job").unwrap().is_null());
+        assert!(!result.get("locked").unwrap().as_bool().unwrap());
+    }
+
+
+    #[test]
+    fn test_readiness() {
+        let details = HealthDetails {
+            loaded_scripts: 1,
+            ..HealthDetails::default()
+        };
+
+        // An unlocked instance with scripts is ready
+        let response = Response::Readiness(details.clone());
+        assert_eq!(response.status(), 200);
+        let json = j(response.json());
+        assert_eq!(json.pointer("/status").unwrap().as_str().unwrap(), "ok");
+
+        // A locked instance is not ready
+        let response = Response::Readiness(HealthDetails {
+            locked: true,
+            ..details.clone()
+        });
+        assert_eq!(response.status(), 503);
+        let json = j(response.json());
+        assert_eq!(
+            json.pointer("/status").unwrap().as_str().unwrap(),
+            "not_ready"
+        );
+
+        // An instance without scripts is not ready
+        let response = Response::Readiness(HealthDetails {
+            loaded_scripts: 0,
+            ..details
+        });
+        assert_eq!(response.status(), 503);
     }
 }